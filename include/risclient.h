@@ -0,0 +1,151 @@
+#ifndef RISCLIENT_H
+#define RISCLIENT_H
+
+/* Generated by cbindgen from src/capi.rs, do not edit */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// A RIS Live client, owning the runtime its subscriptions run on
+typedef struct RisClientHandle RisClientHandle;
+
+// A message received from RIS Live
+typedef struct RisMessageHandle RisMessageHandle;
+
+// A live subscription, created by `risclient_subscribe`
+typedef struct RisSubscriptionHandle RisSubscriptionHandle;
+
+// Subscription filters. NULL strings, a NULL path and negative booleans leave a filter unset.
+typedef struct RisFilter {
+  // A collector, or several separated by commas
+  const char *host;
+  const char *data_type;
+  const char *require;
+  const char *peer;
+  const char *prefix;
+  const uint32_t *path;
+  uintptr_t path_len;
+  int more_specific;
+  int less_specific;
+  int include_raw;
+} RisFilter;
+
+// Called with each message received by `risclient_run`. The message is only valid during the call.
+// Return non-zero to stop receiving.
+typedef int (*RisCallback)(const struct RisMessageHandle *message, void *user_data);
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Returns the last error raised on this thread, or NULL. The string is valid until the next failing call.
+const char *risclient_last_error(void);
+
+// Creates a client. A NULL host connects to ris-live.ripe.net, and a NULL client_id uses "c-risclient".
+// Returns NULL on failure.
+//
+// # Safety
+//
+// `host` and `client_id` must each be NULL or a valid NUL terminated string.
+struct RisClientHandle *risclient_client_new(const char *host,
+                                             const char *client_id);
+
+// Frees a client. Subscriptions created from it keep running until they are freed.
+//
+// # Safety
+//
+// `client` must be NULL or a pointer returned by `risclient_client_new` that has not been freed.
+void risclient_client_free(struct RisClientHandle *client);
+
+// Returns a filter with every field unset
+struct RisFilter risclient_filter_default(void);
+
+// Connects and subscribes, blocking until RIS Live acknowledges or rejects the subscription, for up to
+// 10 seconds. A NULL filter receives the full stream. Returns NULL on failure, including a rejected
+// or unacknowledged subscription, with the reason in `risclient_last_error`.
+//
+// # Safety
+//
+// `client` must be a live client. `filter` must be NULL or point to a filter whose strings are NULL or
+// NUL terminated and whose path, if not NULL, holds `path_len` ASNs.
+struct RisSubscriptionHandle *risclient_subscribe(struct RisClientHandle *client,
+                                                  const struct RisFilter *filter);
+
+// Frees a subscription. The connection to RIS Live is closed when the next message arrives.
+//
+// # Safety
+//
+// `subscription` must be NULL or a pointer returned by `risclient_subscribe` that has not been freed.
+void risclient_subscription_free(struct RisSubscriptionHandle *subscription);
+
+// Waits up to `timeout_ms` milliseconds for the next message, or forever if it is negative.
+// Returns NULL on timeout, on a message that failed to decode, or once the stream has closed,
+// which `risclient_last_error` distinguishes.
+// The message must be freed with `risclient_message_free`.
+//
+// # Safety
+//
+// `subscription` must be a live subscription.
+struct RisMessageHandle *risclient_poll(struct RisSubscriptionHandle *subscription,
+                                        int64_t timeout_ms);
+
+// Calls `callback` with each message until it returns non-zero or the stream closes.
+// Messages that fail to decode are skipped.
+// Returns 0 if the callback stopped the loop, or -1 if the stream closed.
+//
+// # Safety
+//
+// `subscription` must be a live subscription, and `user_data` must be valid for whatever `callback` does with it.
+int risclient_run(struct RisSubscriptionHandle *subscription,
+                  RisCallback callback,
+                  void *user_data);
+
+// Frees a message returned by `risclient_poll`
+//
+// # Safety
+//
+// `message` must be NULL or a pointer returned by `risclient_poll` that has not been freed.
+void risclient_message_free(struct RisMessageHandle *message);
+
+// Returns the message as RIS Live JSON
+//
+// # Safety
+//
+// `message` must be a live message.
+const char *risclient_message_json(const struct RisMessageHandle *message);
+
+// Returns the BGP message type, such as "UPDATE"
+//
+// # Safety
+//
+// `message` must be a live message.
+const char *risclient_message_type(const struct RisMessageHandle *message);
+
+// Returns the collector the message was received at, such as "rrc00"
+//
+// # Safety
+//
+// `message` must be a live message.
+const char *risclient_message_host(const struct RisMessageHandle *message);
+
+// Returns the address of the peer the message was received from
+//
+// # Safety
+//
+// `message` must be a live message.
+const char *risclient_message_peer(const struct RisMessageHandle *message);
+
+// Returns when the collector saw the message, its RIS Live `timestamp`, in seconds since the epoch
+//
+// # Safety
+//
+// `message` must be a live message.
+double risclient_message_timestamp(const struct RisMessageHandle *message);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* RISCLIENT_H */
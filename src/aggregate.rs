@@ -0,0 +1,115 @@
+//! Prefix aggregation
+//!
+//! Summarises sets of prefixes as the fewest supernets covering exactly the
+//! same addresses, so that "AS64500 announced 512 /24s" can be reported as the
+//! three /16s they add up to. `aggregate` works on any set of prefixes, and
+//! `PrefixAggregator` collects them per origin from a stream.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use ipnet::IpNet;
+
+use crate::{AsPathEntry, RisResponseData};
+
+/// Returns the fewest prefixes covering exactly the addresses of `prefixes`, merging
+/// adjacent prefixes and dropping those inside another
+///
+/// # Examples
+///
+/// ```
+/// use ipnet::IpNet;
+/// let prefixes: Vec<IpNet> = ["198.51.100.0/25", "198.51.100.128/25", "198.51.101.0/24", "198.51.100.64/26"]
+///     .iter().map(|prefix| prefix.parse().unwrap()).collect();
+/// assert_eq!(risclient::aggregate::aggregate(prefixes), vec!["198.51.100.0/23".parse::<IpNet>().unwrap()]);
+/// ```
+pub fn aggregate<I: IntoIterator<Item = IpNet>>(prefixes: I) -> Vec<IpNet> {
+    IpNet::aggregate(&prefixes.into_iter().collect())
+}
+
+/// The prefixes one origin announced, and what they aggregate to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct OriginSummary {
+    pub origin: u32,
+    /// How many distinct prefixes were announced
+    pub prefixes: usize,
+    /// The fewest prefixes covering the same addresses
+    pub aggregates: Vec<IpNet>,
+}
+
+impl fmt::Display for OriginSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+	let aggregates: Vec<String> = self.aggregates.iter().map(IpNet::to_string).collect();
+	write!(f, "AS{} announced {} prefixes, covered by {}: {}", self.origin, self.prefixes, aggregates.len(), aggregates.join(", "))
+    }
+}
+
+///
+/// Collects announced prefixes per origin ASN, to summarise as aggregates.
+/// Prefixes are kept until `reset`, so take summaries regularly on busy streams.
+///
+#[derive(Debug, Clone, Default)]
+pub struct PrefixAggregator {
+    origins: BTreeMap<u32, BTreeSet<IpNet>>,
+}
+
+impl PrefixAggregator {
+
+    /// Returns an empty PrefixAggregator
+    pub fn new() -> PrefixAggregator {
+	PrefixAggregator::default()
+    }
+
+    /// Adds the prefixes a message announces under its origin. Paths ending in an AS_SET have no
+    /// single origin and are skipped.
+    pub fn observe(&mut self, data: &RisResponseData) {
+	let Some(AsPathEntry::Asn(origin)) = data.path().last() else {
+	    return;
+	};
+	let prefixes = data.announcements().iter()
+	    .flat_map(|announcement| announcement.prefixes())
+	    .filter_map(|prefix| prefix.parse::<IpNet>().ok())
+	    .map(|prefix| prefix.trunc());
+	self.origins.entry(*origin).or_default().extend(prefixes);
+    }
+
+    /// Returns the aggregates of the prefixes `origin` announced
+    pub fn aggregates(&self, origin: u32) -> Vec<IpNet> {
+	self.origins.get(&origin).map(|prefixes| aggregate(prefixes.iter().copied())).unwrap_or_default()
+    }
+
+    /// Returns a summary for every origin seen, ordered by ASN
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use risclient::RisResponse;
+    /// use risclient::aggregate::PrefixAggregator;
+    /// let message: RisResponse = serde_json::from_str(r#"{"type": "ris_message", "data": {"timestamp": 1650000000.25,
+    ///     "peer": "192.0.2.1", "peer_asn": "64500", "id": "1", "host": "rrc00", "type": "UPDATE", "path": [64500, 64501],
+    ///     "announcements": [{"next_hop": "192.0.2.1", "prefixes": ["203.0.112.0/24", "203.0.113.0/24", "198.51.100.0/24"]}]}}"#).unwrap();
+    /// let mut aggregator = PrefixAggregator::new();
+    /// aggregator.observe(message.data());
+    /// let summary = &aggregator.summary()[0];
+    /// assert_eq!(summary.to_string(), "AS64501 announced 3 prefixes, covered by 2: 198.51.100.0/24, 203.0.112.0/23");
+    /// ```
+    pub fn summary(&self) -> Vec<OriginSummary> {
+	self.origins.iter().map(|(origin, prefixes)| OriginSummary {
+	    origin: *origin,
+	    prefixes: prefixes.len(),
+	    aggregates: aggregate(prefixes.iter().copied()),
+	}).collect()
+    }
+
+    /// Forgets every prefix
+    pub fn reset(&mut self) {
+	self.origins.clear();
+    }
+
+    /// Returns a summary for every origin seen and forgets them
+    pub fn take_summary(&mut self) -> Vec<OriginSummary> {
+	let summary = self.summary();
+	self.reset();
+	summary
+    }
+}
@@ -0,0 +1,451 @@
+//! Threshold alerting
+//!
+//! An `AlertEngine` holds rules, such as "more than 100 withdrawals a minute
+//! of prefixes originated by AS64500" or "nothing from rrc00 for 2 minutes",
+//! and evaluates them against every message it observes and on every tick.
+//! A rule raises an `Alert` when its condition starts to hold and another when
+//! it stops, and each alert is handed to the notifiers bound to the rule.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io::{self, Write};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::clock::{self, Clock};
+use crate::rib::PeerKey;
+use crate::{collectors, RisError, RisReceiver, RisResponseData};
+
+/// What a rule watches for
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    /// More than `limit` prefixes withdrawn within `window`, counting each peer withdrawing a prefix,
+    /// and only prefixes the peer last announced with `origin` if one is given
+    WithdrawalRate { origin: Option<u32>, limit: usize, window: Duration },
+    /// More than `limit` prefixes announced within `window`, counting only those originated by `origin` if one is given
+    AnnouncementRate { origin: Option<u32>, limit: usize, window: Duration },
+    /// More than `limit` messages within `window`, counting only those from `collector` if one is given
+    MessageRate { collector: Option<String>, limit: usize, window: Duration },
+    /// No messages for `after`, counting only those from `collector` if one is given
+    Silence { collector: Option<String>, after: Duration },
+}
+
+/// A named condition, and the notifiers its alerts go to
+#[derive(Debug, Clone)]
+pub struct Rule {
+    name: String,
+    condition: Condition,
+    notifiers: Vec<String>,
+}
+
+impl Rule {
+    /// Returns a Rule called `name`, whose alerts go to every notifier unless bound with `notify`
+    pub fn new(name: &str, condition: Condition) -> Rule {
+	Rule { name: name.to_string(), condition, notifiers: Vec::new() }
+    }
+
+    /// Sends this rule's alerts to the notifier registered as `notifier`, rather than to every notifier
+    pub fn notify(mut self, notifier: &str) -> Rule {
+	self.notifiers.push(notifier.to_string());
+	self
+    }
+
+    /// Returns the rule's name
+    pub fn name(&self) -> &str {
+	&self.name
+    }
+
+    /// Returns what the rule watches for
+    pub fn condition(&self) -> &Condition {
+	&self.condition
+    }
+}
+
+/// A rule starting or stopping to hold
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Alert {
+    /// The name of the rule
+    pub rule: String,
+    /// True when the condition started to hold, false when it stopped
+    pub firing: bool,
+    /// What was measured: the count within the window for rates, or the seconds of silence
+    pub value: f64,
+    /// A description of the alert, for people
+    pub summary: String,
+    /// The labels of the message that raised the alert, if one did
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+}
+
+/// Delivers alerts somewhere
+pub trait Notifier: Send {
+    /// Delivers an alert. Notifiers should not block for long, as the engine waits for them.
+    fn notify(&mut self, alert: &Alert);
+}
+
+impl Notifier for std::sync::mpsc::Sender<Alert> {
+    fn notify(&mut self, alert: &Alert) {
+	let _ = self.send(alert.clone());
+    }
+}
+
+impl Notifier for tokio::sync::mpsc::UnboundedSender<Alert> {
+    fn notify(&mut self, alert: &Alert) {
+	let _ = self.send(alert.clone());
+    }
+}
+
+/// Writes each alert as a line of JSON
+pub struct LogNotifier<W: Write + Send> {
+    out: W,
+}
+
+impl LogNotifier<io::Stderr> {
+    /// Returns a LogNotifier writing to standard error
+    pub fn stderr() -> LogNotifier<io::Stderr> {
+	LogNotifier::new(io::stderr())
+    }
+}
+
+impl<W: Write + Send> LogNotifier<W> {
+    /// Returns a LogNotifier writing to `out`
+    pub fn new(out: W) -> LogNotifier<W> {
+	LogNotifier { out }
+    }
+}
+
+impl<W: Write + Send> Notifier for LogNotifier<W> {
+    fn notify(&mut self, alert: &Alert) {
+	if let Ok(line) = serde_json::to_string(alert) {
+	    let _ = writeln!(self.out, "{}", line).and_then(|_| self.out.flush());
+	}
+    }
+}
+
+/// Posts each alert as JSON to a webhook, from a task on the current tokio runtime so the engine
+/// does not wait for the request. Alerts raised outside a runtime, and requests that fail, are dropped.
+pub struct WebhookNotifier {
+    url: String,
+    http: reqwest::Client,
+}
+
+impl WebhookNotifier {
+    /// Returns a WebhookNotifier posting to `url`
+    pub fn new(url: &str) -> WebhookNotifier {
+	WebhookNotifier { url: url.to_string(), http: reqwest::Client::new() }
+    }
+}
+
+impl Notifier for WebhookNotifier {
+    fn notify(&mut self, alert: &Alert) {
+	let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+	    return;
+	};
+	let Ok(body) = serde_json::to_string(alert) else {
+	    return;
+	};
+	let request = self.http.post(&self.url).header(reqwest::header::CONTENT_TYPE, "application/json").body(body);
+	runtime.spawn(async move {
+	    let _ = request.send().await;
+	});
+    }
+}
+
+/// A rule along with what it has seen so far
+struct RuleState {
+    rule: Rule,
+    // when each counted event happened, for rates
+    events: VecDeque<Instant>,
+    // when the last counted message arrived, for silences
+    last: Instant,
+    firing: bool,
+}
+
+impl RuleState {
+    fn window(&self) -> Option<Duration> {
+	match self.rule.condition {
+	    Condition::WithdrawalRate { window, .. } | Condition::AnnouncementRate { window, .. } | Condition::MessageRate { window, .. } => Some(window),
+	    Condition::Silence { .. } => None,
+	}
+    }
+
+    /// Returns how many events the message counts for
+    fn count(&self, data: &RisResponseData, origins: &HashMap<PeerKey, HashMap<String, u32>>) -> usize {
+	let collector = collectors::short_name(data.host());
+	match &self.rule.condition {
+	    Condition::WithdrawalRate { origin: None, .. } => data.withdrawals().len(),
+	    Condition::WithdrawalRate { origin: Some(origin), .. } => {
+		let Some(announced) = origins.get(&PeerKey::of(data)) else {
+		    return 0;
+		};
+		data.withdrawals().iter().filter(|prefix| announced.get(*prefix) == Some(origin)).count()
+	    },
+	    Condition::AnnouncementRate { origin, .. } => {
+		if origin.is_some() && data.asns().last() != origin.as_ref() {
+		    return 0;
+		}
+		data.announcements().iter().map(|announcement| announcement.prefixes().len()).sum()
+	    },
+	    Condition::MessageRate { collector: wanted, .. } | Condition::Silence { collector: wanted, .. } => {
+		usize::from(wanted.as_deref().is_none_or(|wanted| collectors::short_name(wanted) == collector))
+	    },
+	}
+    }
+
+    /// Returns what the rule measures now, and whether that breaks it
+    fn measure(&mut self, now: Instant) -> (f64, bool) {
+	match self.rule.condition {
+	    Condition::WithdrawalRate { limit, window, .. } | Condition::AnnouncementRate { limit, window, .. } | Condition::MessageRate { limit, window, .. } => {
+		while self.events.front().is_some_and(|event| now.duration_since(*event) > window) {
+		    self.events.pop_front();
+		}
+		(self.events.len() as f64, self.events.len() > limit)
+	    },
+	    Condition::Silence { after, .. } => {
+		let silence = now.duration_since(self.last);
+		(silence.as_secs_f64(), silence >= after)
+	    },
+	}
+    }
+
+    /// Returns an alert if the rule has started or stopped holding
+    fn evaluate(&mut self, now: Instant, labels: &BTreeMap<String, String>) -> Option<Alert> {
+	let (value, broken) = self.measure(now);
+	if broken == self.firing {
+	    return None;
+	}
+	self.firing = broken;
+	let summary = match (&self.rule.condition, broken) {
+	    (Condition::WithdrawalRate { window, .. }, true) => format!("{} withdrawals within {:?}", value, window),
+	    (Condition::AnnouncementRate { window, .. }, true) => format!("{} announcements within {:?}", value, window),
+	    (Condition::MessageRate { window, .. }, true) => format!("{} messages within {:?}", value, window),
+	    (Condition::Silence { .. }, true) => format!("no messages for {:.0}s", value),
+	    (_, false) => "back to normal".to_string(),
+	};
+	Some(Alert { rule: self.rule.name.clone(), firing: broken, value, summary, labels: labels.clone() })
+    }
+}
+
+///
+/// Evaluates rules against a stream, handing their alerts to notifiers.
+/// Rates are counted over a sliding window as messages are observed, and silences are checked on
+/// every observation and every `tick`, so call `tick` regularly, or let `run` do so.
+///
+pub struct AlertEngine {
+    rules: Vec<RuleState>,
+    notifiers: Vec<(String, Box<dyn Notifier>)>,
+    // the watched origin each peer last announced each prefix with, for withdrawal rates by origin
+    origins: HashMap<PeerKey, HashMap<String, u32>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl AlertEngine {
+
+    /// Returns an AlertEngine with no rules or notifiers
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use risclient::RisResponse;
+    /// use risclient::alert::{AlertEngine, Condition, Rule};
+    /// let (tx, alerts) = std::sync::mpsc::channel();
+    /// let mut engine = AlertEngine::new()
+    ///     .rule(Rule::new("acme-withdrawals", Condition::WithdrawalRate { origin: Some(64501), limit: 1, window: Duration::from_secs(60) }))
+    ///     .notifier("ops", tx);
+    /// let announce: RisResponse = serde_json::from_str(r#"{"type": "ris_message", "data": {"host": "rrc00", "type": "UPDATE", "path": [64500, 64501],
+    ///     "announcements": [{"next_hop": "192.0.2.1", "prefixes": ["198.51.100.0/24", "203.0.113.0/24"]}]}}"#).unwrap();
+    /// let withdraw: RisResponse = serde_json::from_str(r#"{"type": "ris_message", "data": {"host": "rrc00", "type": "UPDATE",
+    ///     "withdrawals": ["198.51.100.0/24", "203.0.113.0/24"]}}"#).unwrap();
+    /// engine.observe(announce.data());
+    /// engine.observe(withdraw.data());
+    /// let alert = alerts.try_recv().unwrap();
+    /// assert_eq!((alert.rule.as_str(), alert.firing, alert.value), ("acme-withdrawals", true, 2.0));
+    /// ```
+    pub fn new() -> AlertEngine {
+	AlertEngine {
+	    rules: Vec::new(),
+	    notifiers: Vec::new(),
+	    origins: HashMap::new(),
+	    clock: clock::system(),
+	}
+    }
+
+    /// Measures windows and silences with `clock`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use std::time::Duration;
+    /// use risclient::alert::{AlertEngine, Condition, Rule};
+    /// use risclient::clock::TokioClock;
+    /// # #[tokio::main(flavor = "current_thread", start_paused = true)]
+    /// # async fn main() {
+    /// let mut engine = AlertEngine::new()
+    ///     .rule(Rule::new("quiet", Condition::Silence { collector: None, after: Duration::from_secs(120) }))
+    ///     .with_clock(Arc::new(TokioClock::new()));
+    /// tokio::time::advance(Duration::from_secs(119)).await;
+    /// assert!(engine.tick().is_empty());
+    /// tokio::time::advance(Duration::from_secs(1)).await;
+    /// assert!(engine.tick()[0].firing);
+    /// # }
+    /// ```
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> AlertEngine {
+	for state in &mut self.rules {
+	    state.last = clock.now();
+	}
+	self.clock = clock;
+	self
+    }
+
+    /// Adds a rule
+    pub fn rule(mut self, rule: Rule) -> AlertEngine {
+	self.rules.push(RuleState { rule, events: VecDeque::new(), last: self.clock.now(), firing: false });
+	self
+    }
+
+    /// Registers a notifier as `name`, which gets the alerts of rules bound to it and of rules bound to none
+    pub fn notifier<N: Notifier + 'static>(mut self, name: &str, notifier: N) -> AlertEngine {
+	self.notifiers.push((name.to_string(), Box::new(notifier)));
+	self
+    }
+
+    /// Returns the names of the rules currently firing
+    pub fn firing(&self) -> Vec<&str> {
+	self.rules.iter().filter(|state| state.firing).map(|state| state.rule.name()).collect()
+    }
+
+    fn dispatch(&mut self, alerts: &[Alert]) {
+	for alert in alerts {
+	    let bound = self.rules.iter().find(|state| state.rule.name == alert.rule).map(|state| state.rule.notifiers.clone()).unwrap_or_default();
+	    for (name, notifier) in &mut self.notifiers {
+		if bound.is_empty() || bound.contains(name) {
+		    notifier.notify(alert);
+		}
+	    }
+	}
+    }
+
+    fn learn_origins(&mut self, data: &RisResponseData) {
+	let watched = |origin: &u32| self.rules.iter().any(|state| matches!(state.rule.condition, Condition::WithdrawalRate { origin: Some(watched), .. } if watched == *origin));
+	let origin = data.asns().last().filter(|origin| watched(origin)).copied();
+	let peer = PeerKey::of(data);
+	if data.data_type() == "RIS_PEER_STATE" && data.state() == Some("down") {
+	    self.origins.remove(&peer);
+	    return;
+	}
+	let announced = self.origins.entry(peer.clone()).or_default();
+	for prefix in data.withdrawals() {
+	    announced.remove(prefix);
+	}
+	for prefix in data.announcements().iter().flat_map(|announcement| announcement.prefixes()) {
+	    // announced again by an origin nobody watches, so its withdrawal no longer counts against the old one
+	    match origin {
+		Some(origin) => announced.insert(prefix.clone(), origin),
+		None => announced.remove(prefix),
+	    };
+	}
+	if announced.is_empty() {
+	    self.origins.remove(&peer);
+	}
+    }
+
+    /// Counts a message against every rule, notifying and returning any alerts raised
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use risclient::RisResponse;
+    /// use risclient::alert::{AlertEngine, Condition, Rule};
+    /// let mut engine = AlertEngine::new()
+    ///     .rule(Rule::new("acme-withdrawals", Condition::WithdrawalRate { origin: Some(64500), limit: 1, window: Duration::from_secs(60) }));
+    /// let message = |json: &str| -> RisResponse { serde_json::from_str(json).unwrap() };
+    /// let announce = |peer: &str, origin: u32| message(&format!(r#"{{"type": "ris_message", "data": {{"host": "rrc00", "peer": "{}",
+    ///     "type": "UPDATE", "path": [64511, {}], "announcements": [{{"next_hop": "{}", "prefixes": ["198.51.100.0/24"]}}]}}}}"#, peer, origin, peer));
+    /// for peer in ["192.0.2.1", "192.0.2.2", "192.0.2.3"] {
+    ///     engine.observe(announce(peer, 64500).data());
+    /// }
+    /// // the third peer now hears the prefix from another origin, so its withdrawal does not count
+    /// engine.observe(announce("192.0.2.3", 64502).data());
+    /// let mut alerts = Vec::new();
+    /// for peer in ["192.0.2.1", "192.0.2.2", "192.0.2.3"] {
+    ///     alerts.extend(engine.observe(message(&format!(r#"{{"type": "ris_message", "data": {{"host": "rrc00", "peer": "{}", "type": "UPDATE",
+    ///         "withdrawals": ["198.51.100.0/24"]}}}}"#, peer)).data()));
+    /// }
+    /// assert_eq!((alerts.len(), alerts[0].value), (1, 2.0));
+    /// ```
+    pub fn observe(&mut self, data: &RisResponseData) -> Vec<Alert> {
+	let now = self.clock.now();
+	let mut alerts = Vec::new();
+	for state in &mut self.rules {
+	    let count = state.count(data, &self.origins);
+	    if count > 0 {
+		state.last = now;
+		if state.window().is_some() {
+		    state.events.extend(std::iter::repeat_n(now, count));
+		}
+	    }
+	    alerts.extend(state.evaluate(now, data.labels()));
+	}
+	self.learn_origins(data);
+	self.dispatch(&alerts);
+	alerts
+    }
+
+    /// Checks every rule without a message, so silences are noticed and rates that have fallen
+    /// resolve, notifying and returning any alerts raised
+    pub fn tick(&mut self) -> Vec<Alert> {
+	let now = self.clock.now();
+	let alerts: Vec<Alert> = self.rules.iter_mut().filter_map(|state| state.evaluate(now, &BTreeMap::new())).collect();
+	self.dispatch(&alerts);
+	alerts
+    }
+
+    /// Observes every message from `rx`, ticking every `interval`, until the stream ends
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::time::Duration;
+    /// use risclient::{RisClient, Subscription};
+    /// use risclient::alert::{AlertEngine, Condition, LogNotifier, Rule, WebhookNotifier};
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let mut client = RisClient::default().unwrap();
+    /// let rx = client.subscribe(&Subscription::new().host("rrc00")).await.unwrap();
+    /// AlertEngine::new()
+    ///     .rule(Rule::new("rrc00-silent", Condition::Silence { collector: Some("rrc00".to_string()), after: Duration::from_secs(120) }).notify("ops"))
+    ///     .rule(Rule::new("churn", Condition::MessageRate { collector: None, limit: 50_000, window: Duration::from_secs(60) }))
+    ///     .notifier("ops", WebhookNotifier::new("https://hooks.example.net/ris"))
+    ///     .notifier("log", LogNotifier::stderr())
+    ///     .run(rx, Duration::from_secs(1))
+    ///     .await;
+    /// # }
+    /// ```
+    pub async fn run(mut self, rx: RisReceiver, interval: Duration) {
+	let mut next = self.clock.now();
+	loop {
+	    let tick = self.clock.sleep(next.saturating_duration_since(self.clock.now()));
+	    tokio::select! {
+		received = rx.recv() => match received {
+		    Ok(message) => {
+			self.observe(message.data());
+		    },
+		    Err(RisError::Closed) => break,
+		    Err(_) => {},
+		},
+		_ = tick => {
+		    next += interval;
+		    self.tick();
+		},
+	    }
+	}
+    }
+}
+
+impl Default for AlertEngine {
+    fn default() -> AlertEngine {
+	AlertEngine::new()
+    }
+}
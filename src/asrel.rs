@@ -0,0 +1,181 @@
+//! AS relationship enrichment using CAIDA serial-2 datasets
+//!
+//! CAIDA publishes inferred business relationships between ASes at
+//! <https://publicdata.caida.org/datasets/as-relationships/serial-2/>. Loading one
+//! of these (decompressed) files allows each adjacent pair in an observed AS path
+//! to be classified, and paths which are not valley-free to be flagged as likely
+//! route leaks.
+
+use std::collections::HashMap;
+use std::error;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+
+use crate::{AsPathEntry, RisResponseData};
+
+/// What the right hand AS of a pair is to the left hand AS
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Relationship {
+    Customer,
+    Provider,
+    Peer,
+}
+
+/// A pair of adjacent ASes in a path, where `right` announced the route to `left`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PathLink {
+    pub left: u32,
+    pub right: u32,
+    pub relationship: Option<Relationship>,
+}
+
+/// A path which is not valley-free. `leaker` learned the route from `from` (a provider or peer)
+/// and announced it on to `to` (another provider or peer).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ValleyViolation {
+    pub leaker: u32,
+    pub from: u32,
+    pub to: u32,
+}
+
+/// A loaded set of AS relationships
+#[derive(Debug, Clone, Default)]
+pub struct AsRelationships {
+    // keyed by (provider, customer) for p2c and (low, high) for p2p
+    relationships: HashMap<(u32, u32), i8>,
+}
+
+impl AsRelationships {
+
+    /// Loads a serial-2 dataset from the filesystem. The file must already be decompressed.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<AsRelationships, Box<dyn error::Error>> {
+	match File::open(path) {
+	    Ok(file) => AsRelationships::from_reader(BufReader::new(file)),
+	    Err(e) => Err(Box::new(e))
+	}
+    }
+
+    /// Loads a serial-2 dataset from any buffered reader
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use risclient::asrel::{AsRelationships, Relationship};
+    /// let data = "# comment\n64500|64501|-1|bgp\n64500|64502|0|bgp\n";
+    /// let rels = AsRelationships::from_reader(data.as_bytes()).unwrap();
+    /// assert_eq!(rels.relationship(64500, 64501), Some(Relationship::Customer));
+    /// assert_eq!(rels.relationship(64501, 64500), Some(Relationship::Provider));
+    /// assert_eq!(rels.relationship(64502, 64500), Some(Relationship::Peer));
+    /// ```
+    pub fn from_reader<R: BufRead>(reader: R) -> Result<AsRelationships, Box<dyn error::Error>> {
+	let mut relationships = HashMap::new();
+	for line in reader.lines() {
+	    let line = match line {
+		Ok(line) => line,
+		Err(e) => return Err(Box::new(e))
+	    };
+	    let line = line.trim();
+	    if line.is_empty() || line.starts_with('#') {
+		continue;
+	    }
+	    let fields: Vec<&str> = line.split('|').collect();
+	    if fields.len() < 3 {
+		return Err(Box::new(io::Error::new(io::ErrorKind::InvalidData, format!("malformed relationship line '{}'", line))));
+	    }
+	    let parsed = (fields[0].parse::<u32>(), fields[1].parse::<u32>(), fields[2].parse::<i8>());
+	    let (a, b, kind) = match parsed {
+		(Ok(a), Ok(b), Ok(kind)) if kind == -1 || kind == 0 => (a, b, kind),
+		_ => return Err(Box::new(io::Error::new(io::ErrorKind::InvalidData, format!("malformed relationship line '{}'", line)))),
+	    };
+	    if kind == 0 {
+		relationships.insert((a.min(b), a.max(b)), kind);
+	    } else {
+		relationships.insert((a, b), kind);
+	    }
+	}
+	Ok(AsRelationships { relationships })
+    }
+
+    /// Returns the number of relationships loaded
+    pub fn len(&self) -> usize {
+	self.relationships.len()
+    }
+
+    /// Returns true if no relationships were loaded
+    pub fn is_empty(&self) -> bool {
+	self.relationships.is_empty()
+    }
+
+    /// Returns what `right` is to `left`, if the relationship is known
+    pub fn relationship(&self, left: u32, right: u32) -> Option<Relationship> {
+	if self.relationships.get(&(left.min(right), left.max(right))) == Some(&0) {
+	    return Some(Relationship::Peer);
+	}
+	if self.relationships.get(&(left, right)) == Some(&-1) {
+	    return Some(Relationship::Customer);
+	}
+	if self.relationships.get(&(right, left)) == Some(&-1) {
+	    return Some(Relationship::Provider);
+	}
+	None
+    }
+
+    /// Classifies each adjacent pair of ASes in `path`, ignoring prepending
+    pub fn classify_path(&self, path: &[u32]) -> Vec<PathLink> {
+	let mut path = path.to_vec();
+	path.dedup();
+	path.windows(2).map(|pair| PathLink {
+	    left: pair[0],
+	    right: pair[1],
+	    relationship: self.relationship(pair[0], pair[1]),
+	}).collect()
+    }
+
+    /// Checks that `path` is valley-free, returning the first violation found walking from the origin.
+    /// Links with unknown relationships are skipped, so a path is only flagged on known evidence.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use risclient::asrel::AsRelationships;
+    /// // 64501 is a customer of both 64500 and 64502, and leaks routes between them
+    /// let data = "64500|64501|-1|bgp\n64502|64501|-1|bgp\n64502|64503|-1|bgp\n";
+    /// let rels = AsRelationships::from_reader(data.as_bytes()).unwrap();
+    /// assert!(rels.valley_free(&[64500, 64501, 64502, 64503]).is_some());
+    /// assert!(rels.valley_free(&[64500, 64501]).is_none());
+    /// ```
+    pub fn valley_free(&self, path: &[u32]) -> Option<ValleyViolation> {
+	let links = self.classify_path(path);
+	let mut descending = false;
+	let mut previous: Option<&PathLink> = None;
+	for link in links.iter().rev() {
+	    match link.relationship {
+		// once a route has crossed a peer or gone down to a customer it must keep going down
+		Some(Relationship::Customer) | Some(Relationship::Peer) if descending => {
+		    return Some(ValleyViolation {
+			leaker: link.right,
+			from: previous.map(|previous| previous.right).unwrap_or(link.right),
+			to: link.left,
+		    });
+		},
+		// right is a customer of left, so the route is still travelling uphill
+		Some(Relationship::Customer) => {},
+		Some(Relationship::Peer) | Some(Relationship::Provider) => descending = true,
+		None => {},
+	    }
+	    previous = Some(link);
+	}
+	None
+    }
+
+    /// Checks the AS path of a RIS message, up to any AS_SET it contains
+    pub fn check(&self, data: &RisResponseData) -> Option<ValleyViolation> {
+	let path: Vec<u32> = data.path().iter().map_while(|entry| match entry {
+	    AsPathEntry::Asn(asn) => Some(*asn),
+	    AsPathEntry::Set(_) => None,
+	}).collect();
+	self.valley_free(&path)
+    }
+}
@@ -0,0 +1,157 @@
+//! Typed BGP path attributes
+//!
+//! RIS Live's JSON only carries the attributes most consumers want. When a
+//! subscription includes raw messages, `PathAttribute::of` decodes every
+//! attribute of the original UPDATE, passing those this client does not model
+//! through as `PathAttribute::Unknown` so that nothing the message carried is
+//! out of reach.
+
+use std::error;
+use std::net::{IpAddr, Ipv4Addr};
+
+use crate::bgp::{self, RawAttribute};
+use crate::community::{ExtendedCommunity, LargeCommunity};
+use crate::{AsPathEntry, RisResponseData};
+
+/// A path attribute of a BGP UPDATE
+#[derive(Debug, Clone, PartialEq)]
+pub enum PathAttribute {
+    /// ORIGIN, one of "igp", "egp" or "incomplete"
+    Origin(String),
+    AsPath(Vec<AsPathEntry>),
+    NextHop(IpAddr),
+    Med(u32),
+    LocalPref(u32),
+    AtomicAggregate,
+    Aggregator { asn: u32, address: Ipv4Addr },
+    /// RFC 1997 communities, as (ASN, value)
+    Communities(Vec<(u16, u16)>),
+    /// RFC 4456 ORIGINATOR_ID
+    OriginatorId(Ipv4Addr),
+    /// RFC 4456 CLUSTER_LIST
+    ClusterList(Vec<Ipv4Addr>),
+    /// MP_REACH_NLRI, with a comma separating a global next hop from a link local one as RIS Live does
+    MpReachNlri { afi: u16, safi: u8, next_hop: String, prefixes: Vec<String> },
+    MpUnreachNlri { afi: u16, safi: u8, prefixes: Vec<String> },
+    ExtendedCommunities(Vec<ExtendedCommunity>),
+    /// RFC 6793 AS4_PATH
+    As4Path(Vec<AsPathEntry>),
+    LargeCommunities(Vec<LargeCommunity>),
+    /// An attribute this client does not decode, or a known one whose value is malformed
+    Unknown { flags: u8, type_code: u8, raw: Vec<u8> },
+}
+
+fn ipv4(value: &[u8]) -> Option<Ipv4Addr> {
+    let octets: [u8; 4] = value.try_into().ok()?;
+    Some(Ipv4Addr::from(octets))
+}
+
+fn u32_of(value: &[u8]) -> Option<u32> {
+    Some(u32::from_be_bytes(value.try_into().ok()?))
+}
+
+impl PathAttribute {
+    /// Returns the attributes of a message, if the subscription included raw messages.
+    /// RIS peers' sessions negotiate four octet ASNs, so AS paths are decoded as such.
+    pub fn of(data: &RisResponseData) -> Option<Result<Vec<PathAttribute>, Box<dyn error::Error + Send + Sync>>> {
+	bgp::raw_message(data).map(|raw| PathAttribute::decode_update(&raw, true))
+    }
+
+    /// Decodes every path attribute of a BGP UPDATE message, including its header, in the order they appear
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use risclient::bgp::{self, MESSAGE_UPDATE};
+    /// use risclient::attribute::PathAttribute;
+    /// // no withdrawals, then ORIGIN igp and an attribute of type 99
+    /// let body = [0, 0, 0, 9, 0x40, 1, 1, 0, 0xc0, 99, 2, 0xab, 0xcd];
+    /// let attributes = PathAttribute::decode_update(&bgp::message(MESSAGE_UPDATE, &body), true).unwrap();
+    /// assert_eq!(attributes[0], PathAttribute::Origin("igp".to_string()));
+    /// assert_eq!(attributes[1], PathAttribute::Unknown { flags: 0xc0, type_code: 99, raw: vec![0xab, 0xcd] });
+    /// ```
+    pub fn decode_update(message: &[u8], four_octet_as: bool) -> Result<Vec<PathAttribute>, Box<dyn error::Error + Send + Sync>> {
+	Ok(bgp::raw_attributes(message)?.into_iter().map(|attribute| PathAttribute::decode(attribute, four_octet_as)).collect())
+    }
+
+    /// Decodes a single attribute, falling back to `Unknown` if its value is malformed
+    pub fn decode(attribute: RawAttribute<'_>, four_octet_as: bool) -> PathAttribute {
+	let (flags, type_code, value) = attribute;
+	PathAttribute::decode_known(type_code, value, four_octet_as)
+	    .unwrap_or_else(|| PathAttribute::Unknown { flags, type_code, raw: value.to_vec() })
+    }
+
+    fn decode_known(type_code: u8, value: &[u8], four_octet_as: bool) -> Option<PathAttribute> {
+	let attribute = match type_code {
+	    bgp::ATTR_ORIGIN => PathAttribute::Origin(match value {
+		[0] => "igp",
+		[1] => "egp",
+		[2] => "incomplete",
+		_ => return None,
+	    }.to_string()),
+	    bgp::ATTR_AS_PATH => PathAttribute::AsPath(bgp::decode_as_path(value, four_octet_as).ok()?),
+	    bgp::ATTR_NEXT_HOP => PathAttribute::NextHop(IpAddr::V4(ipv4(value)?)),
+	    bgp::ATTR_MED => PathAttribute::Med(u32_of(value)?),
+	    bgp::ATTR_LOCAL_PREF => PathAttribute::LocalPref(u32_of(value)?),
+	    bgp::ATTR_ATOMIC_AGGREGATE if value.is_empty() => PathAttribute::AtomicAggregate,
+	    bgp::ATTR_AGGREGATOR => match value.len() {
+		6 => PathAttribute::Aggregator { asn: u16::from_be_bytes([value[0], value[1]]) as u32, address: ipv4(&value[2..])? },
+		8 => PathAttribute::Aggregator { asn: u32_of(&value[..4])?, address: ipv4(&value[4..])? },
+		_ => return None,
+	    },
+	    bgp::ATTR_COMMUNITIES if value.len().is_multiple_of(4) => PathAttribute::Communities(value.chunks_exact(4).map(|community| {
+		(u16::from_be_bytes([community[0], community[1]]), u16::from_be_bytes([community[2], community[3]]))
+	    }).collect()),
+	    bgp::ATTR_ORIGINATOR_ID => PathAttribute::OriginatorId(ipv4(value)?),
+	    bgp::ATTR_CLUSTER_LIST if value.len().is_multiple_of(4) => PathAttribute::ClusterList(value.chunks_exact(4).filter_map(ipv4).collect()),
+	    bgp::ATTR_MP_REACH_NLRI => {
+		let next_hop_length = *value.get(3)? as usize;
+		if value.len() < 5 + next_hop_length {
+		    return None;
+		}
+		let afi = u16::from_be_bytes([value[0], value[1]]);
+		PathAttribute::MpReachNlri {
+		    afi,
+		    safi: value[2],
+		    next_hop: bgp::next_hop_string(&value[4..4 + next_hop_length]).ok()?,
+		    prefixes: bgp::decode_prefixes(afi, &value[5 + next_hop_length..]).ok()?,
+		}
+	    },
+	    bgp::ATTR_MP_UNREACH_NLRI if value.len() >= 3 => {
+		let afi = u16::from_be_bytes([value[0], value[1]]);
+		PathAttribute::MpUnreachNlri { afi, safi: value[2], prefixes: bgp::decode_prefixes(afi, &value[3..]).ok()? }
+	    },
+	    bgp::ATTR_EXTENDED_COMMUNITIES if value.len().is_multiple_of(8) => PathAttribute::ExtendedCommunities(value.chunks_exact(8).filter_map(|community| {
+		Some(ExtendedCommunity::from_bytes(community.try_into().ok()?))
+	    }).collect()),
+	    bgp::ATTR_AS4_PATH => PathAttribute::As4Path(bgp::decode_as_path(value, true).ok()?),
+	    bgp::ATTR_LARGE_COMMUNITIES if value.len().is_multiple_of(12) => PathAttribute::LargeCommunities(value.chunks_exact(12).filter_map(|community| {
+		Some(LargeCommunity { global: u32_of(&community[..4])?, local1: u32_of(&community[4..8])?, local2: u32_of(&community[8..])? })
+	    }).collect()),
+	    _ => return None,
+	};
+	Some(attribute)
+    }
+
+    /// Returns the attribute's type code
+    pub fn type_code(&self) -> u8 {
+	match self {
+	    PathAttribute::Origin(_) => bgp::ATTR_ORIGIN,
+	    PathAttribute::AsPath(_) => bgp::ATTR_AS_PATH,
+	    PathAttribute::NextHop(_) => bgp::ATTR_NEXT_HOP,
+	    PathAttribute::Med(_) => bgp::ATTR_MED,
+	    PathAttribute::LocalPref(_) => bgp::ATTR_LOCAL_PREF,
+	    PathAttribute::AtomicAggregate => bgp::ATTR_ATOMIC_AGGREGATE,
+	    PathAttribute::Aggregator { .. } => bgp::ATTR_AGGREGATOR,
+	    PathAttribute::Communities(_) => bgp::ATTR_COMMUNITIES,
+	    PathAttribute::OriginatorId(_) => bgp::ATTR_ORIGINATOR_ID,
+	    PathAttribute::ClusterList(_) => bgp::ATTR_CLUSTER_LIST,
+	    PathAttribute::MpReachNlri { .. } => bgp::ATTR_MP_REACH_NLRI,
+	    PathAttribute::MpUnreachNlri { .. } => bgp::ATTR_MP_UNREACH_NLRI,
+	    PathAttribute::ExtendedCommunities(_) => bgp::ATTR_EXTENDED_COMMUNITIES,
+	    PathAttribute::As4Path(_) => bgp::ATTR_AS4_PATH,
+	    PathAttribute::LargeCommunities(_) => bgp::ATTR_LARGE_COMMUNITIES,
+	    PathAttribute::Unknown { type_code, .. } => *type_code,
+	}
+    }
+}
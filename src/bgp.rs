@@ -0,0 +1,585 @@
+//! BGP-4 message encoding and decoding
+//!
+//! RIS Live delivers UPDATEs already decoded into JSON. Re-encoding them as BGP
+//! messages lets them be handed to tooling which only speaks the wire format,
+//! such as BMP stations. Where the subscription asked for raw messages the
+//! original PDU can be used as-is via `raw_message`.
+//!
+//! Decoding goes the other way, producing the same structures RIS Live sends
+//! from BGP messages found in MRT archives.
+
+use std::error;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use ipnet::IpNet;
+
+use crate::{Announcement, AsPathEntry, RisResponseData};
+
+pub const MESSAGE_OPEN: u8 = 1;
+pub const MESSAGE_UPDATE: u8 = 2;
+pub const MESSAGE_NOTIFICATION: u8 = 3;
+pub const MESSAGE_KEEPALIVE: u8 = 4;
+
+pub const ATTR_ORIGIN: u8 = 1;
+pub const ATTR_AS_PATH: u8 = 2;
+pub const ATTR_NEXT_HOP: u8 = 3;
+pub const ATTR_MED: u8 = 4;
+pub const ATTR_LOCAL_PREF: u8 = 5;
+pub const ATTR_ATOMIC_AGGREGATE: u8 = 6;
+pub const ATTR_AGGREGATOR: u8 = 7;
+pub const ATTR_COMMUNITIES: u8 = 8;
+pub const ATTR_ORIGINATOR_ID: u8 = 9;
+pub const ATTR_CLUSTER_LIST: u8 = 10;
+pub const ATTR_MP_REACH_NLRI: u8 = 14;
+pub const ATTR_MP_UNREACH_NLRI: u8 = 15;
+pub const ATTR_EXTENDED_COMMUNITIES: u8 = 16;
+pub const ATTR_AS4_PATH: u8 = 17;
+pub const ATTR_LARGE_COMMUNITIES: u8 = 32;
+
+const FLAG_OPTIONAL: u8 = 0x80;
+const FLAG_TRANSITIVE: u8 = 0x40;
+const FLAG_EXTENDED_LENGTH: u8 = 0x10;
+
+const AS_SET: u8 = 1;
+const AS_SEQUENCE: u8 = 2;
+
+const AFI_IPV4: u16 = 1;
+const AFI_IPV6: u16 = 2;
+const SAFI_UNICAST: u8 = 1;
+
+const HEADER_LENGTH: usize = 19;
+/// The largest message allowed by RFC 4271
+pub const MAX_MESSAGE_LENGTH: usize = 4096;
+
+/// A path attribute's flags, type code and undecoded value
+pub type RawAttribute<'a> = (u8, u8, &'a [u8]);
+
+// an UPDATE's withdrawn routes, path attributes and NLRI
+type UpdateParts<'a> = (&'a [u8], &'a [u8], &'a [u8]);
+
+fn invalid(message: String) -> Box<dyn error::Error + Send + Sync> {
+    Box::new(io::Error::new(io::ErrorKind::InvalidData, message))
+}
+
+/// Decodes a hex string, such as the `raw` field of a RIS message
+pub fn hex_decode(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+	return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok()).collect()
+}
+
+/// Returns the raw BGP message of a RIS message, if the subscription included raw messages
+pub fn raw_message(data: &RisResponseData) -> Option<Vec<u8>> {
+    let raw = hex_decode(data.raw()?)?;
+    if raw.len() < HEADER_LENGTH {
+	return None;
+    }
+    Some(raw)
+}
+
+/// Wraps a message body in a BGP header
+pub fn message(message_type: u8, body: &[u8]) -> Vec<u8> {
+    let mut message = vec![0xff; 16];
+    message.extend_from_slice(&((HEADER_LENGTH + body.len()) as u16).to_be_bytes());
+    message.push(message_type);
+    message.extend_from_slice(body);
+    message
+}
+
+fn attribute(flags: u8, type_code: u8, value: &[u8]) -> Vec<u8> {
+    let mut attribute = Vec::with_capacity(value.len() + 4);
+    if value.len() > 255 {
+	attribute.push(flags | FLAG_EXTENDED_LENGTH);
+	attribute.push(type_code);
+	attribute.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    } else {
+	attribute.push(flags);
+	attribute.push(type_code);
+	attribute.push(value.len() as u8);
+    }
+    attribute.extend_from_slice(value);
+    attribute
+}
+
+/// Encodes a prefix in NLRI form: a length in bits followed by the minimum number of address octets
+pub fn encode_prefix(prefix: &IpNet, out: &mut Vec<u8>) {
+    let octets = (prefix.prefix_len() as usize).div_ceil(8);
+    out.push(prefix.prefix_len());
+    match prefix.addr() {
+	IpAddr::V4(addr) => out.extend_from_slice(&addr.octets()[..octets]),
+	IpAddr::V6(addr) => out.extend_from_slice(&addr.octets()[..octets]),
+    }
+}
+
+/// Splits prefixes into NLRI blocks no longer than `budget` octets each
+fn nlri_chunks(prefixes: &[IpNet], budget: usize) -> Vec<Vec<u8>> {
+    let mut chunks = Vec::new();
+    let mut chunk = Vec::new();
+    for prefix in prefixes {
+	let mut encoded = Vec::with_capacity(17);
+	encode_prefix(prefix, &mut encoded);
+	if !chunk.is_empty() && chunk.len() + encoded.len() > budget {
+	    chunks.push(std::mem::take(&mut chunk));
+	}
+	chunk.extend_from_slice(&encoded);
+    }
+    if !chunk.is_empty() {
+	chunks.push(chunk);
+    }
+    chunks
+}
+
+fn update(withdrawn: &[u8], attributes: &[u8], nlri: &[u8]) -> Vec<u8> {
+    let mut body = Vec::with_capacity(4 + withdrawn.len() + attributes.len() + nlri.len());
+    body.extend_from_slice(&(withdrawn.len() as u16).to_be_bytes());
+    body.extend_from_slice(withdrawn);
+    body.extend_from_slice(&(attributes.len() as u16).to_be_bytes());
+    body.extend_from_slice(attributes);
+    body.extend_from_slice(nlri);
+    message(MESSAGE_UPDATE, &body)
+}
+
+/// Encodes an AS path with four octet ASNs, splitting long sequences into multiple segments
+pub fn encode_as_path(path: &[AsPathEntry]) -> Vec<u8> {
+    let mut value = Vec::new();
+    let mut sequence: Vec<u32> = Vec::new();
+    let flush = |segment_type: u8, asns: &[u32], value: &mut Vec<u8>| {
+	for segment in asns.chunks(255) {
+	    value.push(segment_type);
+	    value.push(segment.len() as u8);
+	    for asn in segment {
+		value.extend_from_slice(&asn.to_be_bytes());
+	    }
+	}
+    };
+    for entry in path {
+	match entry {
+	    AsPathEntry::Asn(asn) => sequence.push(*asn),
+	    AsPathEntry::Set(set) => {
+		flush(AS_SEQUENCE, &sequence, &mut value);
+		sequence.clear();
+		flush(AS_SET, set, &mut value);
+	    }
+	}
+    }
+    flush(AS_SEQUENCE, &sequence, &mut value);
+    value
+}
+
+/// Encodes the attributes shared by every UPDATE generated from a RIS message
+fn common_attributes(data: &RisResponseData) -> Result<Vec<u8>, Box<dyn error::Error + Send + Sync>> {
+    let mut attributes = Vec::new();
+    let origin = match data.origin() {
+	Some("igp") | Some("IGP") | None => 0,
+	Some("egp") | Some("EGP") => 1,
+	Some(_) => 2,
+    };
+    attributes.extend(attribute(FLAG_TRANSITIVE, ATTR_ORIGIN, &[origin]));
+    attributes.extend(attribute(FLAG_TRANSITIVE, ATTR_AS_PATH, &encode_as_path(data.path())));
+    if let Some(med) = data.med() {
+	attributes.extend(attribute(FLAG_OPTIONAL, ATTR_MED, &med.to_be_bytes()));
+    }
+    if let Some(aggregator) = data.aggregator() {
+	let parsed = aggregator.split_once(':').and_then(|(asn, addr)| Some((asn.parse::<u32>().ok()?, addr.parse::<Ipv4Addr>().ok()?)));
+	match parsed {
+	    Some((asn, addr)) => {
+		let mut value = asn.to_be_bytes().to_vec();
+		value.extend_from_slice(&addr.octets());
+		attributes.extend(attribute(FLAG_OPTIONAL | FLAG_TRANSITIVE, ATTR_AGGREGATOR, &value));
+	    },
+	    None => return Err(invalid(format!("invalid aggregator '{}'", aggregator))),
+	}
+    }
+    let mut communities = Vec::new();
+    for (asn, value) in data.community() {
+	// anything wider than 16 bits cannot be a standard community, so cannot be re-encoded as one
+	if let (Ok(asn), Ok(value)) = (u16::try_from(*asn), u16::try_from(*value)) {
+	    communities.extend_from_slice(&asn.to_be_bytes());
+	    communities.extend_from_slice(&value.to_be_bytes());
+	}
+    }
+    if !communities.is_empty() {
+	attributes.extend(attribute(FLAG_OPTIONAL | FLAG_TRANSITIVE, ATTR_COMMUNITIES, &communities));
+    }
+    Ok(attributes)
+}
+
+fn parse_prefixes(prefixes: &[String]) -> Result<(Vec<IpNet>, Vec<IpNet>), Box<dyn error::Error + Send + Sync>> {
+    let mut v4 = Vec::new();
+    let mut v6 = Vec::new();
+    for prefix in prefixes {
+	match prefix.parse::<IpNet>() {
+	    Ok(net @ IpNet::V4(_)) => v4.push(net),
+	    Ok(net @ IpNet::V6(_)) => v6.push(net),
+	    Err(_) => return Err(invalid(format!("invalid prefix '{}'", prefix))),
+	}
+    }
+    Ok((v4, v6))
+}
+
+/// Re-encodes the UPDATE described by a RIS message as one or more BGP UPDATE messages.
+/// IPv6 reachability is carried in MP_REACH_NLRI and MP_UNREACH_NLRI attributes, and
+/// anything which would not fit in a single message is split across several.
+///
+/// # Examples
+///
+/// ```
+/// use risclient::RisResponse;
+/// use risclient::bgp::encode_update;
+/// let message: RisResponse = serde_json::from_str(r#"{"type": "ris_message", "data": {"type": "UPDATE", "path": [64500, 64501],
+///     "origin": "igp", "announcements": [{"next_hop": "192.0.2.1", "prefixes": ["198.51.100.0/24"]}]}}"#).unwrap();
+/// let updates = encode_update(message.data()).unwrap();
+/// assert_eq!(updates.len(), 1);
+/// assert_eq!(updates[0][18], 2);
+/// ```
+pub fn encode_update(data: &RisResponseData) -> Result<Vec<Vec<u8>>, Box<dyn error::Error + Send + Sync>> {
+    let mut updates = Vec::new();
+    let (withdrawn_v4, withdrawn_v6) = parse_prefixes(data.withdrawals())?;
+    let budget = MAX_MESSAGE_LENGTH - HEADER_LENGTH - 4;
+    for chunk in nlri_chunks(&withdrawn_v4, budget) {
+	updates.push(update(&chunk, &[], &[]));
+    }
+    // attribute header (4) plus AFI and SAFI (3)
+    for chunk in nlri_chunks(&withdrawn_v6, budget - 7) {
+	let mut value = AFI_IPV6.to_be_bytes().to_vec();
+	value.push(SAFI_UNICAST);
+	value.extend_from_slice(&chunk);
+	updates.push(update(&[], &attribute(FLAG_OPTIONAL, ATTR_MP_UNREACH_NLRI, &value), &[]));
+    }
+    if data.announcements().is_empty() {
+	return Ok(updates);
+    }
+    let common = common_attributes(data)?;
+    for announcement in data.announcements() {
+	let (v4, v6) = parse_prefixes(announcement.prefixes())?;
+	let mut next_hops = Vec::new();
+	for next_hop in announcement.next_hop().split(',') {
+	    match next_hop.trim().parse::<IpAddr>() {
+		Ok(next_hop) => next_hops.push(next_hop),
+		Err(_) => return Err(invalid(format!("invalid next hop '{}'", announcement.next_hop()))),
+	    }
+	}
+	if !v4.is_empty() {
+	    let next_hop = match next_hops.first() {
+		Some(IpAddr::V4(next_hop)) => next_hop.octets(),
+		_ => return Err(invalid(format!("IPv4 prefixes with next hop '{}'", announcement.next_hop()))),
+	    };
+	    let mut attributes = common.clone();
+	    attributes.extend(attribute(FLAG_TRANSITIVE, ATTR_NEXT_HOP, &next_hop));
+	    for chunk in nlri_chunks(&v4, budget.saturating_sub(attributes.len())) {
+		updates.push(update(&[], &attributes, &chunk));
+	    }
+	}
+	if !v6.is_empty() {
+	    let mut encoded_next_hop = Vec::new();
+	    for next_hop in &next_hops {
+		match next_hop {
+		    IpAddr::V6(next_hop) => encoded_next_hop.extend_from_slice(&next_hop.octets()),
+		    IpAddr::V4(_) => return Err(invalid(format!("IPv6 prefixes with next hop '{}'", announcement.next_hop()))),
+		}
+	    }
+	    // attribute header (4), AFI and SAFI (3), next hop length, reserved octet
+	    let overhead = common.len() + 4 + 3 + 1 + encoded_next_hop.len() + 1;
+	    for chunk in nlri_chunks(&v6, budget.saturating_sub(overhead)) {
+		let mut value = AFI_IPV6.to_be_bytes().to_vec();
+		value.push(SAFI_UNICAST);
+		value.push(encoded_next_hop.len() as u8);
+		value.extend_from_slice(&encoded_next_hop);
+		value.push(0);
+		value.extend_from_slice(&chunk);
+		let mut attributes = common.clone();
+		attributes.extend(attribute(FLAG_OPTIONAL, ATTR_MP_REACH_NLRI, &value));
+		updates.push(update(&[], &attributes, &[]));
+	    }
+	}
+    }
+    Ok(updates)
+}
+
+/// Encodes an OPEN message advertising IPv4 and IPv6 unicast and four octet ASN support
+pub fn encode_open(asn: u32, bgp_id: Ipv4Addr, hold_time: u16) -> Vec<u8> {
+    let mut capabilities = Vec::new();
+    // multiprotocol IPv4 unicast and IPv6 unicast
+    capabilities.extend_from_slice(&[1, 4, 0, 1, 0, 1]);
+    capabilities.extend_from_slice(&[1, 4, 0, 2, 0, 1]);
+    // four octet ASN
+    capabilities.extend_from_slice(&[65, 4]);
+    capabilities.extend_from_slice(&asn.to_be_bytes());
+    let mut body = vec![4];
+    let two_octet_asn = u16::try_from(asn).unwrap_or(23456);
+    body.extend_from_slice(&two_octet_asn.to_be_bytes());
+    body.extend_from_slice(&hold_time.to_be_bytes());
+    body.extend_from_slice(&bgp_id.octets());
+    body.push(capabilities.len() as u8 + 2);
+    body.push(2);
+    body.push(capabilities.len() as u8);
+    body.extend_from_slice(&capabilities);
+    message(MESSAGE_OPEN, &body)
+}
+
+/// Decodes a single prefix in NLRI form, returning it and the number of octets consumed
+pub fn decode_prefix(afi: u16, bytes: &[u8]) -> Result<(IpNet, usize), Box<dyn error::Error + Send + Sync>> {
+    let length = match bytes.first() {
+	Some(length) => *length,
+	None => return Err(invalid("truncated NLRI".to_string())),
+    };
+    let octets = (length as usize).div_ceil(8);
+    if bytes.len() < 1 + octets {
+	return Err(invalid("truncated NLRI".to_string()));
+    }
+    let prefix = match afi {
+	AFI_IPV4 if length <= 32 => {
+	    let mut address = [0u8; 4];
+	    address[..octets].copy_from_slice(&bytes[1..1 + octets]);
+	    IpNet::new(IpAddr::V4(Ipv4Addr::from(address)), length)?
+	},
+	AFI_IPV6 if length <= 128 => {
+	    let mut address = [0u8; 16];
+	    address[..octets].copy_from_slice(&bytes[1..1 + octets]);
+	    IpNet::new(IpAddr::V6(Ipv6Addr::from(address)), length)?
+	},
+	_ => return Err(invalid(format!("invalid prefix length {} for AFI {}", length, afi))),
+    };
+    Ok((prefix, 1 + octets))
+}
+
+pub(crate) fn decode_prefixes(afi: u16, mut bytes: &[u8]) -> Result<Vec<String>, Box<dyn error::Error + Send + Sync>> {
+    let mut prefixes = Vec::new();
+    while !bytes.is_empty() {
+	let (prefix, used) = decode_prefix(afi, bytes)?;
+	prefixes.push(prefix.to_string());
+	bytes = &bytes[used..];
+    }
+    Ok(prefixes)
+}
+
+pub(crate) fn decode_as_path(value: &[u8], four_octet_as: bool) -> Result<Vec<AsPathEntry>, Box<dyn error::Error + Send + Sync>> {
+    let width = if four_octet_as { 4 } else { 2 };
+    let mut path = Vec::new();
+    let mut offset = 0;
+    while offset < value.len() {
+	if value.len() < offset + 2 {
+	    return Err(invalid("truncated AS path segment".to_string()));
+	}
+	let segment_type = value[offset];
+	let count = value[offset + 1] as usize;
+	let end = offset + 2 + count * width;
+	if value.len() < end {
+	    return Err(invalid("truncated AS path segment".to_string()));
+	}
+	let asns: Vec<u32> = value[offset + 2..end].chunks(width).map(|asn| match asn {
+	    [a, b] => u16::from_be_bytes([*a, *b]) as u32,
+	    [a, b, c, d] => u32::from_be_bytes([*a, *b, *c, *d]),
+	    _ => 0,
+	}).collect();
+	match segment_type {
+	    AS_SET => path.push(AsPathEntry::Set(asns)),
+	    AS_SEQUENCE => path.extend(asns.into_iter().map(AsPathEntry::Asn)),
+	    // confederation segments are not visible outside the confederation, so are left out like RIS does
+	    _ => {},
+	}
+	offset = end;
+    }
+    Ok(path)
+}
+
+pub(crate) fn next_hop_string(next_hop: &[u8]) -> Result<String, Box<dyn error::Error + Send + Sync>> {
+    match next_hop.len() {
+	4 => {
+	    let octets: [u8; 4] = next_hop.try_into()?;
+	    Ok(Ipv4Addr::from(octets).to_string())
+	},
+	16 => {
+	    let octets: [u8; 16] = next_hop.try_into()?;
+	    Ok(Ipv6Addr::from(octets).to_string())
+	},
+	// a global address followed by a link local one, which RIS Live separates with a comma
+	32 => {
+	    let global: [u8; 16] = next_hop[..16].try_into()?;
+	    let link_local: [u8; 16] = next_hop[16..].try_into()?;
+	    Ok(format!("{},{}", Ipv6Addr::from(global), Ipv6Addr::from(link_local)))
+	},
+	length => Err(invalid(format!("invalid next hop length {}", length))),
+    }
+}
+
+/// Decoded path attributes, along with any reachability carried in MP_REACH_NLRI and MP_UNREACH_NLRI
+#[derive(Default)]
+pub(crate) struct Attributes {
+    pub(crate) next_hop: Option<String>,
+    pub(crate) mp_next_hop: Option<String>,
+    pub(crate) mp_announced: Vec<String>,
+    pub(crate) mp_withdrawn: Vec<String>,
+}
+
+/// Splits the first path attribute off a block of them, returning it and the attributes following it
+fn next_attribute(bytes: &[u8]) -> Result<(RawAttribute<'_>, &[u8]), Box<dyn error::Error + Send + Sync>> {
+    if bytes.len() < 3 {
+	return Err(invalid("truncated path attribute".to_string()));
+    }
+    let flags = bytes[0];
+    let type_code = bytes[1];
+    let (length, header) = if flags & FLAG_EXTENDED_LENGTH != 0 {
+	if bytes.len() < 4 {
+	    return Err(invalid("truncated path attribute".to_string()));
+	}
+	(u16::from_be_bytes([bytes[2], bytes[3]]) as usize, 4)
+    } else {
+	(bytes[2] as usize, 3)
+    };
+    if bytes.len() < header + length {
+	return Err(invalid(format!("truncated path attribute {}", type_code)));
+    }
+    Ok(((flags, type_code, &bytes[header..header + length]), &bytes[header + length..]))
+}
+
+/// Decodes a block of path attributes into `data`.
+/// `abbreviated_mp_reach` selects the MP_REACH_NLRI form used in TABLE_DUMP_V2 RIB entries,
+/// which carries only the next hop.
+pub(crate) fn decode_attributes(mut bytes: &[u8], four_octet_as: bool, abbreviated_mp_reach: bool, data: &mut RisResponseData) -> Result<Attributes, Box<dyn error::Error + Send + Sync>> {
+    let mut attributes = Attributes::default();
+    let mut as4_path = None;
+    while !bytes.is_empty() {
+	let ((_, type_code, value), rest) = next_attribute(bytes)?;
+	let length = value.len();
+	bytes = rest;
+	match type_code {
+	    ATTR_ORIGIN => data.origin = Some(match value.first() {
+		Some(0) => "igp",
+		Some(1) => "egp",
+		_ => "incomplete",
+	    }.to_string()),
+	    ATTR_AS_PATH => data.path = decode_as_path(value, four_octet_as)?,
+	    ATTR_AS4_PATH => as4_path = Some(decode_as_path(value, true)?),
+	    ATTR_NEXT_HOP => attributes.next_hop = Some(next_hop_string(value)?),
+	    ATTR_MED if length == 4 => data.med = Some(u32::from_be_bytes([value[0], value[1], value[2], value[3]])),
+	    ATTR_AGGREGATOR => {
+		let (asn, address) = match length {
+		    6 => (u16::from_be_bytes([value[0], value[1]]) as u32, &value[2..]),
+		    8 => (u32::from_be_bytes([value[0], value[1], value[2], value[3]]), &value[4..]),
+		    _ => return Err(invalid(format!("invalid aggregator length {}", length))),
+		};
+		data.aggregator = Some(format!("{}:{}", asn, next_hop_string(address)?));
+	    },
+	    ATTR_COMMUNITIES => {
+		data.community = value.chunks_exact(4).map(|community| {
+		    (u16::from_be_bytes([community[0], community[1]]) as u32, u16::from_be_bytes([community[2], community[3]]) as u32)
+		}).collect();
+	    },
+	    ATTR_MP_REACH_NLRI if abbreviated_mp_reach => {
+		let next_hop_length = *value.first().unwrap_or(&0) as usize;
+		if value.len() < 1 + next_hop_length {
+		    return Err(invalid("truncated MP_REACH_NLRI".to_string()));
+		}
+		attributes.mp_next_hop = Some(next_hop_string(&value[1..1 + next_hop_length])?);
+	    },
+	    ATTR_MP_REACH_NLRI => {
+		if value.len() < 5 {
+		    return Err(invalid("truncated MP_REACH_NLRI".to_string()));
+		}
+		let afi = u16::from_be_bytes([value[0], value[1]]);
+		let next_hop_length = value[3] as usize;
+		if value.len() < 5 + next_hop_length {
+		    return Err(invalid("truncated MP_REACH_NLRI".to_string()));
+		}
+		attributes.mp_next_hop = Some(next_hop_string(&value[4..4 + next_hop_length])?);
+		if value[2] == SAFI_UNICAST {
+		    attributes.mp_announced = decode_prefixes(afi, &value[5 + next_hop_length..])?;
+		}
+	    },
+	    ATTR_MP_UNREACH_NLRI => {
+		if value.len() < 3 {
+		    return Err(invalid("truncated MP_UNREACH_NLRI".to_string()));
+		}
+		let afi = u16::from_be_bytes([value[0], value[1]]);
+		if value[2] == SAFI_UNICAST {
+		    attributes.mp_withdrawn = decode_prefixes(afi, &value[3..])?;
+		}
+	    },
+	    _ => {},
+	}
+    }
+    // RFC 6793: a two octet speaker carries the real path of four octet ASNs in AS4_PATH
+    if let Some(as4_path) = as4_path {
+	if !four_octet_as && as4_path.len() <= data.path.len() {
+	    let keep = data.path.len() - as4_path.len();
+	    data.path.truncate(keep);
+	    data.path.extend(as4_path);
+	}
+    }
+    Ok(attributes)
+}
+
+/// Splits a BGP UPDATE message, including its header, into its withdrawn routes, path attributes and NLRI
+fn split_update(message: &[u8]) -> Result<UpdateParts<'_>, Box<dyn error::Error + Send + Sync>> {
+    if message.len() < HEADER_LENGTH + 4 || message[18] != MESSAGE_UPDATE {
+	return Err(invalid("not a BGP UPDATE message".to_string()));
+    }
+    let body = &message[HEADER_LENGTH..];
+    let withdrawn_length = u16::from_be_bytes([body[0], body[1]]) as usize;
+    if body.len() < 4 + withdrawn_length {
+	return Err(invalid("truncated UPDATE".to_string()));
+    }
+    let withdrawn = &body[2..2 + withdrawn_length];
+    let attributes_offset = 2 + withdrawn_length;
+    let attributes_length = u16::from_be_bytes([body[attributes_offset], body[attributes_offset + 1]]) as usize;
+    if body.len() < attributes_offset + 2 + attributes_length {
+	return Err(invalid("truncated UPDATE".to_string()));
+    }
+    let attributes = &body[attributes_offset + 2..attributes_offset + 2 + attributes_length];
+    Ok((withdrawn, attributes, &body[attributes_offset + 2 + attributes_length..]))
+}
+
+/// Returns the path attributes of a BGP UPDATE message, including its header, in the order they appear
+pub fn raw_attributes(message: &[u8]) -> Result<Vec<RawAttribute<'_>>, Box<dyn error::Error + Send + Sync>> {
+    let (_, mut bytes, _) = split_update(message)?;
+    let mut attributes = Vec::new();
+    while !bytes.is_empty() {
+	let (attribute, rest) = next_attribute(bytes)?;
+	attributes.push(attribute);
+	bytes = rest;
+    }
+    Ok(attributes)
+}
+
+/// Decodes a BGP UPDATE message, including its header, into the structure RIS Live uses.
+/// Only the UPDATE fields are populated, peer and collector details are left as their defaults.
+///
+/// # Examples
+///
+/// ```
+/// use risclient::RisResponse;
+/// use risclient::bgp::{decode_update, encode_update};
+/// let message: RisResponse = serde_json::from_str(r#"{"type": "ris_message", "data": {"type": "UPDATE", "path": [64500, 64501],
+///     "origin": "igp", "announcements": [{"next_hop": "2001:db8::1", "prefixes": ["2001:db8:1::/48"]}]}}"#).unwrap();
+/// let encoded = encode_update(message.data()).unwrap();
+/// let decoded = decode_update(&encoded[0], true).unwrap();
+/// assert_eq!(decoded.path(), message.data().path());
+/// assert_eq!(decoded.announcements(), message.data().announcements());
+/// ```
+pub fn decode_update(message: &[u8], four_octet_as: bool) -> Result<RisResponseData, Box<dyn error::Error + Send + Sync>> {
+    let (withdrawn, attributes, nlri) = split_update(message)?;
+    let mut data = RisResponseData {
+	data_type: "UPDATE".to_string(),
+	..RisResponseData::default()
+    };
+    let decoded = decode_attributes(attributes, four_octet_as, false, &mut data)?;
+    data.withdrawals = decode_prefixes(AFI_IPV4, withdrawn)?;
+    data.withdrawals.extend(decoded.mp_withdrawn);
+    let announced = decode_prefixes(AFI_IPV4, nlri)?;
+    if !announced.is_empty() {
+	data.announcements.push(Announcement {
+	    next_hop: decoded.next_hop.unwrap_or_default(),
+	    prefixes: announced,
+	});
+    }
+    if !decoded.mp_announced.is_empty() {
+	data.announcements.push(Announcement {
+	    next_hop: decoded.mp_next_hop.unwrap_or_default(),
+	    prefixes: decoded.mp_announced,
+	});
+    }
+    Ok(data)
+}
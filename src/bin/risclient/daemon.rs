@@ -0,0 +1,800 @@
+//! The daemon subcommand
+//!
+//! Runs until stopped, with its subscriptions, client-side filters,
+//! enrichments, alert rules and sinks declared in a TOML file:
+//!
+//! ```toml
+//! client_id = "acme-noc"
+//!
+//! [[subscription]]
+//! collectors = ["rrc00", "rrc21"]
+//! type = "UPDATE"
+//! labels = { feed = "core" }
+//!
+//! [filter]
+//! origins = [64500, 64501]
+//!
+//! [enrich.rpki]
+//! source = "https://rpki.example.net/export.json"
+//! refresh = "10m"
+//!
+//! [enrich.peeringdb]
+//! cache_file = "/var/cache/risclient/peeringdb.json"
+//!
+//! [[alert]]
+//! name = "acme-withdrawals"
+//! condition = "withdrawal_rate"
+//! origin = 64500
+//! limit = 100
+//! window = "1m"
+//! notify = ["ops"]
+//!
+//! [[notifier]]
+//! name = "ops"
+//! type = "webhook"
+//! url = "https://hooks.example.net/ris"
+//!
+//! [[sink]]
+//! type = "record"
+//! path = "/var/lib/risclient/acme.risjsonl"
+//!
+//! [[sink]]
+//! type = "bmp"
+//! address = "127.0.0.1:5000"
+//! ```
+//!
+//! SIGHUP reloads the file. The new configuration is started before the old
+//! one is stopped, and subscriptions in both are shared through a
+//! `RisConnectionManager`, so unchanged subscriptions keep their connections.
+//! The old configuration handles what it has already received before it
+//! stops, and messages arriving during the switch may be handled by both. Alert state
+//! starts afresh, and a file that fails to load leaves the running
+//! configuration in place. SIGTERM or Ctrl-C stops the daemon.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::error;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use clap::ValueEnum;
+use futures_util::FutureExt;
+use ipnet::IpNet;
+use risclient::alert::{AlertEngine, Condition, LogNotifier, Rule, WebhookNotifier};
+use risclient::bmp::BmpExporter;
+use risclient::exabgp::ExabgpEncoder;
+use risclient::manager::RisConnectionManager;
+use risclient::peeringdb::PeeringDbEnricher;
+use risclient::record::Recorder;
+use risclient::rib::PeerKey;
+use risclient::rpki::{RoaManager, RoaSource, ValidationState};
+use risclient::{CancellationToken, RisError, RisResponse, RisResponseData, Subscription};
+use serde::de::{Deserialize as _, Deserializer};
+use serde_derive::Deserialize;
+use tokio::signal::unix::{signal, SignalKind};
+
+use crate::output::{Format, Output};
+use crate::{parse_duration, EXIT_CONNECT, EXIT_USAGE};
+
+/// How often alert silences are checked and file sinks flushed
+const TICK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long to wait before subscribing again after a subscription fails or its connection closes
+const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(5);
+
+/// How long to wait before reconnecting to a BMP station that went away
+const RECONNECT_DELAY: Duration = Duration::from_secs(10);
+
+/// How long a reconnection to a BMP station may take before it counts as failed
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a stopping configuration gets to handle the messages it already received
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long to wait before asking PeeringDB about an ASN again after a lookup, successful or not
+const LOOKUP_RETRY: Duration = Duration::from_secs(600);
+
+fn duration<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    parse_duration(&String::deserialize(deserializer)?).map_err(serde::de::Error::custom)
+}
+
+fn format<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Format, D::Error> {
+    Format::from_str(&String::deserialize(deserializer)?, true).map_err(serde::de::Error::custom)
+}
+
+fn default_host() -> String {
+    "ris-live.ripe.net".to_string()
+}
+
+fn default_client_id() -> String {
+    "risclient-daemon".to_string()
+}
+
+fn default_format() -> Format {
+    Format::Ndjson
+}
+
+fn default_refresh() -> Duration {
+    Duration::from_secs(600)
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    /// RIS Live server to connect to
+    #[serde(default = "default_host")]
+    host: String,
+    /// Client name sent to RIS Live
+    #[serde(default = "default_client_id")]
+    client_id: String,
+    #[serde(default, rename = "subscription")]
+    subscriptions: Vec<SubscriptionConfig>,
+    #[serde(default)]
+    filter: Filter,
+    #[serde(default)]
+    enrich: EnrichConfig,
+    #[serde(default, rename = "alert")]
+    alerts: Vec<AlertConfig>,
+    #[serde(default, rename = "notifier")]
+    notifiers: Vec<NotifierConfig>,
+    #[serde(default, rename = "sink")]
+    sinks: Vec<SinkConfig>,
+}
+
+impl Config {
+    /// Reads and checks a configuration file
+    fn load(path: &Path) -> Result<Config, Box<dyn error::Error>> {
+	let config: Config = toml::from_str(&std::fs::read_to_string(path)?)?;
+	if config.subscriptions.is_empty() {
+	    return Err("at least one [[subscription]] is needed".into());
+	}
+	for subscription in &config.subscriptions {
+	    subscription.subscription().validate()?;
+	}
+	for alert in &config.alerts {
+	    if let Some(unknown) = alert.notify.iter().find(|name| !config.notifiers.iter().any(|notifier| &notifier.name == *name)) {
+		return Err(format!("alert {} notifies {}, which is not a [[notifier]]", alert.name, unknown).into());
+	    }
+	}
+	Ok(config)
+    }
+}
+
+/// What to ask RIS Live for, as the options of `Subscription`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SubscriptionConfig {
+    #[serde(default)]
+    collectors: Vec<String>,
+    #[serde(rename = "type")]
+    data_type: Option<String>,
+    require: Option<String>,
+    path: Option<Vec<u32>>,
+    peer: Option<String>,
+    prefix: Option<String>,
+    more_specific: Option<bool>,
+    less_specific: Option<bool>,
+    #[serde(default)]
+    include_raw: bool,
+    #[serde(default)]
+    labels: BTreeMap<String, String>,
+}
+
+impl SubscriptionConfig {
+    fn subscription(&self) -> Subscription {
+	let mut subscription = Subscription::new();
+	if !self.collectors.is_empty() {
+	    subscription = subscription.hosts(&self.collectors.iter().map(String::as_str).collect::<Vec<_>>());
+	}
+	if let Some(data_type) = &self.data_type {
+	    subscription = subscription.data_type(data_type);
+	}
+	if let Some(require) = &self.require {
+	    subscription = subscription.require(require);
+	}
+	if let Some(path) = &self.path {
+	    subscription = subscription.path(path.clone());
+	}
+	if let Some(peer) = &self.peer {
+	    subscription = subscription.peer(peer);
+	}
+	if let Some(prefix) = &self.prefix {
+	    subscription = subscription.prefix(prefix);
+	}
+	if let Some(more_specific) = self.more_specific {
+	    subscription = subscription.more_specific(more_specific);
+	}
+	if let Some(less_specific) = self.less_specific {
+	    subscription = subscription.less_specific(less_specific);
+	}
+	for (key, value) in &self.labels {
+	    subscription = subscription.label(key, value);
+	}
+	subscription.include_raw(self.include_raw)
+    }
+}
+
+/// Which messages to keep, beyond what the subscriptions asked RIS Live for. An empty list keeps everything.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Filter {
+    /// Only keep messages from these peer ASNs
+    #[serde(default)]
+    peer_asns: Vec<u32>,
+    /// Only keep UPDATEs announcing from one of these ASNs, or withdrawing prefixes the same peer last
+    /// announced from one of them
+    #[serde(default)]
+    origins: Vec<u32>,
+    /// Only keep UPDATEs announcing or withdrawing one of these prefixes or their more specifics
+    #[serde(default)]
+    prefixes: Vec<IpNet>,
+    // the prefixes each peer last announced from one of `origins`, as withdrawals carry no path
+    #[serde(skip)]
+    announced: HashSet<(PeerKey, String)>,
+}
+
+impl Filter {
+    fn keeps(&mut self, data: &RisResponseData) -> bool {
+	if !self.peer_asns.is_empty() && !data.peer_asn().parse().is_ok_and(|asn: u32| self.peer_asns.contains(&asn)) {
+	    return false;
+	}
+	if !self.origins.is_empty() && !self.originated(data) {
+	    return false;
+	}
+	if self.prefixes.is_empty() {
+	    return true;
+	}
+	data.announcements().iter().flat_map(|announcement| announcement.prefixes()).chain(data.withdrawals())
+	    .filter_map(|prefix| prefix.parse::<IpNet>().ok())
+	    .any(|prefix| self.prefixes.iter().any(|wanted| wanted.contains(&prefix)))
+    }
+
+    /// Returns true if an UPDATE announces from one of `origins`, or withdraws a prefix its peer last announced from one
+    fn originated(&mut self, data: &RisResponseData) -> bool {
+	let peer = PeerKey::of(data);
+	let mut kept = false;
+	for prefix in data.withdrawals() {
+	    kept |= self.announced.remove(&(peer.clone(), prefix.clone()));
+	}
+	let announcing = data.asns().last().is_some_and(|origin| self.origins.contains(origin));
+	for prefix in data.announcements().iter().flat_map(|announcement| announcement.prefixes()) {
+	    kept |= announcing;
+	    // announced again from another origin, after which its withdrawal is not kept
+	    if announcing {
+		self.announced.insert((peer.clone(), prefix.clone()));
+	    } else {
+		self.announced.remove(&(peer.clone(), prefix.clone()));
+	    }
+	}
+	kept
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EnrichConfig {
+    rpki: Option<RpkiConfig>,
+    peeringdb: Option<PeeringDbConfig>,
+}
+
+/// Labels announcements "rpki" with the worst validation state of their prefixes
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RpkiConfig {
+    /// URL or path of a JSON ROA export
+    source: String,
+    #[serde(default = "default_refresh", deserialize_with = "duration")]
+    refresh: Duration,
+}
+
+/// Labels messages "peer_name" with the PeeringDB name of their peer, once it has been looked up
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PeeringDbConfig {
+    api_key: Option<String>,
+    cache_file: Option<PathBuf>,
+}
+
+// serde cannot deny unknown fields next to a flattened one, so `ConditionConfig` does
+#[derive(Debug, Clone, Deserialize)]
+struct AlertConfig {
+    name: String,
+    #[serde(default)]
+    notify: Vec<String>,
+    #[serde(flatten)]
+    condition: ConditionConfig,
+}
+
+/// An `alert::Condition`, with durations written as "30s", "5m" and so on
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "condition", rename_all = "snake_case", deny_unknown_fields)]
+enum ConditionConfig {
+    WithdrawalRate {
+	origin: Option<u32>,
+	limit: usize,
+	#[serde(deserialize_with = "duration")]
+	window: Duration,
+    },
+    AnnouncementRate {
+	origin: Option<u32>,
+	limit: usize,
+	#[serde(deserialize_with = "duration")]
+	window: Duration,
+    },
+    MessageRate {
+	collector: Option<String>,
+	limit: usize,
+	#[serde(deserialize_with = "duration")]
+	window: Duration,
+    },
+    Silence {
+	collector: Option<String>,
+	#[serde(deserialize_with = "duration")]
+	after: Duration,
+    },
+}
+
+impl AlertConfig {
+    fn rule(&self) -> Rule {
+	let condition = match self.condition.clone() {
+	    ConditionConfig::WithdrawalRate { origin, limit, window } => Condition::WithdrawalRate { origin, limit, window },
+	    ConditionConfig::AnnouncementRate { origin, limit, window } => Condition::AnnouncementRate { origin, limit, window },
+	    ConditionConfig::MessageRate { collector, limit, window } => Condition::MessageRate { collector, limit, window },
+	    ConditionConfig::Silence { collector, after } => Condition::Silence { collector, after },
+	};
+	self.notify.iter().fold(Rule::new(&self.name, condition), |rule, notifier| rule.notify(notifier))
+    }
+}
+
+// as for `AlertConfig`, `NotifierKind` denies unknown fields
+#[derive(Debug, Clone, Deserialize)]
+struct NotifierConfig {
+    name: String,
+    #[serde(flatten)]
+    kind: NotifierKind,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum NotifierKind {
+    /// Alerts as JSON lines on stderr
+    // braces, as unit variants ignore unknown fields
+    Log {},
+    Webhook { url: String },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum SinkConfig {
+    /// Messages on stdout, in any of the formats of --output
+    Stdout {
+	#[serde(default = "default_format", deserialize_with = "format")]
+	format: Format,
+    },
+    /// A capture file that `risclient replay` can play back, appended to
+    Record { path: PathBuf },
+    /// exabgp JSON lines, appended to a file or written to stdout
+    Exabgp { path: Option<PathBuf> },
+    /// A BMP station, which should be fed subscriptions that include raw messages
+    Bmp { address: String },
+}
+
+/// Opens a file for appending, so that reloading does not lose what was written before
+fn append(path: &Path) -> io::Result<BufWriter<File>> {
+    Ok(BufWriter::new(OpenOptions::new().create(true).append(true).open(path)?))
+}
+
+type Connecting = tokio::task::JoinHandle<Result<BmpExporter, String>>;
+
+enum Sink {
+    Stdout(Output),
+    Record(Recorder<BufWriter<File>>),
+    Exabgp { encoder: ExabgpEncoder, out: Box<dyn Write + Send> },
+    // messages arriving while the station is away, or being reconnected to in the background, are dropped
+    Bmp { address: String, exporter: Option<BmpExporter>, connecting: Option<Connecting>, retry: Instant },
+}
+
+/// Connects to a BMP station from a task, so that a station that is down does not hold up the pipeline
+fn reconnect(address: &str) -> Connecting {
+    let address = address.to_string();
+    tokio::spawn(async move {
+	match tokio::time::timeout(CONNECT_TIMEOUT, BmpExporter::connect(&address)).await {
+	    Ok(connected) => connected.map_err(|e| format!("failed to reconnect to {}: {}", address, e)),
+	    Err(_) => Err(format!("failed to reconnect to {} within {:?}", address, CONNECT_TIMEOUT)),
+	}
+    })
+}
+
+impl Sink {
+    async fn open(config: &SinkConfig) -> Result<Sink, Box<dyn error::Error>> {
+	let sink = match config {
+	    SinkConfig::Stdout { format } => Sink::Stdout(Output::new(*format)),
+	    SinkConfig::Record { path } => Sink::Record(Recorder::new(append(path)?)),
+	    SinkConfig::Exabgp { path } => Sink::Exabgp {
+		encoder: ExabgpEncoder::new(),
+		out: match path {
+		    Some(path) => Box::new(append(path)?),
+		    None => Box::new(io::stdout()),
+		},
+	    },
+	    SinkConfig::Bmp { address } => Sink::Bmp {
+		address: address.clone(),
+		exporter: Some(BmpExporter::connect(address).await.map_err(|e| format!("failed to connect to {}: {}", address, e))?),
+		connecting: None,
+		retry: Instant::now(),
+	    },
+	};
+	Ok(sink)
+    }
+
+    async fn write(&mut self, message: &RisResponse) -> Result<(), Box<dyn error::Error>> {
+	match self {
+	    Sink::Stdout(output) => output.write(message)?,
+	    Sink::Record(recorder) => recorder.record(message)?,
+	    Sink::Exabgp { encoder, out } => {
+		if let Some(line) = encoder.encode_line(message) {
+		    writeln!(out, "{}", line)?;
+		}
+	    },
+	    Sink::Bmp { address, exporter, connecting, retry } => {
+		if let Some(connected) = connecting.as_mut().and_then(|task| task.now_or_never()) {
+		    *connecting = None;
+		    *exporter = Some(connected.map_err(|e| e.to_string()).and_then(|connected| connected)?);
+		}
+		if exporter.is_none() && connecting.is_none() && Instant::now() >= *retry {
+		    *retry = Instant::now() + RECONNECT_DELAY;
+		    *connecting = Some(reconnect(address));
+		}
+		if let Some(connected) = exporter {
+		    if let Err(e) = connected.export(message).await {
+			*exporter = None;
+			return Err(format!("lost {}: {}", address, e).into());
+		    }
+		}
+	    },
+	}
+	Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+	match self {
+	    Sink::Stdout(_) | Sink::Bmp { .. } => Ok(()),
+	    Sink::Record(recorder) => recorder.flush(),
+	    Sink::Exabgp { out, .. } => out.flush(),
+	}
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+	match self {
+	    Sink::Stdout(output) => output.finish(),
+	    Sink::Bmp { connecting, .. } => {
+		if let Some(task) = connecting.take() {
+		    task.abort();
+		}
+		Ok(())
+	    },
+	    _ => self.flush(),
+	}
+    }
+}
+
+/// Attaches what the configured enrichments know about a message as labels
+struct Enrichment {
+    roas: Option<(RoaManager, tokio::task::JoinHandle<()>)>,
+    peeringdb: Option<Arc<PeeringDbEnricher>>,
+    // when each peer ASN was last looked up, so lookups run in the background and are not repeated
+    lookups: HashMap<u32, Instant>,
+}
+
+impl Enrichment {
+    async fn start(config: &EnrichConfig) -> Result<Enrichment, Box<dyn error::Error>> {
+	let roas = match &config.rpki {
+	    Some(rpki) => {
+		let source = if rpki.source.starts_with("http://") || rpki.source.starts_with("https://") {
+		    RoaSource::Url(rpki.source.clone())
+		} else {
+		    RoaSource::File(PathBuf::from(&rpki.source))
+		};
+		let roas = RoaManager::new(source);
+		roas.refresh().await.map_err(|e| format!("failed to load ROAs from {}: {}", rpki.source, e))?;
+		let refreshing = roas.spawn(rpki.refresh);
+		Some((roas, refreshing))
+	    },
+	    None => None,
+	};
+	let peeringdb = match &config.peeringdb {
+	    Some(peeringdb) => {
+		let mut enricher = PeeringDbEnricher::new();
+		if let Some(api_key) = &peeringdb.api_key {
+		    enricher = enricher.with_api_key(api_key.clone());
+		}
+		if let Some(cache_file) = &peeringdb.cache_file {
+		    enricher = enricher.with_cache_file(cache_file.clone());
+		    if cache_file.exists() {
+			enricher.load_cache()?;
+		    }
+		}
+		Some(Arc::new(enricher))
+	    },
+	    None => None,
+	};
+	Ok(Enrichment { roas, peeringdb, lookups: HashMap::new() })
+    }
+
+    fn enrich(&mut self, mut message: RisResponse) -> RisResponse {
+	if let Some((roas, _)) = &self.roas {
+	    let data = message.data();
+	    let states: Vec<ValidationState> = match data.asns().last() {
+		Some(origin) => data.announcements().iter()
+		    .flat_map(|announcement| announcement.prefixes())
+		    .filter_map(|prefix| prefix.parse::<IpNet>().ok())
+		    .map(|prefix| roas.validate(&prefix, *origin))
+		    .collect(),
+		None => Vec::new(),
+	    };
+	    let worst = [ValidationState::Invalid, ValidationState::NotFound, ValidationState::Valid].into_iter().find(|state| states.contains(state));
+	    if let Some(worst) = worst {
+		message = message.with_label("rpki", &worst.to_string());
+	    }
+	}
+	if let (Some(peeringdb), Ok(asn)) = (&self.peeringdb, message.data().peer_asn().parse::<u32>()) {
+	    match peeringdb.cached(asn) {
+		Some(Some(network)) => message = message.with_label("peer_name", &network.name),
+		Some(None) => {},
+		None if self.lookups.get(&asn).is_some_and(|looked_up| looked_up.elapsed() < LOOKUP_RETRY) => {},
+		None => {
+		    self.lookups.insert(asn, Instant::now());
+		    let peeringdb = peeringdb.clone();
+		    tokio::spawn(async move {
+			if let Err(e) = peeringdb.lookup(asn).await.map_err(|e| e.to_string()) {
+			    eprintln!("risclient: failed to look up AS{} in PeeringDB: {}", asn, e);
+			}
+		    });
+		},
+	    }
+	}
+	message
+    }
+
+    fn stop(&self) {
+	if let Some((_, refreshing)) = &self.roas {
+	    refreshing.abort();
+	}
+	if let Some(Err(e)) = self.peeringdb.as_ref().map(|peeringdb| peeringdb.save_cache()) {
+	    eprintln!("risclient: failed to save the PeeringDB cache: {}", e);
+	}
+    }
+}
+
+/// Keeps a subscription's messages flowing into `messages`, subscribing again whenever its connection
+/// is lost, until `stop` is cancelled
+async fn forward(manager: RisConnectionManager, host: String, subscription: Subscription, messages: flume::Sender<RisResponse>, stop: CancellationToken) {
+    loop {
+	let subscribed = tokio::select! {
+	    _ = stop.cancelled() => return,
+	    subscribed = manager.subscribe(&subscription) => subscribed.map_err(|e| e.to_string()),
+	};
+	match subscribed {
+	    Ok(rx) => loop {
+		let received = tokio::select! {
+		    _ = stop.cancelled() => return,
+		    received = rx.recv() => received,
+		};
+		match received {
+		    Ok(message) => {
+			if messages.send(message).is_err() {
+			    return;
+			}
+		    },
+		    Err(RisError::Closed) => {
+			eprintln!("risclient: stream from {} closed, subscribing again", host);
+			break;
+		    },
+		    Err(e) => eprintln!("risclient: {}", e),
+		}
+	    },
+	    Err(e) => eprintln!("risclient: failed to subscribe to {}: {}", host, e),
+	}
+	tokio::select! {
+	    _ = stop.cancelled() => return,
+	    _ = tokio::time::sleep(RESUBSCRIBE_DELAY) => {},
+	}
+    }
+}
+
+/// One configuration, running
+struct Pipeline {
+    config: Config,
+    manager: RisConnectionManager,
+    messages: flume::Receiver<RisResponse>,
+    // held so that `messages` stays open with no subscriptions forwarding
+    _tx: flume::Sender<RisResponse>,
+    stop: CancellationToken,
+    forwarders: Vec<tokio::task::JoinHandle<()>>,
+    enrichment: Enrichment,
+    alerts: AlertEngine,
+    sinks: Vec<Sink>,
+}
+
+impl Pipeline {
+    /// Opens the sinks and enrichments, then subscribes, sharing the connections of `previous` if it used the same server
+    async fn start(config: Config, previous: Option<&Pipeline>) -> Result<Pipeline, Box<dyn error::Error>> {
+	let mut sinks = Vec::new();
+	for sink in &config.sinks {
+	    sinks.push(Sink::open(sink).await?);
+	}
+	let enrichment = Enrichment::start(&config.enrich).await?;
+	let mut alerts = AlertEngine::new();
+	for alert in &config.alerts {
+	    alerts = alerts.rule(alert.rule());
+	}
+	for notifier in &config.notifiers {
+	    alerts = match &notifier.kind {
+		NotifierKind::Log {} => alerts.notifier(&notifier.name, LogNotifier::stderr()),
+		NotifierKind::Webhook { url } => alerts.notifier(&notifier.name, WebhookNotifier::new(url)),
+	    };
+	}
+	if config.notifiers.is_empty() {
+	    alerts = alerts.notifier("log", LogNotifier::stderr());
+	}
+	let manager = match previous {
+	    Some(previous) if (&previous.config.host, &previous.config.client_id) == (&config.host, &config.client_id) => previous.manager.clone(),
+	    _ => RisConnectionManager::new(config.host.clone(), config.client_id.clone()),
+	};
+	let (tx, messages) = flume::unbounded();
+	let stop = CancellationToken::new();
+	let forwarders = config.subscriptions.iter()
+	    .map(|subscription| tokio::spawn(forward(manager.clone(), config.host.clone(), subscription.subscription(), tx.clone(), stop.clone())))
+	    .collect();
+	Ok(Pipeline { config, manager, messages, _tx: tx, stop, forwarders, enrichment, alerts, sinks })
+    }
+
+    async fn handle(&mut self, message: RisResponse) {
+	if !self.config.filter.keeps(message.data()) {
+	    return;
+	}
+	let message = self.enrichment.enrich(message);
+	self.alerts.observe(message.data());
+	for sink in &mut self.sinks {
+	    if let Err(e) = sink.write(&message).await {
+		eprintln!("risclient: sink failed: {}", e);
+	    }
+	}
+    }
+
+    fn tick(&mut self) {
+	self.alerts.tick();
+	for sink in &mut self.sinks {
+	    if let Err(e) = sink.flush() {
+		eprintln!("risclient: failed to flush sink: {}", e);
+	    }
+	}
+    }
+
+    /// Stops subscribing and handles the messages already received, for up to `DRAIN_TIMEOUT`, before closing the sinks
+    async fn stop(mut self) {
+	self.stop.cancel();
+	let drained = tokio::time::timeout(DRAIN_TIMEOUT, async {
+	    // once the forwarders have returned, nothing more is queued
+	    for forwarder in std::mem::take(&mut self.forwarders) {
+		let _ = forwarder.await;
+	    }
+	    while let Ok(message) = self.messages.try_recv() {
+		self.handle(message).await;
+	    }
+	}).await;
+	if drained.is_err() {
+	    eprintln!("risclient: dropped {} messages that could not be handled within {:?}", self.messages.len(), DRAIN_TIMEOUT);
+	}
+	self.enrichment.stop();
+	for sink in &mut self.sinks {
+	    if let Err(e) = sink.finish() {
+		eprintln!("risclient: failed to flush sink: {}", e);
+	    }
+	}
+    }
+}
+
+/// Runs the configuration in `path` until SIGTERM or Ctrl-C, reloading it on SIGHUP
+pub async fn run(path: PathBuf) -> ExitCode {
+    let config = match Config::load(&path) {
+	Ok(config) => config,
+	Err(e) => {
+	    eprintln!("risclient: invalid configuration {}: {}", path.display(), e);
+	    return ExitCode::from(EXIT_USAGE);
+	},
+    };
+    let mut pipeline = match Pipeline::start(config, None).await {
+	Ok(pipeline) => pipeline,
+	Err(e) => {
+	    eprintln!("risclient: failed to start: {}", e);
+	    return ExitCode::from(EXIT_CONNECT);
+	},
+    };
+    let (mut hangup, mut terminate) = match (signal(SignalKind::hangup()), signal(SignalKind::terminate())) {
+	(Ok(hangup), Ok(terminate)) => (hangup, terminate),
+	(Err(e), _) | (_, Err(e)) => {
+	    eprintln!("risclient: failed to handle signals: {}", e);
+	    return ExitCode::from(EXIT_CONNECT);
+	},
+    };
+    let mut ticks = tokio::time::interval(TICK_INTERVAL);
+    loop {
+	tokio::select! {
+	    // the pipeline holds a sender, so this only fails if it is being torn down
+	    Ok(message) = pipeline.messages.recv_async() => pipeline.handle(message).await,
+	    _ = ticks.tick() => pipeline.tick(),
+	    _ = hangup.recv() => {
+		let reloaded = match Config::load(&path) {
+		    Ok(config) => Pipeline::start(config, Some(&pipeline)).await,
+		    Err(e) => Err(format!("invalid configuration: {}", e).into()),
+		};
+		match reloaded.map_err(|e| e.to_string()) {
+		    Ok(reloaded) => {
+			std::mem::replace(&mut pipeline, reloaded).stop().await;
+			eprintln!("risclient: reloaded {}", path.display());
+		    },
+		    Err(e) => eprintln!("risclient: failed to reload {}, keeping the running configuration: {}", path.display(), e),
+		}
+	    },
+	    _ = terminate.recv() => break,
+	    _ = tokio::signal::ctrl_c() => break,
+	}
+    }
+    pipeline.stop().await;
+    ExitCode::SUCCESS
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    /// Returns the example configuration in the module documentation
+    fn example() -> Config {
+	let example: String = include_str!("daemon.rs").lines()
+	    .skip_while(|line| *line != "//! ```toml")
+	    .skip(1)
+	    .take_while(|line| *line != "//! ```")
+	    .map(|line| format!("{}\n", line.strip_prefix("//! ").unwrap_or_default()))
+	    .collect();
+	toml::from_str(&example).unwrap()
+    }
+
+    fn message(data: serde_json::Value) -> RisResponse {
+	serde_json::from_value(json!({"type": "ris_message", "data": data})).unwrap()
+    }
+
+    #[test]
+    fn example_alerts_on_withdrawals_by_filtered_origin() {
+	let mut config = example();
+	let (tx, alerts) = std::sync::mpsc::channel();
+	let mut engine = config.alerts.iter().fold(AlertEngine::new(), |engine, alert| engine.rule(alert.rule())).notifier("ops", tx);
+	let prefixes: Vec<String> = (0..=100).map(|i| format!("10.0.{}.0/24", i)).collect();
+	let announce = message(json!({"host": "rrc00", "peer": "192.0.2.1", "type": "UPDATE", "path": [64511, 64500],
+	    "announcements": [{"next_hop": "192.0.2.1", "prefixes": prefixes}]}));
+	let withdraw = message(json!({"host": "rrc00", "peer": "192.0.2.1", "type": "UPDATE", "withdrawals": prefixes}));
+	for update in [announce, withdraw] {
+	    assert!(config.filter.keeps(update.data()));
+	    engine.observe(update.data());
+	}
+	assert!(alerts.try_recv().unwrap().firing);
+	let unrelated = message(json!({"host": "rrc00", "peer": "192.0.2.1", "type": "UPDATE", "withdrawals": ["203.0.113.0/24"]}));
+	assert!(!config.filter.keeps(unrelated.data()));
+    }
+
+    #[test]
+    fn alerts_and_notifiers_reject_unknown_keys() {
+	let alert = |extra: &str| toml::from_str::<AlertConfig>(&format!("name = \"a\"\ncondition = \"withdrawal_rate\"\nlimit = 1\nwindow = \"1m\"\n{}", extra));
+	assert!(alert("").is_ok());
+	assert!(alert("orgin = 64500").is_err());
+	let notifier = |extra: &str| toml::from_str::<NotifierConfig>(&format!("name = \"ops\"\ntype = \"webhook\"\nurl = \"https://hooks.example.net/ris\"\n{}", extra));
+	assert!(notifier("").is_ok());
+	assert!(notifier("uri = \"https://hooks.example.net/other\"").is_err());
+	assert!(toml::from_str::<NotifierConfig>("name = \"log\"\ntype = \"log\"\nurl = \"https://hooks.example.net/ris\"").is_err());
+    }
+}
@@ -0,0 +1,523 @@
+//! Command line client for RIS Live
+//!
+//! With no subcommand, streams messages matching the given filters to stdout,
+//! by default one JSON object per line. `record` saves them to a capture file
+//! instead, `replay` plays a capture back as if it were live, `stats` prints
+//! message rates, `top` shows an interactive dashboard, `watch` monitors one
+//! prefix for hijacks and outages, and `daemon` runs a whole monitoring setup
+//! from a configuration file.
+//!
+//! Exit codes:
+//!  - 0: the count or duration limit was reached, a replay finished, or the client was interrupted with Ctrl-C or SIGTERM
+//!  - 2: the arguments or the daemon's configuration were invalid
+//!  - 3: connecting or subscribing to RIS Live, opening a capture, or starting the daemon's sinks and enrichments failed
+//!  - 4: the stream was interrupted
+//!  - 5: writing output failed
+//!  - 6: watch found a violation, with --exit-on-violation
+
+#[cfg(unix)]
+mod daemon;
+mod output;
+mod stats;
+mod top;
+mod watch;
+
+use std::io;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use clap::{Parser, Subcommand};
+use ipnet::IpNet;
+use risclient::record::{self, Recorder};
+use risclient::subscription::{DATA_TYPES, REQUIRES};
+use risclient::watch::PrefixWatch;
+use risclient::{RisClient, RisError, RisReceiver, RisResponse, Subscription};
+
+use output::{Format, Output};
+
+const EXIT_USAGE: u8 = 2;
+const EXIT_CONNECT: u8 = 3;
+const EXIT_STREAM: u8 = 4;
+const EXIT_OUTPUT: u8 = 5;
+const EXIT_VIOLATION: u8 = 6;
+
+/// How often to check for Ctrl-C or SIGTERM and the duration limit while waiting for messages
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long to wait for the websocket to close cleanly before exiting
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Parses a duration such as "90", "30s", "5m", "2h" or "1d"
+fn parse_duration(duration: &str) -> Result<Duration, String> {
+    let (number, unit) = match duration.find(|c: char| !c.is_ascii_digit()) {
+	Some(index) => duration.split_at(index),
+	None => (duration, "s"),
+    };
+    let number: u64 = number.parse().map_err(|_| format!("invalid duration '{}'", duration))?;
+    let seconds = match unit {
+	"s" => number,
+	"m" => number * 60,
+	"h" => number * 3600,
+	"d" => number * 86400,
+	_ => return Err(format!("unknown unit '{}' in duration, expected s, m, h or d", unit)),
+    };
+    Ok(Duration::from_secs(seconds))
+}
+
+/// Parses a replay speed such as "10x", "0.5" or "max"
+fn parse_speed(speed: &str) -> Result<f64, String> {
+    if speed == "max" {
+	return Ok(0.0);
+    }
+    match speed.trim_end_matches('x').parse::<f64>() {
+	Ok(speed) if speed > 0.0 => Ok(speed),
+	_ => Err(format!("invalid speed '{}', expected a multiplier such as 10x, or max", speed)),
+    }
+}
+
+/// Parses a label given as key=value
+fn parse_label(label: &str) -> Result<(String, String), String> {
+    match label.split_once('=') {
+	Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+	_ => Err(format!("invalid label '{}', expected key=value", label)),
+    }
+}
+
+#[derive(Debug, Parser)]
+#[command(name = "risclient", version, about = "Stream BGP messages from RIPE RIS Live", args_conflicts_with_subcommands = true)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    filters: Filters,
+
+    /// How to write messages to stdout
+    #[arg(long, value_enum, default_value_t = Format::Ndjson)]
+    output: Format,
+
+    #[command(flatten)]
+    limits: Limits,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Save matching messages to a capture file
+    Record {
+	/// File to write the capture to, conventionally ending in .risjsonl
+	#[arg(long)]
+	out: PathBuf,
+
+	#[command(flatten)]
+	filters: Filters,
+
+	#[command(flatten)]
+	limits: Limits,
+    },
+    /// Play back a capture file as if it were live
+    Replay {
+	/// Capture file written by record
+	file: PathBuf,
+
+	/// How much faster than real time to replay, such as 10x, or max for no delay
+	#[arg(long, value_parser = parse_speed, default_value = "1x")]
+	speed: f64,
+
+	/// How to write messages to stdout
+	#[arg(long, value_enum, default_value_t = Format::Ndjson)]
+	output: Format,
+
+	#[command(flatten)]
+	limits: Limits,
+    },
+    /// Print message rates and the busiest prefixes and origins at regular intervals
+    Stats {
+	/// How often to print, such as 1s or 1m
+	#[arg(long, value_parser = parse_duration, default_value = "1s")]
+	interval: Duration,
+
+	/// How many prefixes and origins to list
+	#[arg(long, default_value_t = 5)]
+	top: usize,
+
+	/// Also summarise the prefixes each listed origin announced as the fewest covering aggregates
+	#[arg(long)]
+	aggregate: bool,
+
+	#[command(flatten)]
+	filters: Filters,
+
+	#[command(flatten)]
+	limits: Limits,
+    },
+    /// Show an interactive dashboard of rates, busy prefixes and origins, peer sessions and alerts
+    Top {
+	/// How many prefixes and origins to list
+	#[arg(long, default_value_t = 10)]
+	top: usize,
+
+	#[command(flatten)]
+	filters: Filters,
+    },
+    /// Monitor a prefix for origin changes, unexpected more specifics, next hop changes and loss of visibility
+    Watch {
+	/// Prefix to watch
+	#[arg(long)]
+	prefix: IpNet,
+
+	/// Comma separated ASNs allowed to originate the prefix. With none, only visibility is watched.
+	#[arg(long, value_delimiter = ',')]
+	origin: Vec<u32>,
+
+	/// Accept more specifics up to this length from an allowed origin
+	#[arg(long)]
+	max_length: Option<u8>,
+
+	/// Report a visibility loss when fewer than this fraction of the most peers seen still see the prefix
+	#[arg(long, default_value_t = 0.5)]
+	min_visibility: f64,
+
+	/// POST each event as JSON to this URL
+	#[arg(long)]
+	webhook: Option<String>,
+
+	/// Exit with status 6 at the first origin change, more specific or visibility loss
+	#[arg(long)]
+	exit_on_violation: bool,
+
+	/// Only watch messages from this collector, such as rrc00. Repeat for several.
+	#[arg(long)]
+	collector: Vec<String>,
+
+	/// Attach a key=value label to every event. Repeat for several.
+	#[arg(long, value_parser = parse_label)]
+	label: Vec<(String, String)>,
+
+	#[command(flatten)]
+	connection: Connection,
+
+	#[command(flatten)]
+	limits: Limits,
+    },
+    /// Run the subscriptions, filters, enrichments, alerts and sinks of a configuration file, reloading it on SIGHUP
+    #[cfg(unix)]
+    Daemon {
+	/// TOML file to read the configuration from
+	#[arg(long)]
+	config: PathBuf,
+    },
+}
+
+#[derive(Debug, clap::Args)]
+struct Connection {
+    /// RIS Live server to connect to
+    #[arg(long, default_value = "ris-live.ripe.net")]
+    host: String,
+
+    /// Client name sent to RIS Live, so RIPE can tell who is connecting
+    #[arg(long, default_value = "risclient-cli")]
+    client_id: String,
+}
+
+impl Connection {
+    /// Connects and subscribes, reporting failures on stderr. The client is returned to be `close`d once done.
+    async fn subscribe(&self, subscription: &Subscription) -> Result<(RisClient, RisReceiver), ExitCode> {
+	let mut client = match RisClient::new(self.host.clone(), self.client_id.clone()) {
+	    Ok(client) => client,
+	    Err(e) => {
+		eprintln!("risclient: failed to create client: {}", e);
+		return Err(ExitCode::from(EXIT_CONNECT));
+	    },
+	};
+	match client.subscribe(subscription).await {
+	    Ok(rx) => Ok((client, rx)),
+	    Err(e) if matches!(e.downcast_ref(), Some(RisError::InvalidFilter { .. })) => {
+		eprintln!("risclient: {}", e);
+		Err(ExitCode::from(EXIT_USAGE))
+	    },
+	    Err(e) => {
+		eprintln!("risclient: failed to subscribe to {}: {}", self.host, e);
+		Err(ExitCode::from(EXIT_CONNECT))
+	    },
+	}
+    }
+}
+
+#[derive(Debug, clap::Args)]
+struct Filters {
+    #[command(flatten)]
+    connection: Connection,
+
+    /// Only show messages from this collector, such as rrc00. Repeat for several.
+    #[arg(long)]
+    collector: Vec<String>,
+
+    /// Only show messages of this type
+    #[arg(long = "type", value_parser = DATA_TYPES)]
+    data_type: Option<String>,
+
+    /// Only show UPDATEs for this prefix or its more specifics
+    #[arg(long)]
+    prefix: Option<String>,
+
+    /// Only show UPDATEs whose AS path contains this comma separated sequence of ASNs
+    #[arg(long, value_delimiter = ',')]
+    path: Option<Vec<u32>>,
+
+    /// Only show UPDATEs containing announcements or withdrawals
+    #[arg(long, value_parser = REQUIRES)]
+    require: Option<String>,
+
+    /// Attach a key=value label to every message. Repeat for several.
+    #[arg(long, value_parser = parse_label)]
+    label: Vec<(String, String)>,
+}
+
+impl Filters {
+    fn subscription(&self) -> Subscription {
+	let mut subscription = Subscription::new();
+	if !self.collector.is_empty() {
+	    subscription = subscription.hosts(&self.collector.iter().map(String::as_str).collect::<Vec<_>>());
+	}
+	if let Some(data_type) = &self.data_type {
+	    subscription = subscription.data_type(data_type);
+	}
+	if let Some(prefix) = &self.prefix {
+	    subscription = subscription.prefix(prefix);
+	}
+	if let Some(path) = &self.path {
+	    subscription = subscription.path(path.clone());
+	}
+	if let Some(require) = &self.require {
+	    subscription = subscription.require(require);
+	}
+	for (key, value) in &self.label {
+	    subscription = subscription.label(key, value);
+	}
+	subscription
+    }
+
+    async fn subscribe(&self) -> Result<(RisClient, RisReceiver), ExitCode> {
+	self.connection.subscribe(&self.subscription()).await
+    }
+}
+
+#[derive(Debug, clap::Args)]
+struct Limits {
+    /// Stop after this many messages
+    #[arg(long)]
+    count: Option<u64>,
+
+    /// Stop after this long, such as 30s, 5m or 1h
+    #[arg(long, value_parser = parse_duration)]
+    duration: Option<Duration>,
+}
+
+/// Why consuming a stream stopped
+enum Ended {
+    /// A limit was reached, or Ctrl-C was pressed or SIGTERM received
+    Stopped,
+    /// The sender closed the stream
+    Closed,
+    /// Handling a message failed
+    Failed(io::Error),
+}
+
+/// Closes the client's websocket, sending RIS Live a close frame rather than just dropping the connection
+async fn close(mut client: RisClient) {
+    let _ = client.close_and_drain(CLOSE_TIMEOUT).await;
+}
+
+/// Waits for Ctrl-C, or for SIGTERM on unix, so that either stops the client cleanly
+async fn interrupt() -> io::Result<()> {
+    #[cfg(unix)]
+    {
+	let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+	tokio::select! {
+	    _ = terminate.recv() => Ok(()),
+	    interrupted = tokio::signal::ctrl_c() => interrupted,
+	}
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await
+}
+
+///
+/// Passes messages to `handle` until a limit is reached, Ctrl-C is pressed or SIGTERM received, the stream closes or `handle` fails.
+/// `handle` is also called with `None` whenever no message has arrived for a while, so it can do periodic work.
+/// Dropping the receiver afterwards and then calling `close` closes the connection.
+///
+fn consume<F: FnMut(Option<&RisResponse>) -> io::Result<()>>(rx: &RisReceiver, limits: &Limits, mut handle: F) -> Ended {
+    let interrupted = Arc::new(AtomicBool::new(false));
+    let signalled = interrupted.clone();
+    tokio::spawn(async move {
+	if interrupt().await.is_ok() {
+	    signalled.store(true, Ordering::SeqCst);
+	}
+    });
+    let deadline = limits.duration.map(|duration| Instant::now() + duration);
+    let mut received = 0;
+    while !interrupted.load(Ordering::SeqCst) && limits.count.is_none_or(|count| received < count) {
+	let mut timeout = POLL_INTERVAL;
+	if let Some(deadline) = deadline {
+	    match deadline.checked_duration_since(Instant::now()) {
+		Some(remaining) => timeout = timeout.min(remaining),
+		None => break,
+	    }
+	}
+	let message = match rx.recv_timeout(timeout) {
+	    Ok(message) => Some(message),
+	    Err(RisError::Timeout) => None,
+	    Err(RisError::Closed) => return Ended::Closed,
+	    Err(e) => {
+		eprintln!("risclient: {}", e);
+		continue;
+	    },
+	};
+	if let Err(e) = handle(message.as_ref()) {
+	    return Ended::Failed(e);
+	}
+	if message.is_some() {
+	    received += 1;
+	}
+    }
+    Ended::Stopped
+}
+
+/// Writes messages to stdout. `closed_ok` says whether the stream closing is a normal end, as it is for a replay.
+fn print(rx: RisReceiver, format: Format, limits: &Limits, closed_ok: bool) -> ExitCode {
+    let mut output = Output::new(format);
+    let ended = consume(&rx, limits, |message| match message {
+	Some(message) => output.write(message),
+	None => Ok(()),
+    });
+    drop(rx);
+    let code = match ended {
+	Ended::Stopped => ExitCode::SUCCESS,
+	Ended::Closed if closed_ok => ExitCode::SUCCESS,
+	Ended::Closed => {
+	    eprintln!("risclient: stream closed");
+	    ExitCode::from(EXIT_STREAM)
+	},
+	// a closed pipe, such as from head, is a normal way to stop
+	Ended::Failed(e) if e.kind() == io::ErrorKind::BrokenPipe => return ExitCode::SUCCESS,
+	Ended::Failed(e) => {
+	    eprintln!("risclient: failed to write output: {}", e);
+	    return ExitCode::from(EXIT_OUTPUT);
+	},
+    };
+    if let Err(e) = output.finish() {
+	eprintln!("risclient: failed to write output: {}", e);
+	return ExitCode::from(EXIT_OUTPUT);
+    }
+    code
+}
+
+async fn record(out: PathBuf, filters: Filters, limits: Limits) -> ExitCode {
+    let mut recorder = match Recorder::create(&out) {
+	Ok(recorder) => recorder,
+	Err(e) => {
+	    eprintln!("risclient: failed to create {}: {}", out.display(), e);
+	    return ExitCode::from(EXIT_OUTPUT);
+	},
+    };
+    let (client, rx) = match filters.subscribe().await {
+	Ok(subscribed) => subscribed,
+	Err(code) => return code,
+    };
+    let ended = consume(&rx, &limits, |message| match message {
+	Some(message) => recorder.record(message),
+	None => Ok(()),
+    });
+    drop(rx);
+    close(client).await;
+    let flushed = recorder.flush();
+    eprintln!("risclient: recorded {} messages to {}", recorder.recorded(), out.display());
+    match (ended, flushed) {
+	(Ended::Failed(e), _) | (_, Err(e)) => {
+	    eprintln!("risclient: failed to write {}: {}", out.display(), e);
+	    ExitCode::from(EXIT_OUTPUT)
+	},
+	(Ended::Closed, _) => {
+	    eprintln!("risclient: stream from {} closed", filters.connection.host);
+	    ExitCode::from(EXIT_STREAM)
+	},
+	(Ended::Stopped, _) => ExitCode::SUCCESS,
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args = match Args::try_parse() {
+	Ok(args) => args,
+	Err(e) if e.use_stderr() => {
+	    let _ = e.print();
+	    return ExitCode::from(EXIT_USAGE);
+	},
+	// --help and --version
+	Err(e) => {
+	    let _ = e.print();
+	    return ExitCode::SUCCESS;
+	},
+    };
+    match args.command {
+	None => match args.filters.subscribe().await {
+	    Ok((client, rx)) => {
+		let code = print(rx, args.output, &args.limits, false);
+		close(client).await;
+		code
+	    },
+	    Err(code) => code,
+	},
+	Some(Command::Record { out, filters, limits }) => record(out, filters, limits).await,
+	Some(Command::Stats { interval, top, aggregate, filters, limits }) => match filters.subscribe().await {
+	    Ok((client, rx)) => {
+		let code = stats::run(rx, interval, top, aggregate, &limits);
+		close(client).await;
+		code
+	    },
+	    Err(code) => code,
+	},
+	Some(Command::Top { top, filters }) => match filters.subscribe().await {
+	    Ok((client, rx)) => {
+		let code = top::run(rx, top);
+		close(client).await;
+		code
+	    },
+	    Err(code) => code,
+	},
+	Some(Command::Watch { prefix, origin, max_length, min_visibility, webhook, exit_on_violation, collector, label, connection, limits }) => {
+	    let mut subscription = Subscription::new().prefix(&prefix.to_string()).more_specific(true);
+	    if !collector.is_empty() {
+		subscription = subscription.hosts(&collector.iter().map(String::as_str).collect::<Vec<_>>());
+	    }
+	    for (key, value) in &label {
+		subscription = subscription.label(key, value);
+	    }
+	    let mut prefix_watch = PrefixWatch::new(prefix, &origin).with_min_visibility(min_visibility);
+	    if let Some(max_length) = max_length {
+		prefix_watch = prefix_watch.with_max_length(max_length);
+	    }
+	    match connection.subscribe(&subscription).await {
+		Ok((client, rx)) => {
+		    let code = watch::run(rx, prefix_watch, webhook, exit_on_violation, &limits);
+		    close(client).await;
+		    code
+		},
+		Err(code) => code,
+	    }
+	},
+	#[cfg(unix)]
+	Some(Command::Daemon { config }) => daemon::run(config).await,
+	Some(Command::Replay { file, speed, output, limits }) => match record::replay(&file, speed) {
+	    Ok(rx) => print(rx, output, &limits, true),
+	    Err(e) => {
+		eprintln!("risclient: failed to open {}: {}", file.display(), e);
+		ExitCode::from(EXIT_CONNECT)
+	    },
+	},
+    }
+}
@@ -0,0 +1,150 @@
+//! Output formats for streamed messages
+
+use std::io::{self, IsTerminal, Write};
+
+use clap::ValueEnum;
+use risclient::{AsPathEntry, RisResponse, RisResponseData};
+
+const RESET: &str = "\x1b[0m";
+const DIM: &str = "\x1b[2m";
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const YELLOW: &str = "\x1b[33m";
+const CYAN: &str = "\x1b[36m";
+
+const CSV_HEADER: &str = "timestamp,collector,peer,peer_asn,type,action,prefix,next_hop,path,origin,community";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// A single JSON array, completed when the stream ends
+    Json,
+    /// One JSON object per line
+    Ndjson,
+    /// One row per announced or withdrawn prefix
+    Csv,
+    /// One line summaries for reading in a terminal
+    Pretty,
+}
+
+fn path(path: &[AsPathEntry]) -> String {
+    path.iter().map(|entry| match entry {
+	AsPathEntry::Asn(asn) => asn.to_string(),
+	AsPathEntry::Set(set) => format!("{{{}}}", set.iter().map(|asn| asn.to_string()).collect::<Vec<_>>().join(",")),
+    }).collect::<Vec<_>>().join(" ")
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+	format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+	field.to_string()
+    }
+}
+
+/// Formats seconds since the epoch as a UTC time of day
+fn time_of_day(timestamp: f64) -> String {
+    let millis = (timestamp * 1000.0) as u64 % 86_400_000;
+    format!("{:02}:{:02}:{:02}.{:03}", millis / 3_600_000, millis / 60_000 % 60, millis / 1000 % 60, millis % 1000)
+}
+
+/// Writes messages to stdout in the chosen format
+pub struct Output {
+    format: Format,
+    color: bool,
+    out: io::BufWriter<io::Stdout>,
+    written: u64,
+}
+
+impl Output {
+    pub fn new(format: Format) -> Output {
+	let stdout = io::stdout();
+	Output {
+	    format,
+	    color: format == Format::Pretty && stdout.is_terminal() && std::env::var_os("NO_COLOR").is_none(),
+	    out: io::BufWriter::new(stdout),
+	    written: 0,
+	}
+    }
+
+    fn paint(&self, color: &str, text: &str) -> String {
+	if self.color {
+	    format!("{}{}{}", color, text, RESET)
+	} else {
+	    text.to_string()
+	}
+    }
+
+    fn csv(&mut self, data: &RisResponseData) -> io::Result<()> {
+	if self.written == 0 {
+	    writeln!(self.out, "{}", CSV_HEADER)?;
+	}
+	let community = data.community().iter().map(|(asn, value)| format!("{}:{}", asn, value)).collect::<Vec<_>>().join(" ");
+	let common = [data.timestamp().to_string(), data.host().to_string(), data.peer().to_string(), data.peer_asn().to_string(), data.data_type().to_string()];
+	let mut rows = Vec::new();
+	for announcement in data.announcements() {
+	    for prefix in announcement.prefixes() {
+		rows.push(["+", prefix.as_str(), announcement.next_hop()]);
+	    }
+	}
+	for prefix in data.withdrawals() {
+	    rows.push(["-", prefix.as_str(), ""]);
+	}
+	if rows.is_empty() {
+	    rows.push(["", "", ""]);
+	}
+	for row in rows {
+	    let fields: Vec<String> = common.iter().map(String::as_str)
+		.chain(row)
+		.chain([path(data.path()).as_str(), data.origin().unwrap_or(""), community.as_str()])
+		.map(csv_field)
+		.collect();
+	    writeln!(self.out, "{}", fields.join(","))?;
+	}
+	Ok(())
+    }
+
+    fn pretty(&mut self, data: &RisResponseData) -> io::Result<()> {
+	let prefix = format!("{} {} {}",
+	    self.paint(DIM, &time_of_day(data.timestamp())),
+	    self.paint(CYAN, &format!("{:<6}", data.host())),
+	    format_args!("AS{:<10}", data.peer_asn()));
+	if data.data_type() != "UPDATE" {
+	    let detail = data.state().map(|state| format!(" {}", state)).unwrap_or_default();
+	    return writeln!(self.out, "{} {}{}", prefix, self.paint(YELLOW, data.data_type()), detail);
+	}
+	let path = path(data.path());
+	for announcement in data.announcements() {
+	    for announced in announcement.prefixes() {
+		writeln!(self.out, "{} {} {:<20} {}", prefix, self.paint(GREEN, "+"), announced, path)?;
+	    }
+	}
+	for withdrawn in data.withdrawals() {
+	    writeln!(self.out, "{} {} {}", prefix, self.paint(RED, "\u{2212}"), withdrawn)?;
+	}
+	Ok(())
+    }
+
+    /// Writes a message, flushing after each one so output can be followed live
+    pub fn write(&mut self, message: &RisResponse) -> io::Result<()> {
+	match self.format {
+	    Format::Json => {
+		let separator = if self.written == 0 { "[\n" } else { ",\n" };
+		write!(self.out, "{}{}", separator, serde_json::to_string_pretty(message)?)?;
+	    },
+	    Format::Ndjson => writeln!(self.out, "{}", serde_json::to_string(message)?)?,
+	    Format::Csv => self.csv(message.data())?,
+	    Format::Pretty => self.pretty(message.data())?,
+	}
+	self.written += 1;
+	self.out.flush()
+    }
+
+    /// Completes the output, closing the array in JSON mode
+    pub fn finish(&mut self) -> io::Result<()> {
+	if self.format == Format::Json {
+	    let opening = if self.written == 0 { "[" } else { "" };
+	    writeln!(self.out, "{}\n]", opening)?;
+	}
+	self.out.flush()
+    }
+}
@@ -0,0 +1,74 @@
+//! The stats subcommand
+
+use std::io::{self, Write};
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
+
+use risclient::aggregate::PrefixAggregator;
+use risclient::stats::{StatsReport, StreamStats};
+use risclient::RisReceiver;
+
+use crate::{consume, Ended, Limits, EXIT_OUTPUT, EXIT_STREAM};
+
+fn breakdown<K: std::fmt::Display>(report: &StatsReport, entries: &[(K, u64)]) -> String {
+    entries.iter().map(|(key, count)| format!("{} {:.1}/s", key, report.rate(*count))).collect::<Vec<_>>().join(", ")
+}
+
+fn write_report(out: &mut impl Write, report: &StatsReport, aggregator: Option<&PrefixAggregator>) -> io::Result<()> {
+    writeln!(out, "{:.1} msg/s, {:.1} announcements/s, {:.1} withdrawals/s over {:.1}s",
+	     report.rate(report.messages), report.rate(report.announcements), report.rate(report.withdrawals), report.window.as_secs_f64())?;
+    writeln!(out, "  collectors: {}", breakdown(report, &report.collectors))?;
+    writeln!(out, "  types:      {}", breakdown(report, &report.types))?;
+    writeln!(out, "  prefixes:   {}", breakdown(report, &report.prefixes))?;
+    let origins: Vec<(String, u64)> = report.origins.iter().map(|(asn, count)| (format!("AS{}", asn), *count)).collect();
+    writeln!(out, "  origins:    {}", breakdown(report, &origins))?;
+    if let Some(aggregator) = aggregator {
+	for (origin, _) in &report.origins {
+	    let aggregates: Vec<String> = aggregator.aggregates(*origin).iter().map(|prefix| prefix.to_string()).collect();
+	    writeln!(out, "    AS{} aggregates: {}", origin, aggregates.join(", "))?;
+	}
+    }
+    out.flush()
+}
+
+/// Prints a report every `interval`, and a final one for the partial window when the stream stops.
+/// With `aggregate`, the prefixes each listed origin announced are summarised too.
+pub fn run(rx: RisReceiver, interval: Duration, top: usize, aggregate: bool, limits: &Limits) -> ExitCode {
+    let mut stats = StreamStats::new();
+    let mut aggregator = aggregate.then(PrefixAggregator::new);
+    let mut out = io::stdout();
+    let mut last = Instant::now();
+    let ended = consume(&rx, limits, |message| {
+	if let Some(message) = message {
+	    stats.observe(message.data());
+	    if let Some(aggregator) = &mut aggregator {
+		aggregator.observe(message.data());
+	    }
+	}
+	if last.elapsed() >= interval {
+	    last = Instant::now();
+	    write_report(&mut out, &stats.take_report(top), aggregator.as_ref())?;
+	    if let Some(aggregator) = &mut aggregator {
+		aggregator.reset();
+	    }
+	}
+	Ok(())
+    });
+    drop(rx);
+    match ended {
+	Ended::Failed(e) if e.kind() == io::ErrorKind::BrokenPipe => ExitCode::SUCCESS,
+	Ended::Failed(e) => {
+	    eprintln!("risclient: failed to write output: {}", e);
+	    ExitCode::from(EXIT_OUTPUT)
+	},
+	Ended::Closed => {
+	    let _ = write_report(&mut out, &stats.report(top), aggregator.as_ref());
+	    eprintln!("risclient: stream closed");
+	    ExitCode::from(EXIT_STREAM)
+	},
+	Ended::Stopped => match write_report(&mut out, &stats.report(top), aggregator.as_ref()) {
+	    Ok(()) => ExitCode::SUCCESS,
+	    Err(_) => ExitCode::from(EXIT_OUTPUT),
+	},
+    }
+}
@@ -0,0 +1,288 @@
+//! The top subcommand, an interactive dashboard of the live feed
+
+use std::collections::{BTreeSet, VecDeque};
+use std::io;
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
+
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, List, ListItem, Paragraph, Row, Sparkline, Table};
+use ratatui::{DefaultTerminal, Frame};
+
+use risclient::community::{CommunityWatcher, SignalState};
+use risclient::session::{SessionEvent, SessionTracker};
+use risclient::stats::{StatsReport, StreamStats};
+use risclient::subscription::DATA_TYPES;
+use risclient::{RisError, RisReceiver, RisResponse};
+
+use crate::{EXIT_OUTPUT, EXIT_STREAM};
+
+/// How long the top prefixes and origins are counted over before starting again
+const TOP_WINDOW: Duration = Duration::from_secs(60);
+/// How many rate samples the sparkline shows
+const HISTORY: usize = 120;
+/// How many peer state changes and alerts are kept
+const RECENT: usize = 100;
+/// The most messages handled between redraws, so the screen keeps up on a busy feed
+const BATCH: usize = 20_000;
+/// A peer going down this many times within `FLAP_WINDOW` is shown as flapping
+const FLAP_THRESHOLD: usize = 5;
+const FLAP_WINDOW: Duration = Duration::from_secs(600);
+
+fn time_of_day(timestamp: f64) -> String {
+    let seconds = timestamp as u64 % 86400;
+    format!("{:02}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+/// Cycles `current` through `None` and then each of `options` in turn
+fn cycle(current: &Option<String>, options: &[String]) -> Option<String> {
+    let next = match current {
+	None => 0,
+	Some(current) => options.iter().position(|option| option == current).map(|index| index + 1).unwrap_or(options.len()),
+    };
+    options.get(next).cloned()
+}
+
+struct Dashboard {
+    second: StreamStats,
+    window: StreamStats,
+    rates: StatsReport,
+    tops: StatsReport,
+    history: VecDeque<u64>,
+    sessions: VecDeque<Line<'static>>,
+    alerts: VecDeque<Line<'static>>,
+    watcher: CommunityWatcher,
+    tracker: SessionTracker,
+    collectors: BTreeSet<String>,
+    collector: Option<String>,
+    data_type: Option<String>,
+    paused: bool,
+    closed: bool,
+    top: usize,
+    last_tick: Instant,
+    window_started: Instant,
+}
+
+impl Dashboard {
+    fn new(top: usize) -> Dashboard {
+	let second = StreamStats::new();
+	let window = StreamStats::new();
+	Dashboard {
+	    rates: second.report(top),
+	    tops: window.report(top),
+	    second,
+	    window,
+	    history: VecDeque::with_capacity(HISTORY),
+	    sessions: VecDeque::new(),
+	    alerts: VecDeque::new(),
+	    watcher: CommunityWatcher::new(),
+	    tracker: SessionTracker::new().with_flap_detection(FLAP_THRESHOLD, FLAP_WINDOW),
+	    collectors: BTreeSet::new(),
+	    collector: None,
+	    data_type: None,
+	    paused: false,
+	    closed: false,
+	    top,
+	    last_tick: Instant::now(),
+	    window_started: Instant::now(),
+	}
+    }
+
+    fn push(recent: &mut VecDeque<Line<'static>>, line: Line<'static>) {
+	recent.push_front(line);
+	recent.truncate(RECENT);
+    }
+
+    fn observe(&mut self, message: &RisResponse) {
+	let data = message.data();
+	self.collectors.insert(data.host().to_string());
+	if self.paused {
+	    return;
+	}
+	if self.collector.as_deref().is_some_and(|collector| collector != data.host())
+	    || self.data_type.as_deref().is_some_and(|data_type| data_type != data.data_type()) {
+	    return;
+	}
+	self.second.observe(data);
+	self.window.observe(data);
+	if let Some(state) = data.state() {
+	    let styled = if state == "connected" { Span::from(state.to_string()).green() } else { Span::from(state.to_string()).red() };
+	    Dashboard::push(&mut self.sessions, Line::from(vec![
+		Span::from(format!("{} {:<6} AS{:<10} {:<40} ", time_of_day(data.timestamp()), data.host(), data.peer_asn(), data.peer())),
+		styled,
+	    ]));
+	}
+	for event in self.tracker.observe(data) {
+	    if let SessionEvent::PeerFlapping { collector, peer, flaps, over, timestamp, .. } = event {
+		Dashboard::push(&mut self.alerts, Line::from(vec![
+		    Span::from(format!("{} {:<6} ", time_of_day(timestamp), collector)),
+		    Span::from(format!("{:<18} ", "flapping")).bold(),
+		    Span::from(format!("{} down {} times in {:.0}s", peer, flaps, over)).red(),
+		]));
+	    }
+	}
+	for event in self.watcher.observe(data) {
+	    let state = match event.state {
+		SignalState::Raised => Span::from("raised ").yellow(),
+		SignalState::Cleared => Span::from("cleared").dim(),
+	    };
+	    Dashboard::push(&mut self.alerts, Line::from(vec![
+		Span::from(format!("{} {:<6} ", time_of_day(event.timestamp), event.collector)),
+		Span::from(format!("{:<18} ", event.community.to_string())).bold(),
+		state,
+		Span::from(format!(" {} from AS{}", event.prefix, event.peer_asn)),
+	    ]));
+	}
+    }
+
+    fn tick(&mut self) {
+	if self.last_tick.elapsed() < Duration::from_secs(1) {
+	    return;
+	}
+	self.last_tick = Instant::now();
+	self.rates = self.second.take_report(self.top);
+	if self.history.len() == HISTORY {
+	    self.history.pop_front();
+	}
+	self.history.push_back(self.rates.rate(self.rates.messages) as u64);
+	self.tops = self.window.report(self.top);
+	if self.window_started.elapsed() >= TOP_WINDOW {
+	    self.window.reset();
+	    self.window_started = Instant::now();
+	}
+    }
+
+    fn reset(&mut self) {
+	self.second.reset();
+	self.window.reset();
+	self.window_started = Instant::now();
+	self.history.clear();
+	self.sessions.clear();
+	self.alerts.clear();
+    }
+
+    fn status(&self) -> Line<'static> {
+	let mut spans = vec![
+	    Span::from(" risclient top ").bold().reversed(),
+	    Span::from(format!("  {:.0} msg/s  {:.0} ann/s  {:.0} wdr/s",
+			       self.rates.rate(self.rates.messages), self.rates.rate(self.rates.announcements), self.rates.rate(self.rates.withdrawals))),
+	    Span::from(format!("  collector: {}  type: {}", self.collector.as_deref().unwrap_or("all"), self.data_type.as_deref().unwrap_or("all"))),
+	];
+	if self.paused {
+	    spans.push(Span::from("  PAUSED").yellow().bold());
+	}
+	if self.closed {
+	    spans.push(Span::from("  STREAM CLOSED").red().bold());
+	}
+	Line::from(spans)
+    }
+
+    fn counts(&self, title: &str, entries: Vec<(String, u64)>, area: Rect, frame: &mut Frame) {
+	let seconds = self.tops.window.as_secs_f64().max(1.0);
+	let rows = entries.into_iter().map(|(key, count)| Row::new(vec![key, count.to_string(), format!("{:.1}/s", count as f64 / seconds)]));
+	let table = Table::new(rows, [Constraint::Fill(1), Constraint::Length(8), Constraint::Length(9)])
+	    .header(Row::new(vec!["", "count", "rate"]).style(Style::new().dim()))
+	    .block(Block::bordered().title(format!(" {} (last {:.0}s) ", title, seconds)));
+	frame.render_widget(table, area);
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+	let [status, sparkline, middle, bottom, help] = Layout::vertical([
+	    Constraint::Length(1),
+	    Constraint::Length(5),
+	    Constraint::Fill(1),
+	    Constraint::Fill(1),
+	    Constraint::Length(1),
+	]).areas(frame.area());
+	frame.render_widget(Paragraph::new(self.status()), status);
+
+	let history: Vec<u64> = self.history.iter().copied().collect();
+	let collectors = self.rates.collectors.iter().map(|(collector, count)| format!("{} {:.0}", collector, self.rates.rate(*count))).collect::<Vec<_>>().join("  ");
+	frame.render_widget(Sparkline::default()
+	    .block(Block::bordered().title(" msg/s ").title_bottom(format!(" {} ", collectors)))
+	    .data(&history)
+	    .style(Style::new().cyan()), sparkline);
+
+	let [prefixes, origins] = Layout::horizontal([Constraint::Fill(1), Constraint::Fill(1)]).areas(middle);
+	self.counts("top prefixes", self.tops.prefixes.clone(), prefixes, frame);
+	self.counts("top origins", self.tops.origins.iter().map(|(asn, count)| (format!("AS{}", asn), *count)).collect(), origins, frame);
+
+	let [sessions, alerts] = Layout::horizontal([Constraint::Fill(1), Constraint::Fill(1)]).areas(bottom);
+	let visible = |recent: &VecDeque<Line<'static>>, area: Rect| -> Vec<ListItem<'static>> {
+	    recent.iter().take(area.height as usize).cloned().map(ListItem::new).collect()
+	};
+	frame.render_widget(List::new(visible(&self.sessions, sessions)).block(Block::bordered().title(" peer sessions ")), sessions);
+	frame.render_widget(List::new(visible(&self.alerts, alerts)).block(Block::bordered().title(" alerts ")), alerts);
+
+	frame.render_widget(Paragraph::new(" q quit  c cycle collector  t cycle type  p pause  r reset").dim(), help);
+    }
+
+    /// Handles a key press, returning false to quit
+    fn key(&mut self, code: KeyCode, modifiers: KeyModifiers) -> bool {
+	match code {
+	    KeyCode::Char('q') | KeyCode::Esc => return false,
+	    KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => return false,
+	    KeyCode::Char('c') => {
+		let collectors: Vec<String> = self.collectors.iter().cloned().collect();
+		self.collector = cycle(&self.collector, &collectors);
+		self.reset();
+	    },
+	    KeyCode::Char('t') => {
+		let types: Vec<String> = DATA_TYPES.iter().map(|data_type| data_type.to_string()).collect();
+		self.data_type = cycle(&self.data_type, &types);
+		self.reset();
+	    },
+	    KeyCode::Char('p') => self.paused = !self.paused,
+	    KeyCode::Char('r') => self.reset(),
+	    _ => {},
+	}
+	true
+    }
+}
+
+fn run_dashboard(terminal: &mut DefaultTerminal, rx: &RisReceiver, top: usize) -> io::Result<bool> {
+    let mut dashboard = Dashboard::new(top);
+    loop {
+	for _ in 0..BATCH {
+	    match rx.try_recv() {
+		Ok(message) => dashboard.observe(&message),
+		Err(RisError::Empty) => break,
+		Err(RisError::Closed) => {
+		    dashboard.closed = true;
+		    break;
+		},
+		Err(_) => continue,
+	    }
+	}
+	dashboard.tick();
+	terminal.draw(|frame| dashboard.draw(frame))?;
+	if event::poll(Duration::from_millis(100))? {
+	    if let Event::Key(key) = event::read()? {
+		if key.kind == KeyEventKind::Press && !dashboard.key(key.code, key.modifiers) {
+		    return Ok(dashboard.closed);
+		}
+	    }
+	}
+    }
+}
+
+/// Runs the dashboard until the user quits
+pub fn run(rx: RisReceiver, top: usize) -> ExitCode {
+    let mut terminal = ratatui::init();
+    let result = run_dashboard(&mut terminal, &rx, top);
+    ratatui::restore();
+    match result {
+	Ok(false) => ExitCode::SUCCESS,
+	Ok(true) => {
+	    eprintln!("risclient: stream closed");
+	    ExitCode::from(EXIT_STREAM)
+	},
+	Err(e) => {
+	    eprintln!("risclient: failed to draw: {}", e);
+	    ExitCode::from(EXIT_OUTPUT)
+	},
+    }
+}
@@ -0,0 +1,60 @@
+//! The watch subcommand
+
+use std::io::{self, Write};
+use std::process::ExitCode;
+
+use risclient::watch::PrefixWatch;
+use risclient::RisReceiver;
+
+use crate::{consume, Ended, Limits, EXIT_OUTPUT, EXIT_STREAM, EXIT_VIOLATION};
+
+/// Posts an event to a webhook, waiting for the request to finish so nothing is lost on exit
+fn notify(client: &reqwest::Client, webhook: &str, body: String) {
+    let request = client.post(webhook).header(reqwest::header::CONTENT_TYPE, "application/json").body(body);
+    let result = tokio::task::block_in_place(|| tokio::runtime::Handle::current().block_on(async {
+	request.send().await?.error_for_status()
+    }));
+    if let Err(e) = result {
+	eprintln!("risclient: failed to notify {}: {}", webhook, e);
+    }
+}
+
+/// Prints each event as a JSON line and posts it to `webhook`, stopping at the first violation if asked to
+pub fn run(rx: RisReceiver, mut watch: PrefixWatch, webhook: Option<String>, exit_on_violation: bool, limits: &Limits) -> ExitCode {
+    let client = reqwest::Client::new();
+    let mut out = io::stdout();
+    let mut violated = false;
+    let ended = consume(&rx, limits, |message| {
+	let message = match message {
+	    Some(message) if !violated => message,
+	    _ => return Ok(()),
+	};
+	for event in watch.observe(message.data()) {
+	    let line = serde_json::to_string(&event)?;
+	    writeln!(out, "{}", line)?;
+	    out.flush()?;
+	    if let Some(webhook) = &webhook {
+		notify(&client, webhook, line);
+	    }
+	    if exit_on_violation && event.is_violation() {
+		violated = true;
+		return Err(io::Error::other("violation"));
+	    }
+	}
+	Ok(())
+    });
+    drop(rx);
+    match ended {
+	Ended::Failed(_) if violated => ExitCode::from(EXIT_VIOLATION),
+	Ended::Failed(e) if e.kind() == io::ErrorKind::BrokenPipe => ExitCode::SUCCESS,
+	Ended::Failed(e) => {
+	    eprintln!("risclient: failed to write output: {}", e);
+	    ExitCode::from(EXIT_OUTPUT)
+	},
+	Ended::Closed => {
+	    eprintln!("risclient: stream closed");
+	    ExitCode::from(EXIT_STREAM)
+	},
+	Ended::Stopped => ExitCode::SUCCESS,
+    }
+}
@@ -0,0 +1,92 @@
+//! A blocking client, for code that does not use async
+//!
+//! `BlockingRisClient` runs the async client on a runtime it manages itself,
+//! so it can be used from plain synchronous code without setting up tokio.
+//! Creating one must not happen inside an existing tokio runtime.
+
+use std::error;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::runtime::Runtime;
+
+use crate::{RisClient, RisError, RisReceiver, RisResponse, Subscription};
+
+///
+/// A RIS Live client whose methods block until they complete.
+/// Streams keep the client's runtime alive, so they continue to work after the client is dropped.
+///
+pub struct BlockingRisClient {
+    runtime: Arc<Runtime>,
+    client: RisClient,
+}
+
+impl BlockingRisClient {
+
+    /// Returns a BlockingRisClient connecting to `host` as `client_id`
+    pub fn new(host: String, client_id: String) -> Result<BlockingRisClient, Box<dyn error::Error>> {
+	Ok(BlockingRisClient {
+	    runtime: Arc::new(Runtime::new()?),
+	    client: RisClient::new(host, client_id)?,
+	})
+    }
+
+    /// Returns a BlockingRisClient connecting to ris-live.ripe.net, like `RisClient::default`
+    #[allow(clippy::should_implement_trait)]
+    pub fn default() -> Result<BlockingRisClient, Box<dyn error::Error>> {
+	Ok(BlockingRisClient {
+	    runtime: Arc::new(Runtime::new()?),
+	    client: RisClient::default()?,
+	})
+    }
+
+    /// Connects and subscribes, returning an iterator of messages
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use risclient::Subscription;
+    /// use risclient::blocking::BlockingRisClient;
+    /// let mut client = BlockingRisClient::default().unwrap();
+    /// for message in client.subscribe(&Subscription::new().host("rrc00")).unwrap() {
+    ///     println!("{:?}", message);
+    /// }
+    /// ```
+    pub fn subscribe(&mut self, subscription: &Subscription) -> Result<BlockingStream, Box<dyn error::Error>> {
+	let rx = self.runtime.block_on(self.client.subscribe(subscription))?;
+	Ok(BlockingStream { _runtime: self.runtime.clone(), rx })
+    }
+
+    /// Connects and subscribes with no filters
+    pub fn stream(&mut self) -> Result<BlockingStream, Box<dyn error::Error>> {
+	self.subscribe(&Subscription::new())
+    }
+}
+
+/// Messages from a subscription, ending if the connection closes.
+/// Messages that fail to decode are passed on as errors.
+pub struct BlockingStream {
+    // keeps the task feeding the receiver running
+    _runtime: Arc<Runtime>,
+    rx: RisReceiver,
+}
+
+impl BlockingStream {
+    /// Waits for the next message, returning `RisError::Closed` once the stream has ended
+    pub fn recv(&self) -> Result<RisResponse, RisError> {
+	self.rx.blocking_recv()
+    }
+
+    /// Waits up to `timeout` for the next message, returning `RisError::Timeout` if none arrives
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<RisResponse, RisError> {
+	self.rx.recv_timeout(timeout)
+    }
+}
+
+impl Iterator for BlockingStream {
+    type Item = Result<RisResponse, RisError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+	self.rx.iter().next()
+    }
+}
@@ -0,0 +1,270 @@
+//! BMP (RFC 7854) export of the live feed
+//!
+//! Presents each RIS peer to a BMP station as if it were a peer of a local
+//! router, so monitoring stacks built around BMP can ingest RIS Live without
+//! any changes. Each collector is reported as a separate local instance,
+//! so the same peer seen at two collectors remains distinguishable.
+
+use std::collections::HashSet;
+use std::error;
+use std::net::{IpAddr, Ipv4Addr};
+
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+
+use crate::bgp;
+use crate::{RisResponse, RisResponseData};
+
+const VERSION: u8 = 3;
+
+const MESSAGE_ROUTE_MONITORING: u8 = 0;
+const MESSAGE_PEER_DOWN: u8 = 2;
+const MESSAGE_PEER_UP: u8 = 3;
+const MESSAGE_INITIATION: u8 = 4;
+const MESSAGE_TERMINATION: u8 = 5;
+
+const PEER_TYPE_LOCAL_INSTANCE: u8 = 2;
+const PEER_FLAG_IPV6: u8 = 0x80;
+
+const INFO_STRING: u16 = 0;
+const INFO_SYS_DESCR: u16 = 1;
+const INFO_SYS_NAME: u16 = 2;
+
+/// The remote system closed the session without a notification
+const PEER_DOWN_REMOTE_NO_NOTIFICATION: u8 = 4;
+
+/// The ASN RIS collectors peer from
+const RIS_ASN: u32 = 12654;
+
+fn message(message_type: u8, body: &[u8]) -> Vec<u8> {
+    let mut message = vec![VERSION];
+    message.extend_from_slice(&((6 + body.len()) as u32).to_be_bytes());
+    message.push(message_type);
+    message.extend_from_slice(body);
+    message
+}
+
+fn information_tlv(tlv_type: u16, value: &str) -> Vec<u8> {
+    let mut tlv = tlv_type.to_be_bytes().to_vec();
+    tlv.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    tlv.extend_from_slice(value.as_bytes());
+    tlv
+}
+
+/// Derives a non-zero peer distinguisher from a collector name, "rrc00" becoming 1, "rrc21" becoming 22
+fn distinguisher(host: &str) -> u64 {
+    let digits: String = host.chars().filter(|c| c.is_ascii_digit()).collect();
+    digits.parse::<u64>().map(|n| n + 1).unwrap_or(u64::MAX)
+}
+
+///
+/// Encodes RIS messages as BMP messages.
+/// This is the encoding half of `BmpExporter`, useful for writing BMP to something other than a TCP station.
+///
+#[derive(Debug, Clone)]
+pub struct BmpEncoder {
+    sys_name: String,
+    sys_descr: String,
+}
+
+impl BmpEncoder {
+
+    /// Returns a BmpEncoder which identifies itself to stations with the provided name and description
+    pub fn new(sys_name: String, sys_descr: String) -> BmpEncoder {
+	BmpEncoder {
+	    sys_name,
+	    sys_descr,
+	}
+    }
+
+    /// Returns the per-peer header for the peer a message was received from
+    fn peer_header(&self, data: &RisResponseData) -> Result<Vec<u8>, Box<dyn error::Error + Send + Sync>> {
+	let peer: IpAddr = data.peer().parse()?;
+	let peer_asn: u32 = data.peer_asn().parse()?;
+	let mut header = vec![PEER_TYPE_LOCAL_INSTANCE];
+	let mut address = [0u8; 16];
+	let bgp_id = match peer {
+	    IpAddr::V4(peer) => {
+		header.push(0);
+		address[12..].copy_from_slice(&peer.octets());
+		peer
+	    },
+	    IpAddr::V6(peer) => {
+		header.push(PEER_FLAG_IPV6);
+		address.copy_from_slice(&peer.octets());
+		Ipv4Addr::UNSPECIFIED
+	    },
+	};
+	header.extend_from_slice(&distinguisher(data.host()).to_be_bytes());
+	header.extend_from_slice(&address);
+	header.extend_from_slice(&peer_asn.to_be_bytes());
+	header.extend_from_slice(&bgp_id.octets());
+	let seconds = data.timestamp().trunc();
+	let micros = ((data.timestamp() - seconds) * 1_000_000.0) as u32;
+	header.extend_from_slice(&(seconds as u32).to_be_bytes());
+	header.extend_from_slice(&micros.to_be_bytes());
+	Ok(header)
+    }
+
+    /// Returns the Initiation message a station expects at the start of a session
+    pub fn initiation(&self) -> Vec<u8> {
+	let mut body = information_tlv(INFO_SYS_DESCR, &self.sys_descr);
+	body.extend(information_tlv(INFO_SYS_NAME, &self.sys_name));
+	message(MESSAGE_INITIATION, &body)
+    }
+
+    /// Returns the Termination message sent before closing a session
+    pub fn termination(&self) -> Vec<u8> {
+	// reason 0: session administratively closed
+	let mut body = 1u16.to_be_bytes().to_vec();
+	body.extend_from_slice(&2u16.to_be_bytes());
+	body.extend_from_slice(&0u16.to_be_bytes());
+	message(MESSAGE_TERMINATION, &body)
+    }
+
+    /// Returns a Peer Up notification for the peer a message was received from.
+    /// RIS Live does not carry the OPEN messages exchanged with the peer, so these are synthesised.
+    pub fn peer_up(&self, data: &RisResponseData) -> Result<Vec<u8>, Box<dyn error::Error + Send + Sync>> {
+	let mut body = self.peer_header(data)?;
+	let peer_asn: u32 = data.peer_asn().parse()?;
+	let bgp_id = match data.peer().parse::<IpAddr>()? {
+	    IpAddr::V4(peer) => peer,
+	    IpAddr::V6(_) => Ipv4Addr::UNSPECIFIED,
+	};
+	// the collector's local address is not known
+	body.extend_from_slice(&[0u8; 16]);
+	body.extend_from_slice(&179u16.to_be_bytes());
+	body.extend_from_slice(&0u16.to_be_bytes());
+	body.extend(bgp::encode_open(RIS_ASN, Ipv4Addr::UNSPECIFIED, 180));
+	body.extend(bgp::encode_open(peer_asn, bgp_id, 180));
+	body.extend(information_tlv(INFO_STRING, data.host()));
+	Ok(message(MESSAGE_PEER_UP, &body))
+    }
+
+    /// Returns a Peer Down notification for the peer a message was received from
+    pub fn peer_down(&self, data: &RisResponseData) -> Result<Vec<u8>, Box<dyn error::Error + Send + Sync>> {
+	let mut body = self.peer_header(data)?;
+	body.push(PEER_DOWN_REMOTE_NO_NOTIFICATION);
+	Ok(message(MESSAGE_PEER_DOWN, &body))
+    }
+
+    /// Returns Route Monitoring messages for an UPDATE, using the raw PDU where the message carries one
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use risclient::RisResponse;
+    /// use risclient::bmp::BmpEncoder;
+    /// let message: RisResponse = serde_json::from_str(r#"{"type": "ris_message", "data": {"timestamp": 1650000000.25,
+    ///     "peer": "192.0.2.1", "peer_asn": "64500", "id": "1", "host": "rrc00", "type": "UPDATE",
+    ///     "path": [64500, 64501], "origin": "igp", "withdrawals": ["198.51.100.0/24"]}}"#).unwrap();
+    /// let encoder = BmpEncoder::default();
+    /// let messages = encoder.route_monitoring(message.data()).unwrap();
+    /// assert_eq!(messages.len(), 1);
+    /// assert_eq!(messages[0][5], 0);
+    /// ```
+    pub fn route_monitoring(&self, data: &RisResponseData) -> Result<Vec<Vec<u8>>, Box<dyn error::Error + Send + Sync>> {
+	let header = self.peer_header(data)?;
+	let updates = match bgp::raw_message(data) {
+	    Some(raw) if raw[18] == bgp::MESSAGE_UPDATE => vec![raw],
+	    _ => bgp::encode_update(data)?,
+	};
+	Ok(updates.into_iter().map(|update| {
+	    let mut body = header.clone();
+	    body.extend(update);
+	    message(MESSAGE_ROUTE_MONITORING, &body)
+	}).collect())
+    }
+}
+
+impl Default for BmpEncoder {
+    fn default() -> BmpEncoder {
+	BmpEncoder::new("risclient".to_string(), "RIS Live via risclient".to_string())
+    }
+}
+
+///
+/// Streams RIS messages to a BMP station.
+/// Peers are announced with a Peer Up the first time they are seen, and withdrawn
+/// with a Peer Down when RIS reports their session going down.
+///
+pub struct BmpExporter {
+    stream: TcpStream,
+    encoder: BmpEncoder,
+    peers: HashSet<(String, String)>,
+}
+
+impl BmpExporter {
+
+    /// Connects to the BMP station at `addr` and sends the Initiation message
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use risclient::{RisClient, Subscription};
+    /// use risclient::bmp::BmpExporter;
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let mut exporter = BmpExporter::connect("127.0.0.1:5000").await.unwrap();
+    /// let mut client = RisClient::default().unwrap();
+    /// let rx = client.subscribe(&Subscription::new().include_raw(true)).await.unwrap();
+    /// while let Ok(message) = rx.recv().await {
+    ///     exporter.export(&message).await.unwrap();
+    /// }
+    /// # }
+    /// ```
+    pub async fn connect(addr: &str) -> Result<BmpExporter, Box<dyn error::Error + Send + Sync>> {
+	BmpExporter::connect_with_encoder(addr, BmpEncoder::default()).await
+    }
+
+    /// Connects to the BMP station at `addr`, identifying with the provided encoder's names
+    pub async fn connect_with_encoder(addr: &str, encoder: BmpEncoder) -> Result<BmpExporter, Box<dyn error::Error + Send + Sync>> {
+	let mut stream = TcpStream::connect(addr).await?;
+	stream.write_all(&encoder.initiation()).await?;
+	Ok(BmpExporter {
+	    stream,
+	    encoder,
+	    peers: HashSet::new(),
+	})
+    }
+
+    /// Exports a single RIS message. Messages other than UPDATEs and peer state changes are ignored.
+    pub async fn export(&mut self, message: &RisResponse) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+	let data = message.data();
+	let key = (data.host().to_string(), data.peer().to_string());
+	match (data.data_type(), data.state()) {
+	    ("UPDATE", _) => {
+		self.peer_up(data, key).await?;
+		for monitoring in self.encoder.route_monitoring(data)? {
+		    self.stream.write_all(&monitoring).await?;
+		}
+	    },
+	    ("RIS_PEER_STATE", Some("connected")) => self.peer_up(data, key).await?,
+	    ("RIS_PEER_STATE", Some("down")) => self.peer_down(data, &key).await?,
+	    _ => {},
+	}
+	Ok(())
+    }
+
+    async fn peer_up(&mut self, data: &RisResponseData, key: (String, String)) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+	if !self.peers.contains(&key) {
+	    self.stream.write_all(&self.encoder.peer_up(data)?).await?;
+	    self.peers.insert(key);
+	}
+	Ok(())
+    }
+
+    async fn peer_down(&mut self, data: &RisResponseData, key: &(String, String)) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+	if self.peers.remove(key) {
+	    self.stream.write_all(&self.encoder.peer_down(data)?).await?;
+	}
+	Ok(())
+    }
+
+    /// Sends the Termination message and closes the connection to the station
+    pub async fn close(mut self) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+	self.stream.write_all(&self.encoder.termination()).await?;
+	self.stream.shutdown().await?;
+	Ok(())
+    }
+}
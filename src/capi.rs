@@ -0,0 +1,330 @@
+//! C API
+//!
+//! A stable C ABI for embedding the client in C and C++ tools. The header at
+//! `include/risclient.h` is generated by cbindgen and committed, so after changing
+//! this module regenerate it with `cbindgen --config cbindgen.toml --output include/risclient.h`.
+//! Every object is created and freed through this API, and
+//! strings returned from a message stay valid until that message is freed.
+//!
+//! ```c
+//! RisClientHandle *client = risclient_client_new(NULL, "my-tool");
+//! RisFilter filter = risclient_filter_default();
+//! filter.host = "rrc00";
+//! RisSubscriptionHandle *subscription = risclient_subscribe(client, &filter);
+//! RisMessageHandle *message;
+//! while ((message = risclient_poll(subscription, 1000)) != NULL) {
+//!     puts(risclient_message_json(message));
+//!     risclient_message_free(message);
+//! }
+//! ```
+
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::ptr;
+use std::time::Duration;
+
+use crate::blocking::{BlockingRisClient, BlockingStream};
+use crate::{RisError, RisResponse, Subscription};
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_error(error: String) {
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(cstring(error)));
+}
+
+fn cstring(string: String) -> CString {
+    CString::new(string.replace('\0', "")).unwrap_or_default()
+}
+
+/// Reads an optional C string, treating NULL as unset
+unsafe fn optional(string: *const c_char) -> Option<String> {
+    if string.is_null() {
+	None
+    } else {
+	Some(CStr::from_ptr(string).to_string_lossy().into_owned())
+    }
+}
+
+/// A RIS Live client, owning the runtime its subscriptions run on
+pub struct RisClientHandle {
+    client: BlockingRisClient,
+}
+
+/// A live subscription, created by `risclient_subscribe`
+pub struct RisSubscriptionHandle {
+    stream: BlockingStream,
+}
+
+/// A message received from RIS Live
+pub struct RisMessageHandle {
+    response: RisResponse,
+    json: CString,
+    data_type: CString,
+    host: CString,
+    peer: CString,
+}
+
+impl RisMessageHandle {
+    fn new(response: RisResponse) -> RisMessageHandle {
+	let data = response.data();
+	RisMessageHandle {
+	    json: cstring(serde_json::to_string(&response).unwrap_or_default()),
+	    data_type: cstring(data.data_type().to_string()),
+	    host: cstring(data.host().to_string()),
+	    peer: cstring(data.peer().to_string()),
+	    response,
+	}
+    }
+}
+
+/// Subscription filters. NULL strings, a NULL path and negative booleans leave a filter unset.
+#[repr(C)]
+pub struct RisFilter {
+    /// A collector, or several separated by commas
+    pub host: *const c_char,
+    pub data_type: *const c_char,
+    pub require: *const c_char,
+    pub peer: *const c_char,
+    pub prefix: *const c_char,
+    pub path: *const u32,
+    pub path_len: usize,
+    pub more_specific: c_int,
+    pub less_specific: c_int,
+    pub include_raw: c_int,
+}
+
+/// Called with each message received by `risclient_run`. The message is only valid during the call.
+/// Return non-zero to stop receiving.
+pub type RisCallback = extern "C" fn(message: *const RisMessageHandle, user_data: *mut c_void) -> c_int;
+
+/// Returns the last error raised on this thread, or NULL. The string is valid until the next failing call.
+#[no_mangle]
+pub extern "C" fn risclient_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map(|error| error.as_ptr()).unwrap_or(ptr::null()))
+}
+
+/// Creates a client. A NULL host connects to ris-live.ripe.net, and a NULL client_id uses "c-risclient".
+/// Returns NULL on failure.
+///
+/// # Safety
+///
+/// `host` and `client_id` must each be NULL or a valid NUL terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn risclient_client_new(host: *const c_char, client_id: *const c_char) -> *mut RisClientHandle {
+    let host = optional(host).unwrap_or_else(|| "ris-live.ripe.net".to_string());
+    let client_id = optional(client_id).unwrap_or_else(|| "c-risclient".to_string());
+    match BlockingRisClient::new(host, client_id) {
+	Ok(client) => Box::into_raw(Box::new(RisClientHandle { client })),
+	Err(e) => {
+	    set_error(e.to_string());
+	    ptr::null_mut()
+	},
+    }
+}
+
+/// Frees a client. Subscriptions created from it keep running until they are freed.
+///
+/// # Safety
+///
+/// `client` must be NULL or a pointer returned by `risclient_client_new` that has not been freed.
+#[no_mangle]
+pub unsafe extern "C" fn risclient_client_free(client: *mut RisClientHandle) {
+    if !client.is_null() {
+	drop(Box::from_raw(client));
+    }
+}
+
+/// Returns a filter with every field unset
+#[no_mangle]
+pub extern "C" fn risclient_filter_default() -> RisFilter {
+    RisFilter {
+	host: ptr::null(),
+	data_type: ptr::null(),
+	require: ptr::null(),
+	peer: ptr::null(),
+	prefix: ptr::null(),
+	path: ptr::null(),
+	path_len: 0,
+	more_specific: -1,
+	less_specific: -1,
+	include_raw: 0,
+    }
+}
+
+/// Connects and subscribes, blocking until RIS Live acknowledges or rejects the subscription, for up to
+/// 10 seconds. A NULL filter receives the full stream. Returns NULL on failure, including a rejected
+/// or unacknowledged subscription, with the reason in `risclient_last_error`.
+///
+/// # Safety
+///
+/// `client` must be a live client. `filter` must be NULL or point to a filter whose strings are NULL or
+/// NUL terminated and whose path, if not NULL, holds `path_len` ASNs.
+#[no_mangle]
+pub unsafe extern "C" fn risclient_subscribe(client: *mut RisClientHandle, filter: *const RisFilter) -> *mut RisSubscriptionHandle {
+    let client = match client.as_mut() {
+	Some(client) => client,
+	None => {
+	    set_error("client is NULL".to_string());
+	    return ptr::null_mut();
+	},
+    };
+    let mut subscription = Subscription::new();
+    if let Some(filter) = filter.as_ref() {
+	subscription.hosts = optional(filter.host).map(|hosts| hosts.split(',').map(|host| host.trim().to_string()).collect()).unwrap_or_default();
+	subscription.data_type = optional(filter.data_type);
+	subscription.require = optional(filter.require);
+	subscription.peer = optional(filter.peer);
+	subscription.prefix = optional(filter.prefix);
+	if !filter.path.is_null() {
+	    subscription.path = Some(std::slice::from_raw_parts(filter.path, filter.path_len).to_vec());
+	}
+	subscription.more_specific = if filter.more_specific < 0 { None } else { Some(filter.more_specific != 0) };
+	subscription.less_specific = if filter.less_specific < 0 { None } else { Some(filter.less_specific != 0) };
+	subscription.include_raw = filter.include_raw > 0;
+    }
+    match client.client.subscribe(&subscription) {
+	Ok(stream) => Box::into_raw(Box::new(RisSubscriptionHandle { stream })),
+	Err(e) => {
+	    set_error(e.to_string());
+	    ptr::null_mut()
+	},
+    }
+}
+
+/// Frees a subscription. The connection to RIS Live is closed when the next message arrives.
+///
+/// # Safety
+///
+/// `subscription` must be NULL or a pointer returned by `risclient_subscribe` that has not been freed.
+#[no_mangle]
+pub unsafe extern "C" fn risclient_subscription_free(subscription: *mut RisSubscriptionHandle) {
+    if !subscription.is_null() {
+	drop(Box::from_raw(subscription));
+    }
+}
+
+/// Waits up to `timeout_ms` milliseconds for the next message, or forever if it is negative.
+/// Returns NULL on timeout, on a message that failed to decode, or once the stream has closed,
+/// which `risclient_last_error` distinguishes.
+/// The message must be freed with `risclient_message_free`.
+///
+/// # Safety
+///
+/// `subscription` must be a live subscription.
+#[no_mangle]
+pub unsafe extern "C" fn risclient_poll(subscription: *mut RisSubscriptionHandle, timeout_ms: i64) -> *mut RisMessageHandle {
+    let subscription = match subscription.as_ref() {
+	Some(subscription) => subscription,
+	None => {
+	    set_error("subscription is NULL".to_string());
+	    return ptr::null_mut();
+	},
+    };
+    let received = if timeout_ms < 0 {
+	subscription.stream.recv()
+    } else {
+	subscription.stream.recv_timeout(Duration::from_millis(timeout_ms as u64))
+    };
+    match received {
+	Ok(response) => Box::into_raw(Box::new(RisMessageHandle::new(response))),
+	Err(e) => {
+	    set_error(e.to_string());
+	    ptr::null_mut()
+	},
+    }
+}
+
+/// Calls `callback` with each message until it returns non-zero or the stream closes.
+/// Messages that fail to decode are skipped.
+/// Returns 0 if the callback stopped the loop, or -1 if the stream closed.
+///
+/// # Safety
+///
+/// `subscription` must be a live subscription, and `user_data` must be valid for whatever `callback` does with it.
+#[no_mangle]
+pub unsafe extern "C" fn risclient_run(subscription: *mut RisSubscriptionHandle, callback: RisCallback, user_data: *mut c_void) -> c_int {
+    let subscription = match subscription.as_ref() {
+	Some(subscription) => subscription,
+	None => {
+	    set_error("subscription is NULL".to_string());
+	    return -1;
+	},
+    };
+    loop {
+	let response = match subscription.stream.recv() {
+	    Ok(response) => response,
+	    Err(RisError::Closed) => break,
+	    Err(_) => continue,
+	};
+	let message = RisMessageHandle::new(response);
+	if callback(&message, user_data) != 0 {
+	    return 0;
+	}
+    }
+    set_error("stream closed".to_string());
+    -1
+}
+
+/// Frees a message returned by `risclient_poll`
+///
+/// # Safety
+///
+/// `message` must be NULL or a pointer returned by `risclient_poll` that has not been freed.
+#[no_mangle]
+pub unsafe extern "C" fn risclient_message_free(message: *mut RisMessageHandle) {
+    if !message.is_null() {
+	drop(Box::from_raw(message));
+    }
+}
+
+/// Returns the message as RIS Live JSON
+///
+/// # Safety
+///
+/// `message` must be a live message.
+#[no_mangle]
+pub unsafe extern "C" fn risclient_message_json(message: *const RisMessageHandle) -> *const c_char {
+    (*message).json.as_ptr()
+}
+
+/// Returns the BGP message type, such as "UPDATE"
+///
+/// # Safety
+///
+/// `message` must be a live message.
+#[no_mangle]
+pub unsafe extern "C" fn risclient_message_type(message: *const RisMessageHandle) -> *const c_char {
+    (*message).data_type.as_ptr()
+}
+
+/// Returns the collector the message was received at, such as "rrc00"
+///
+/// # Safety
+///
+/// `message` must be a live message.
+#[no_mangle]
+pub unsafe extern "C" fn risclient_message_host(message: *const RisMessageHandle) -> *const c_char {
+    (*message).host.as_ptr()
+}
+
+/// Returns the address of the peer the message was received from
+///
+/// # Safety
+///
+/// `message` must be a live message.
+#[no_mangle]
+pub unsafe extern "C" fn risclient_message_peer(message: *const RisMessageHandle) -> *const c_char {
+    (*message).peer.as_ptr()
+}
+
+/// Returns when the collector saw the message, its RIS Live `timestamp`, in seconds since the epoch
+///
+/// # Safety
+///
+/// `message` must be a live message.
+#[no_mangle]
+pub unsafe extern "C" fn risclient_message_timestamp(message: *const RisMessageHandle) -> f64 {
+    (*message).response.data().timestamp()
+}
@@ -0,0 +1,132 @@
+//! Time sources
+//!
+//! Everything in the client that waits or measures time asks a `Clock`: the
+//! client's reconnect backoff, timeouts and health, the `RisReceiver` adapters,
+//! replays, the windows of statistics and alerts, and the refreshes of ROAs,
+//! RTR, PeeringDB and the collector registry. Each takes one with `with_clock`, or from the client or
+//! replay it came from. `SystemClock` is the real thing, and `TokioClock`
+//! follows tokio's clock, so that under `tokio::time::pause` time only moves
+//! when the test advances it, and windowed logic can be tested deterministically.
+
+use std::fmt::Debug;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::RisError;
+
+/// A source of time
+pub trait Clock: Debug + Send + Sync {
+    /// Returns the current time, for measuring how long something took
+    fn now(&self) -> Instant;
+
+    /// Returns the current wall clock time, in seconds since the epoch
+    fn epoch_seconds(&self) -> f64;
+
+    /// Waits for `duration` to pass
+    fn sleep(&self, duration: Duration) -> Pin<Box<dyn Future<Output = ()> + Send>>;
+}
+
+/// The system's clocks, sleeping with tokio
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+	Instant::now()
+    }
+
+    fn epoch_seconds(&self) -> f64 {
+	SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs_f64()).unwrap_or(0.0)
+    }
+
+    fn sleep(&self, duration: Duration) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+	Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+///
+/// Tokio's clock, which stands still while paused with `tokio::time::pause` and moves with
+/// `tokio::time::advance`. The wall clock starts at the system time when the TokioClock is created,
+/// or at `starting_at`, and moves with tokio's.
+///
+#[derive(Debug, Clone, Copy)]
+pub struct TokioClock {
+    started: tokio::time::Instant,
+    epoch: f64,
+}
+
+impl TokioClock {
+    /// Returns a TokioClock whose wall clock starts at the system time
+    pub fn new() -> TokioClock {
+	TokioClock::starting_at(SystemClock.epoch_seconds())
+    }
+
+    /// Returns a TokioClock whose wall clock starts at `epoch`, in seconds since the epoch
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use risclient::clock::{Clock, TokioClock};
+    /// # #[tokio::main(flavor = "current_thread", start_paused = true)]
+    /// # async fn main() {
+    /// let clock = TokioClock::starting_at(1650000000.0);
+    /// let started = clock.now();
+    /// tokio::time::advance(Duration::from_secs(90)).await;
+    /// assert_eq!(clock.now() - started, Duration::from_secs(90));
+    /// assert_eq!(clock.epoch_seconds(), 1650000090.0);
+    /// # }
+    /// ```
+    pub fn starting_at(epoch: f64) -> TokioClock {
+	TokioClock { started: tokio::time::Instant::now(), epoch }
+    }
+}
+
+impl Default for TokioClock {
+    fn default() -> TokioClock {
+	TokioClock::new()
+    }
+}
+
+impl Clock for TokioClock {
+    fn now(&self) -> Instant {
+	tokio::time::Instant::now().into_std()
+    }
+
+    fn epoch_seconds(&self) -> f64 {
+	self.epoch + self.started.elapsed().as_secs_f64()
+    }
+
+    fn sleep(&self, duration: Duration) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+	Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+/// Returns the clock used unless another is given
+pub(crate) fn system() -> Arc<dyn Clock> {
+    Arc::new(SystemClock)
+}
+
+/// Runs `future` on the current tokio runtime, or on a thread with a runtime of its own when called
+/// outside one, so that it can sleep through a `Clock` either way
+pub(crate) fn spawn<F: Future<Output = ()> + Send + 'static>(future: F) {
+    if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+	runtime.spawn(future);
+	return;
+    }
+    std::thread::spawn(move || {
+	if let Ok(runtime) = tokio::runtime::Builder::new_current_thread().enable_time().build() {
+	    runtime.block_on(future);
+	}
+    });
+}
+
+/// Runs `future` until `deadline` by `clock`, failing with `RisError::Timeout` if it has not finished by then
+pub(crate) async fn timeout_at<F: Future>(clock: &dyn Clock, deadline: Instant, future: F) -> Result<F::Output, RisError> {
+    tokio::select! {
+	output = future => Ok(output),
+	_ = clock.sleep(deadline.saturating_duration_since(clock.now())) => Err(RisError::Timeout),
+    }
+}
@@ -0,0 +1,262 @@
+//! The registry of RIS collectors
+//!
+//! Lists the route collectors RIPE operates, as published by RIPEstat, so that
+//! tools can tell which collectors exist without hard coding them. RIS Live
+//! sends an unfiltered subscription's messages from every collector, so a
+//! `Subscription::all_collectors` stream follows RIPE's collectors by itself;
+//! the registry says which to expect, and `CollectorRegistry::changes` reports
+//! when RIPE adds or retires one.
+
+#[cfg(feature = "http")]
+use std::collections::{BTreeMap, BTreeSet};
+#[cfg(feature = "http")]
+use std::error;
+#[cfg(feature = "http")]
+use std::sync::Arc;
+#[cfg(feature = "http")]
+use std::time::Duration;
+
+#[cfg(feature = "http")]
+use crate::clock::{self, Clock};
+#[cfg(feature = "http")]
+use crate::RisResponseData;
+
+/// Returns a collector's short name, such as "rrc00", whether given as that or as "rrc00.ripe.net"
+///
+/// # Examples
+///
+/// ```
+/// assert_eq!(risclient::collectors::short_name("rrc21.ripe.net"), "rrc21");
+/// assert_eq!(risclient::collectors::short_name("rrc00"), "rrc00");
+/// ```
+pub fn short_name(host: &str) -> &str {
+    host.strip_suffix(".ripe.net").unwrap_or(host)
+}
+
+/// A RIS route collector
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Collector {
+    /// The collector's name as RIS Live reports it, such as "rrc00"
+    pub name: String,
+    /// Where the collector is, such as "Amsterdam, Netherlands"
+    pub location: String,
+    /// Whether the collector takes multihop sessions from anywhere rather than peering at one exchange
+    pub multihop: bool,
+    /// The date the collector started, as YYYY-MM-DD
+    pub activated_on: String,
+    /// The date the collector was retired, if it has been
+    pub deactivated_on: Option<String>,
+}
+
+impl Collector {
+    /// Returns true unless the collector has been retired
+    pub fn is_active(&self) -> bool {
+	self.deactivated_on.is_none()
+    }
+}
+
+/// A change to the set of active collectors
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CollectorChange {
+    /// A collector appeared in the registry or on the stream
+    Added(Collector),
+    /// A collector was retired, or dropped from the registry
+    Retired(Collector),
+}
+
+#[cfg(feature = "http")]
+#[derive(Deserialize)]
+struct RrcInfoResponse {
+    data: RrcInfoData,
+}
+
+#[cfg(feature = "http")]
+#[derive(Deserialize)]
+struct RrcInfoData {
+    rrcs: Vec<RrcInfoRecord>,
+}
+
+#[cfg(feature = "http")]
+#[derive(Deserialize)]
+struct RrcInfoRecord {
+    name: String,
+    #[serde(default)]
+    geographical_location: String,
+    #[serde(default)]
+    multihop: bool,
+    #[serde(default)]
+    activated_on: String,
+    #[serde(default)]
+    deactivated_on: String,
+}
+
+#[cfg(feature = "http")]
+///
+/// The active RIS collectors, refreshed from RIPEstat on request.
+/// Collectors seen on the stream but not yet in the registry are added too,
+/// as RIS Live can start sending a new collector's messages before RIPEstat lists it.
+///
+pub struct CollectorRegistry {
+    url: String,
+    collectors: BTreeMap<String, Collector>,
+    // collectors only known from the stream, which are kept until the registry lists them
+    observed: BTreeSet<String>,
+    http: reqwest::Client,
+    clock: Arc<dyn Clock>,
+}
+
+#[cfg(feature = "http")]
+impl CollectorRegistry {
+
+    /// Returns an empty CollectorRegistry using RIPEstat's rrc-info data call
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use risclient::collectors::CollectorRegistry;
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let mut registry = CollectorRegistry::new();
+    /// registry.refresh().await.unwrap();
+    /// for collector in registry.collectors() {
+    ///     println!("{} in {}", collector.name, collector.location);
+    /// }
+    /// # }
+    /// ```
+    pub fn new() -> CollectorRegistry {
+	CollectorRegistry {
+	    url: "https://stat.ripe.net/data/rrc-info/data.json".to_string(),
+	    collectors: BTreeMap::new(),
+	    observed: BTreeSet::new(),
+	    http: reqwest::Client::new(),
+	    clock: clock::system(),
+	}
+    }
+
+    /// Uses a different URL for the rrc-info data, such as a local mirror
+    pub fn with_url(mut self, url: String) -> CollectorRegistry {
+	self.url = url;
+	self
+    }
+
+    /// Waits between the refreshes of `changes` with `clock`
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> CollectorRegistry {
+	self.clock = clock;
+	self
+    }
+
+    /// Returns the active collectors, ordered by name
+    pub fn collectors(&self) -> impl Iterator<Item = &Collector> {
+	self.collectors.values()
+    }
+
+    /// Returns the active collector called `name`
+    pub fn get(&self, name: &str) -> Option<&Collector> {
+	self.collectors.get(short_name(name))
+    }
+
+    /// Fetches the registry again, returning the collectors added and retired since the last refresh
+    pub async fn refresh(&mut self) -> Result<Vec<CollectorChange>, Box<dyn error::Error>> {
+	let response = self.http.get(&self.url).send().await?.error_for_status()?;
+	let body = response.text().await?;
+	let parsed: RrcInfoResponse = serde_json::from_str(&body)?;
+	let mut fetched: BTreeMap<String, Collector> = parsed.data.rrcs.into_iter()
+	    .map(|record| Collector {
+		name: record.name.to_lowercase(),
+		location: record.geographical_location,
+		multihop: record.multihop,
+		activated_on: record.activated_on,
+		deactivated_on: Some(record.deactivated_on).filter(|date| !date.is_empty()),
+	    })
+	    .filter(Collector::is_active)
+	    .map(|collector| (collector.name.clone(), collector))
+	    .collect();
+	self.observed.retain(|name| !fetched.contains_key(name));
+	for name in &self.observed {
+	    if let Some(collector) = self.collectors.get(name) {
+		fetched.insert(name.clone(), collector.clone());
+	    }
+	}
+	let mut changes: Vec<CollectorChange> = self.collectors.iter()
+	    .filter(|(name, _)| !fetched.contains_key(*name))
+	    .map(|(_, collector)| CollectorChange::Retired(collector.clone()))
+	    .collect();
+	changes.extend(fetched.iter()
+	    .filter(|(name, _)| !self.collectors.contains_key(*name))
+	    .map(|(_, collector)| CollectorChange::Added(collector.clone())));
+	self.collectors = fetched;
+	Ok(changes)
+    }
+
+    /// Refreshes the registry every `interval` until collectors are added or retired, returning those changes,
+    /// so that a task watching an all collectors subscription can keep its set of collectors up to date
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use risclient::collectors::{CollectorChange, CollectorRegistry};
+    /// use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// // a stand in for RIPEstat, which lists rrc26 from its third answer on
+    /// let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    /// let url = format!("http://{}/rrc-info", listener.local_addr().unwrap());
+    /// tokio::spawn(async move {
+    ///     for answer in 0.. {
+    ///         let (mut stream, _) = listener.accept().await.unwrap();
+    ///         let _ = stream.read(&mut [0; 4096]).await.unwrap();
+    ///         let mut rrcs = vec![r#"{"name": "RRC00", "geographical_location": "Amsterdam, Netherlands"}"#];
+    ///         if answer >= 2 {
+    ///             rrcs.push(r#"{"name": "RRC26", "geographical_location": "Dubai, United Arab Emirates"}"#);
+    ///         }
+    ///         let body = format!(r#"{{"data": {{"rrcs": [{}]}}}}"#, rrcs.join(","));
+    ///         let response = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body);
+    ///         stream.write_all(response.as_bytes()).await.unwrap();
+    ///     }
+    /// });
+    /// let mut registry = CollectorRegistry::new().with_url(url);
+    /// registry.refresh().await.unwrap();
+    /// let changes = registry.changes(Duration::from_millis(10)).await.unwrap();
+    /// match changes.as_slice() {
+    ///     [CollectorChange::Added(collector)] => assert_eq!(collector.name, "rrc26"),
+    ///     changes => panic!("unexpected {:?}", changes),
+    /// }
+    /// assert_eq!(registry.collectors().count(), 2);
+    /// # }
+    /// ```
+    pub async fn changes(&mut self, interval: Duration) -> Result<Vec<CollectorChange>, Box<dyn error::Error>> {
+	loop {
+	    self.clock.sleep(interval).await;
+	    let changes = self.refresh().await?;
+	    if !changes.is_empty() {
+		return Ok(changes);
+	    }
+	}
+    }
+
+    /// Adds the collector a message came from if the registry does not list it yet
+    pub fn observe(&mut self, data: &RisResponseData) -> Option<CollectorChange> {
+	let name = short_name(data.host());
+	if name.is_empty() || name == "unknown" || self.collectors.contains_key(name) {
+	    return None;
+	}
+	let collector = Collector {
+	    name: name.to_string(),
+	    location: String::new(),
+	    multihop: false,
+	    activated_on: String::new(),
+	    deactivated_on: None,
+	};
+	self.observed.insert(collector.name.clone());
+	self.collectors.insert(collector.name.clone(), collector.clone());
+	Some(CollectorChange::Added(collector))
+    }
+}
+
+#[cfg(feature = "http")]
+impl Default for CollectorRegistry {
+    fn default() -> CollectorRegistry {
+	CollectorRegistry::new()
+    }
+}
@@ -0,0 +1,381 @@
+//! Well-known community signals
+//!
+//! Some communities carry an instruction rather than a policy tag: RFC 7999
+//! BLACKHOLE asks for traffic to a prefix to be dropped, RFC 8326
+//! GRACEFUL_SHUTDOWN announces that a session is about to be drained, and the
+//! RFC 1997 communities restrict where a route may propagate. `CommunityWatcher`
+//! follows these across the feed and reports when each one appears on a route
+//! and when it goes away again, with the `analytics` feature.
+//!
+//! `Communities` types the standard, RFC 8092 large and RFC 4360 extended
+//! communities of an UPDATE, decoding the latter two from the raw message.
+
+use std::collections::BTreeSet;
+#[cfg(feature = "analytics")]
+use std::collections::{BTreeMap, HashMap};
+use std::error;
+use std::fmt;
+use std::net::Ipv4Addr;
+
+use crate::attribute::PathAttribute;
+use crate::bgp;
+#[cfg(feature = "analytics")]
+use crate::rib::PeerKey;
+#[cfg(feature = "analytics")]
+use crate::AsPathEntry;
+use crate::RisResponseData;
+
+/// A community with a meaning defined by the IANA well-known communities registry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum WellKnownCommunity {
+    /// 65535:0, RFC 8326
+    GracefulShutdown,
+    /// 65535:1, RFC 7611
+    AcceptOwn,
+    /// 65535:666, RFC 7999
+    Blackhole,
+    /// 65535:65281, RFC 1997
+    NoExport,
+    /// 65535:65282, RFC 1997
+    NoAdvertise,
+    /// 65535:65283, RFC 1997
+    NoExportSubconfed,
+    /// 65535:65284, RFC 3765
+    NoPeer,
+}
+
+impl WellKnownCommunity {
+    /// Returns the well-known community `community` represents, if any
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use risclient::community::WellKnownCommunity;
+    /// assert_eq!(WellKnownCommunity::from_community((65535, 666)), Some(WellKnownCommunity::Blackhole));
+    /// assert_eq!(WellKnownCommunity::from_community((3333, 666)), None);
+    /// ```
+    pub fn from_community(community: (u32, u32)) -> Option<WellKnownCommunity> {
+	match community {
+	    (65535, 0) => Some(WellKnownCommunity::GracefulShutdown),
+	    (65535, 1) => Some(WellKnownCommunity::AcceptOwn),
+	    (65535, 666) => Some(WellKnownCommunity::Blackhole),
+	    (65535, 65281) => Some(WellKnownCommunity::NoExport),
+	    (65535, 65282) => Some(WellKnownCommunity::NoAdvertise),
+	    (65535, 65283) => Some(WellKnownCommunity::NoExportSubconfed),
+	    (65535, 65284) => Some(WellKnownCommunity::NoPeer),
+	    _ => None,
+	}
+    }
+
+    /// Returns the community as it appears in RIS messages
+    pub fn community(&self) -> (u32, u32) {
+	match self {
+	    WellKnownCommunity::GracefulShutdown => (65535, 0),
+	    WellKnownCommunity::AcceptOwn => (65535, 1),
+	    WellKnownCommunity::Blackhole => (65535, 666),
+	    WellKnownCommunity::NoExport => (65535, 65281),
+	    WellKnownCommunity::NoAdvertise => (65535, 65282),
+	    WellKnownCommunity::NoExportSubconfed => (65535, 65283),
+	    WellKnownCommunity::NoPeer => (65535, 65284),
+	}
+    }
+
+    /// Returns every well-known community present in a message
+    pub fn in_message(data: &RisResponseData) -> BTreeSet<WellKnownCommunity> {
+	data.community().iter().filter_map(|community| WellKnownCommunity::from_community(*community)).collect()
+    }
+}
+
+impl fmt::Display for WellKnownCommunity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+	let name = match self {
+	    WellKnownCommunity::GracefulShutdown => "GRACEFUL_SHUTDOWN",
+	    WellKnownCommunity::AcceptOwn => "ACCEPT_OWN",
+	    WellKnownCommunity::Blackhole => "BLACKHOLE",
+	    WellKnownCommunity::NoExport => "NO_EXPORT",
+	    WellKnownCommunity::NoAdvertise => "NO_ADVERTISE",
+	    WellKnownCommunity::NoExportSubconfed => "NO_EXPORT_SUBCONFED",
+	    WellKnownCommunity::NoPeer => "NO_PEER",
+	};
+	write!(f, "{}", name)
+    }
+}
+
+/// A BGP large community, RFC 8092
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct LargeCommunity {
+    /// The ASN of the network that defined the community
+    pub global: u32,
+    pub local1: u32,
+    pub local2: u32,
+}
+
+impl fmt::Display for LargeCommunity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+	write!(f, "{}:{}:{}", self.global, self.local1, self.local2)
+    }
+}
+
+/// The global administrator of a route target or route origin, RFC 4360 and RFC 5668
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Administrator {
+    Asn(u32),
+    Ipv4(Ipv4Addr),
+}
+
+impl fmt::Display for Administrator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+	match self {
+	    Administrator::Asn(asn) => write!(f, "{}", asn),
+	    Administrator::Ipv4(address) => write!(f, "{}", address),
+	}
+    }
+}
+
+/// A BGP extended community, RFC 4360
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExtendedCommunity {
+    /// Route target, identifying the routing instances a route is for
+    RouteTarget { administrator: Administrator, value: u32 },
+    /// Route origin, identifying where a route came from
+    RouteOrigin { administrator: Administrator, value: u32 },
+    /// Link bandwidth, in bytes per second, of the link to the neighbour `asn`
+    LinkBandwidth { asn: u16, bandwidth: f32 },
+    /// Any other extended community, left undecoded
+    Opaque([u8; 8]),
+}
+
+impl ExtendedCommunity {
+    /// Decodes an extended community from its eight octets on the wire
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use risclient::community::{Administrator, ExtendedCommunity};
+    /// let community = ExtendedCommunity::from_bytes([0x00, 0x02, 0xfb, 0xf4, 0, 0, 0, 100]);
+    /// assert_eq!(community, ExtendedCommunity::RouteTarget { administrator: Administrator::Asn(64500), value: 100 });
+    /// assert_eq!(community.to_string(), "rt:64500:100");
+    /// ```
+    pub fn from_bytes(bytes: [u8; 8]) -> ExtendedCommunity {
+	let two_octets = |at: usize| u16::from_be_bytes([bytes[at], bytes[at + 1]]);
+	let four_octets = |at: usize| u32::from_be_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]]);
+	// the high type octet sets the administrator's format, and whether the community is transitive
+	let (administrator, value) = match bytes[0] & !0x40 {
+	    0x00 => (Administrator::Asn(two_octets(2) as u32), four_octets(4)),
+	    0x01 => (Administrator::Ipv4(Ipv4Addr::from(four_octets(2))), two_octets(6) as u32),
+	    0x02 => (Administrator::Asn(four_octets(2)), two_octets(6) as u32),
+	    _ => return ExtendedCommunity::Opaque(bytes),
+	};
+	match (bytes[0], bytes[1]) {
+	    (0x00..=0x02, 0x02) => ExtendedCommunity::RouteTarget { administrator, value },
+	    (0x00..=0x02, 0x03) => ExtendedCommunity::RouteOrigin { administrator, value },
+	    (0x00 | 0x40, 0x04) => ExtendedCommunity::LinkBandwidth { asn: two_octets(2), bandwidth: f32::from_bits(four_octets(4)) },
+	    _ => ExtendedCommunity::Opaque(bytes),
+	}
+    }
+}
+
+impl fmt::Display for ExtendedCommunity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+	match self {
+	    ExtendedCommunity::RouteTarget { administrator, value } => write!(f, "rt:{}:{}", administrator, value),
+	    ExtendedCommunity::RouteOrigin { administrator, value } => write!(f, "ro:{}:{}", administrator, value),
+	    ExtendedCommunity::LinkBandwidth { asn, bandwidth } => write!(f, "lb:{}:{}", asn, bandwidth),
+	    ExtendedCommunity::Opaque(bytes) => {
+		write!(f, "0x")?;
+		bytes.iter().try_for_each(|byte| write!(f, "{:02x}", byte))
+	    },
+	}
+    }
+}
+
+/// The communities of every kind attached to an UPDATE
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Communities {
+    /// RFC 1997 communities, as (ASN, value)
+    pub standard: Vec<(u16, u16)>,
+    pub large: Vec<LargeCommunity>,
+    pub extended: Vec<ExtendedCommunity>,
+}
+
+impl Communities {
+    /// Returns the communities of a message. RIS Live's JSON only carries standard communities,
+    /// so large and extended ones are found only when the subscription included raw messages.
+    /// Standard communities wider than 16 bits are not valid, so are left out.
+    pub fn of(data: &RisResponseData) -> Communities {
+	if let Some(communities) = bgp::raw_message(data).and_then(|raw| Communities::from_update(&raw).ok()) {
+	    return communities;
+	}
+	Communities {
+	    standard: data.community().iter()
+		.filter_map(|(asn, value)| Some((u16::try_from(*asn).ok()?, u16::try_from(*value).ok()?)))
+		.collect(),
+	    ..Communities::default()
+	}
+    }
+
+    /// Decodes the communities of a BGP UPDATE message, including its header
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use risclient::bgp::{self, MESSAGE_UPDATE};
+    /// use risclient::community::{Communities, LargeCommunity};
+    /// // no withdrawals, then a LARGE_COMMUNITIES attribute of 64500:1:2
+    /// let mut body = vec![0, 0, 0, 15, 0xc0, 32, 12];
+    /// body.extend([64500u32, 1, 2].iter().flat_map(|part| part.to_be_bytes()));
+    /// let communities = Communities::from_update(&bgp::message(MESSAGE_UPDATE, &body)).unwrap();
+    /// assert_eq!(communities.large, vec![LargeCommunity { global: 64500, local1: 1, local2: 2 }]);
+    /// ```
+    pub fn from_update(message: &[u8]) -> Result<Communities, Box<dyn error::Error + Send + Sync>> {
+	let mut communities = Communities::default();
+	for attribute in PathAttribute::decode_update(message, true)? {
+	    match attribute {
+		PathAttribute::Communities(standard) => communities.standard.extend(standard),
+		PathAttribute::LargeCommunities(large) => communities.large.extend(large),
+		PathAttribute::ExtendedCommunities(extended) => communities.extended.extend(extended),
+		_ => {},
+	    }
+	}
+	Ok(communities)
+    }
+
+    /// Returns true if there are no communities of any kind
+    pub fn is_empty(&self) -> bool {
+	self.standard.is_empty() && self.large.is_empty() && self.extended.is_empty()
+    }
+}
+
+#[cfg(feature = "analytics")]
+/// Whether a signal has just appeared on a route or has just gone away
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignalState {
+    /// The route was announced with the community
+    Raised,
+    /// The route was withdrawn, re-announced without the community, or its session went down
+    Cleared,
+}
+
+#[cfg(feature = "analytics")]
+/// A change to the well-known communities on one peer's route for one prefix
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommunityEvent {
+    pub community: WellKnownCommunity,
+    pub state: SignalState,
+    pub collector: String,
+    pub peer: String,
+    pub peer_asn: String,
+    pub prefix: String,
+    /// The AS path of the announcement raising the signal, empty when it is cleared
+    pub path: Vec<AsPathEntry>,
+    pub timestamp: f64,
+    /// The labels of the subscription the message came from
+    pub labels: BTreeMap<String, String>,
+}
+
+#[cfg(feature = "analytics")]
+///
+/// Tracks well-known communities per peer and prefix, reporting when they are raised and cleared.
+/// Only routes currently carrying a watched community are remembered.
+///
+#[derive(Debug, Clone)]
+pub struct CommunityWatcher {
+    watched: BTreeSet<WellKnownCommunity>,
+    active: HashMap<PeerKey, HashMap<String, BTreeSet<WellKnownCommunity>>>,
+}
+
+#[cfg(feature = "analytics")]
+impl CommunityWatcher {
+
+    /// Returns a CommunityWatcher for BLACKHOLE, GRACEFUL_SHUTDOWN and NO_EXPORT
+    pub fn new() -> CommunityWatcher {
+	CommunityWatcher::watching(&[WellKnownCommunity::Blackhole, WellKnownCommunity::GracefulShutdown, WellKnownCommunity::NoExport])
+    }
+
+    /// Returns a CommunityWatcher for the given communities
+    pub fn watching(communities: &[WellKnownCommunity]) -> CommunityWatcher {
+	CommunityWatcher {
+	    watched: communities.iter().copied().collect(),
+	    active: HashMap::new(),
+	}
+    }
+
+    /// Returns the prefixes each session currently has `community` raised on
+    pub fn active(&self, community: WellKnownCommunity) -> Vec<(&PeerKey, &str)> {
+	self.active.iter()
+	    .flat_map(|(key, prefixes)| prefixes.iter().filter(|(_, raised)| raised.contains(&community)).map(move |(prefix, _)| (key, prefix.as_str())))
+	    .collect()
+    }
+
+    fn event(data: &RisResponseData, community: WellKnownCommunity, state: SignalState, prefix: &str) -> CommunityEvent {
+	CommunityEvent {
+	    community,
+	    state,
+	    collector: data.host().to_string(),
+	    peer: data.peer().to_string(),
+	    peer_asn: data.peer_asn().to_string(),
+	    prefix: prefix.to_string(),
+	    path: if state == SignalState::Raised { data.path().to_vec() } else { Vec::new() },
+	    timestamp: data.timestamp(),
+	    labels: data.labels().clone(),
+	}
+    }
+
+    /// Updates the watcher with a message, returning the signals it raised or cleared
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use risclient::RisResponse;
+    /// use risclient::community::{CommunityWatcher, SignalState, WellKnownCommunity};
+    /// let message: RisResponse = serde_json::from_str(r#"{"type": "ris_message", "data": {"timestamp": 1650000000.25,
+    ///     "peer": "192.0.2.1", "peer_asn": "64500", "id": "1", "host": "rrc00", "type": "UPDATE", "path": [64500, 64501],
+    ///     "community": [[65535, 666]], "announcements": [{"next_hop": "192.0.2.1", "prefixes": ["198.51.100.1/32"]}]}}"#).unwrap();
+    /// let mut watcher = CommunityWatcher::new();
+    /// let events = watcher.observe(message.data());
+    /// assert_eq!(events[0].community, WellKnownCommunity::Blackhole);
+    /// assert_eq!(events[0].state, SignalState::Raised);
+    /// ```
+    pub fn observe(&mut self, data: &RisResponseData) -> Vec<CommunityEvent> {
+	let mut events = Vec::new();
+	match data.data_type() {
+	    "UPDATE" => {},
+	    "RIS_PEER_STATE" if data.state() == Some("down") => {
+		if let Some(prefixes) = self.active.remove(&PeerKey::of(data)) {
+		    for (prefix, raised) in prefixes {
+			events.extend(raised.into_iter().map(|community| CommunityWatcher::event(data, community, SignalState::Cleared, &prefix)));
+		    }
+		}
+		return events;
+	    },
+	    _ => return events,
+	}
+	let key = PeerKey::of(data);
+	let current: BTreeSet<WellKnownCommunity> = WellKnownCommunity::in_message(data).intersection(&self.watched).copied().collect();
+	let table = self.active.entry(key.clone()).or_default();
+	for prefix in data.withdrawals() {
+	    if let Some(raised) = table.remove(prefix) {
+		events.extend(raised.into_iter().map(|community| CommunityWatcher::event(data, community, SignalState::Cleared, prefix)));
+	    }
+	}
+	for prefix in data.announcements().iter().flat_map(|announcement| announcement.prefixes()) {
+	    let previous = table.remove(prefix).unwrap_or_default();
+	    events.extend(previous.difference(&current).map(|community| CommunityWatcher::event(data, *community, SignalState::Cleared, prefix)));
+	    events.extend(current.difference(&previous).map(|community| CommunityWatcher::event(data, *community, SignalState::Raised, prefix)));
+	    if !current.is_empty() {
+		table.insert(prefix.clone(), current.clone());
+	    }
+	}
+	if table.is_empty() {
+	    self.active.remove(&key);
+	}
+	events
+    }
+}
+
+#[cfg(feature = "analytics")]
+impl Default for CommunityWatcher {
+    fn default() -> CommunityWatcher {
+	CommunityWatcher::new()
+    }
+}
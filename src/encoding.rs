@@ -0,0 +1,59 @@
+//! Compact binary encodings of messages
+//!
+//! Forwarding messages over an internal bus as JSON wastes space and time, so
+//! with the `cbor` or `msgpack` features messages can be encoded as CBOR or
+//! MessagePack instead. Both encode the same shape as the RIS Live JSON, so a
+//! decoded message is the same as the one encoded.
+
+use std::error;
+
+use crate::RisResponse;
+
+/// Encodes a message as CBOR
+///
+/// # Examples
+///
+/// ```
+/// use risclient::RisResponse;
+/// use risclient::encoding;
+/// let message: RisResponse = serde_json::from_str(r#"{"type": "ris_message", "data": {"timestamp": 1650000000.25,
+///     "peer": "192.0.2.1", "peer_asn": "64500", "id": "1", "host": "rrc00", "type": "UPDATE", "path": [64500, [64501, 64502]],
+///     "announcements": [{"next_hop": "192.0.2.1", "prefixes": ["198.51.100.0/24"]}]}}"#).unwrap();
+/// let decoded = encoding::from_cbor(&encoding::to_cbor(&message).unwrap()).unwrap();
+/// assert_eq!(serde_json::to_value(&decoded).unwrap(), serde_json::to_value(&message).unwrap());
+/// ```
+#[cfg(feature = "cbor")]
+pub fn to_cbor(message: &RisResponse) -> Result<Vec<u8>, Box<dyn error::Error + Send + Sync>> {
+    let mut encoded = Vec::new();
+    ciborium::into_writer(message, &mut encoded)?;
+    Ok(encoded)
+}
+
+/// Decodes a message encoded with `to_cbor`
+#[cfg(feature = "cbor")]
+pub fn from_cbor(encoded: &[u8]) -> Result<RisResponse, Box<dyn error::Error + Send + Sync>> {
+    Ok(ciborium::from_reader(encoded)?)
+}
+
+/// Encodes a message as MessagePack, with field names so that it stays self describing
+///
+/// # Examples
+///
+/// ```
+/// use risclient::RisResponse;
+/// use risclient::encoding;
+/// let message: RisResponse = serde_json::from_str(r#"{"type": "ris_message", "data": {"timestamp": 1650000000.0,
+///     "peer": "192.0.2.1", "peer_asn": "64500", "id": "2", "host": "rrc00", "type": "OPEN", "hold_time": 180}}"#).unwrap();
+/// let decoded = encoding::from_msgpack(&encoding::to_msgpack(&message).unwrap()).unwrap();
+/// assert_eq!(serde_json::to_value(&decoded).unwrap(), serde_json::to_value(&message).unwrap());
+/// ```
+#[cfg(feature = "msgpack")]
+pub fn to_msgpack(message: &RisResponse) -> Result<Vec<u8>, Box<dyn error::Error + Send + Sync>> {
+    Ok(rmp_serde::to_vec_named(message)?)
+}
+
+/// Decodes a message encoded with `to_msgpack`
+#[cfg(feature = "msgpack")]
+pub fn from_msgpack(encoded: &[u8]) -> Result<RisResponse, Box<dyn error::Error + Send + Sync>> {
+    Ok(rmp_serde::from_slice(encoded)?)
+}
@@ -0,0 +1,80 @@
+//! Errors from RIS Live streams
+
+use std::error;
+use std::fmt;
+use std::time::Duration;
+
+/// Something that went wrong receiving from RIS Live
+#[derive(Debug)]
+pub enum RisError {
+    /// A subscription filter is malformed, so nothing was sent
+    InvalidFilter {
+	filter: &'static str,
+	reason: String,
+    },
+    /// The websocket connection failed. The stream ends after this.
+    Connection(Box<tungstenite::Error>),
+    /// A message could not be decoded. The stream carries on with the next one.
+    Decode {
+	error: serde_json::Error,
+	message: String,
+    },
+    /// A message decoded but departs from the schema, reported with `ParseMode::Strict`.
+    /// The stream carries on with the next one.
+    Schema {
+	reason: String,
+	message: String,
+    },
+    /// RIS Live sent a `ris_error` message, usually because it did not accept the subscription
+    Server(String),
+    /// RIS Live neither acknowledged nor rejected the subscription within this long
+    Unacknowledged(Duration),
+    /// The stream has ended
+    Closed,
+    /// Nothing arrived within the time allowed
+    Timeout,
+    /// Nothing has arrived yet
+    Empty,
+    /// Nothing arrived for this long on a stream from `RisReceiver::idle_timeout`. The stream ends after this.
+    Idle(Duration),
+    /// The client's cancellation token was cancelled, so nothing more will be sent
+    Cancelled,
+}
+
+impl RisError {
+    /// Returns the frame that could not be decoded, for `Decode` and `Schema` errors
+    pub fn frame(&self) -> Option<&str> {
+	match self {
+	    RisError::Decode { message, .. } | RisError::Schema { message, .. } => Some(message),
+	    _ => None,
+	}
+    }
+}
+
+impl fmt::Display for RisError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+	match self {
+	    RisError::InvalidFilter { filter, reason } => write!(f, "invalid {} filter: {}", filter, reason),
+	    RisError::Connection(e) => write!(f, "connection failed: {}", e),
+	    RisError::Decode { error, message } => write!(f, "failed decoding message: {}, '{}'", error, message),
+	    RisError::Schema { reason, message } => write!(f, "message does not match the schema: {}, '{}'", reason, message),
+	    RisError::Server(message) => write!(f, "RIS Live error: {}", message),
+	    RisError::Unacknowledged(timeout) => write!(f, "subscription not acknowledged within {:?}", timeout),
+	    RisError::Closed => write!(f, "stream closed"),
+	    RisError::Timeout => write!(f, "timed out waiting for a message"),
+	    RisError::Empty => write!(f, "no message available"),
+	    RisError::Idle(idle) => write!(f, "no message for {:?}", idle),
+	    RisError::Cancelled => write!(f, "cancelled"),
+	}
+    }
+}
+
+impl error::Error for RisError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+	match self {
+	    RisError::Connection(e) => Some(e.as_ref()),
+	    RisError::Decode { error, .. } => Some(error),
+	    _ => None,
+	}
+    }
+}
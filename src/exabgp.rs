@@ -0,0 +1,163 @@
+//! exabgp compatible JSON output
+//!
+//! Re-serialises RIS messages using the JSON schema exabgp 4 writes to its API
+//! processes, one object per line, so existing scripts written against an
+//! exabgp pipe can consume RIS Live unchanged. Each RIS peer appears as an
+//! exabgp neighbor, and the collector name is used as the exabgp host.
+
+use std::collections::BTreeMap;
+
+use ipnet::IpNet;
+use serde_json::{json, Map, Value};
+
+use crate::{RisResponse, RisResponseData};
+
+/// The exabgp release whose schema is produced
+const EXABGP_VERSION: &str = "4.0.1";
+
+/// The ASN RIS collectors peer from, reported as the local ASN of each neighbor
+const RIS_ASN: u32 = 12654;
+
+fn family(prefix: &str) -> &'static str {
+    match prefix.parse::<IpNet>() {
+	Ok(IpNet::V6(_)) => "ipv6 unicast",
+	_ => "ipv4 unicast",
+    }
+}
+
+///
+/// Converts RIS messages to exabgp JSON.
+/// exabgp numbers messages with a per-process counter, which this mirrors.
+///
+#[derive(Debug, Clone)]
+pub struct ExabgpEncoder {
+    counter: u64,
+    pid: u32,
+}
+
+impl ExabgpEncoder {
+
+    /// Returns an ExabgpEncoder with its message counter starting at 1
+    pub fn new() -> ExabgpEncoder {
+	ExabgpEncoder {
+	    counter: 0,
+	    pid: std::process::id(),
+	}
+    }
+
+    fn neighbor(&self, data: &RisResponseData) -> Map<String, Value> {
+	let mut neighbor = Map::new();
+	neighbor.insert("address".to_string(), json!({ "local": "0.0.0.0", "peer": data.peer() }));
+	let peer_asn = data.peer_asn().parse::<u32>().map(Value::from).unwrap_or_else(|_| Value::from(data.peer_asn()));
+	neighbor.insert("asn".to_string(), json!({ "local": RIS_ASN, "peer": peer_asn }));
+	neighbor
+    }
+
+    fn attributes(&self, data: &RisResponseData) -> Value {
+	let mut attributes = Map::new();
+	if let Some(origin) = data.origin() {
+	    attributes.insert("origin".to_string(), Value::from(origin.to_lowercase()));
+	}
+	attributes.insert("as-path".to_string(), json!(data.path()));
+	attributes.insert("confederation-path".to_string(), json!([]));
+	if let Some(med) = data.med() {
+	    attributes.insert("med".to_string(), Value::from(med));
+	}
+	if let Some(aggregator) = data.aggregator() {
+	    attributes.insert("aggregator".to_string(), Value::from(aggregator));
+	}
+	if !data.community().is_empty() {
+	    attributes.insert("community".to_string(), json!(data.community()));
+	}
+	Value::Object(attributes)
+    }
+
+    fn update(&self, data: &RisResponseData) -> Value {
+	let mut update = Map::new();
+	if !data.announcements().is_empty() {
+	    update.insert("attribute".to_string(), self.attributes(data));
+	    // family -> next hop -> nlri
+	    let mut announce: BTreeMap<&str, BTreeMap<&str, Vec<Value>>> = BTreeMap::new();
+	    for announcement in data.announcements() {
+		let next_hop = announcement.next_hop().split(',').next().unwrap_or("");
+		for prefix in announcement.prefixes() {
+		    announce.entry(family(prefix)).or_default().entry(next_hop).or_default().push(json!({ "nlri": prefix }));
+		}
+	    }
+	    update.insert("announce".to_string(), json!(announce));
+	}
+	if !data.withdrawals().is_empty() {
+	    let mut withdraw: BTreeMap<&str, Vec<Value>> = BTreeMap::new();
+	    for prefix in data.withdrawals() {
+		withdraw.entry(family(prefix)).or_default().push(json!({ "nlri": prefix }));
+	    }
+	    update.insert("withdraw".to_string(), json!(withdraw));
+	}
+	Value::Object(update)
+    }
+
+    /// Converts a RIS message to an exabgp JSON object.
+    /// Returns `None` for messages exabgp has no equivalent for, such as OPENs and NOTIFICATIONs.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use risclient::RisResponse;
+    /// use risclient::exabgp::ExabgpEncoder;
+    /// let message: RisResponse = serde_json::from_str(r#"{"type": "ris_message", "data": {"timestamp": 1650000000.25,
+    ///     "peer": "192.0.2.1", "peer_asn": "64500", "id": "1", "host": "rrc00", "type": "UPDATE", "path": [64500, 64501],
+    ///     "origin": "IGP", "announcements": [{"next_hop": "192.0.2.1", "prefixes": ["198.51.100.0/24"]}]}}"#).unwrap();
+    /// let mut encoder = ExabgpEncoder::new();
+    /// let update = encoder.encode(&message).unwrap();
+    /// assert_eq!(update["type"], "update");
+    /// assert_eq!(update["neighbor"]["message"]["update"]["announce"]["ipv4 unicast"]["192.0.2.1"][0]["nlri"], "198.51.100.0/24");
+    /// ```
+    pub fn encode(&mut self, message: &RisResponse) -> Option<Value> {
+	let data = message.data();
+	let mut neighbor = self.neighbor(data);
+	let message_type = match (data.data_type(), data.state()) {
+	    ("UPDATE", _) => {
+		neighbor.insert("direction".to_string(), Value::from("receive"));
+		neighbor.insert("message".to_string(), json!({ "update": self.update(data) }));
+		"update"
+	    },
+	    ("KEEPALIVE", _) => {
+		neighbor.insert("direction".to_string(), Value::from("receive"));
+		"keepalive"
+	    },
+	    ("RIS_PEER_STATE", Some(state)) => {
+		let state = if state == "connected" { "up" } else { "down" };
+		neighbor.insert("state".to_string(), Value::from(state));
+		"state"
+	    },
+	    _ => return None,
+	};
+	self.counter += 1;
+	let mut encoded = json!({
+	    "exabgp": EXABGP_VERSION,
+	    "time": data.timestamp(),
+	    "host": data.host(),
+	    "pid": self.pid,
+	    "ppid": 1,
+	    "counter": self.counter,
+	    "type": message_type,
+	    "neighbor": Value::Object(neighbor),
+	});
+	// not part of exabgp's format, but lets whatever reads the pipe ingest each message exactly once
+	if let Some(sequence) = data.sequence() {
+	    encoded["sequence"] = json!(sequence);
+	}
+	Some(encoded)
+    }
+
+    /// Converts a RIS message to a single line of exabgp JSON, ready to be written to a pipe
+    pub fn encode_line(&mut self, message: &RisResponse) -> Option<String> {
+	self.encode(message).map(|value| value.to_string())
+    }
+}
+
+impl Default for ExabgpEncoder {
+    fn default() -> ExabgpEncoder {
+	ExabgpEncoder::new()
+    }
+}
@@ -0,0 +1,214 @@
+//! Full routing tables assembled from RIS archives and the live feed
+//!
+//! RIS Live only sends changes, so a table built from it alone is missing every
+//! route announced before the connection was made. `FullTable` fills that gap:
+//! it subscribes to the live feed first so nothing is missed, then loads the
+//! latest bview for each collector and the updates archived since, and finally
+//! hands over to the buffered live messages once the archives have caught up.
+
+use std::collections::HashMap;
+use std::error;
+use std::fs::File;
+use std::io::{self, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+use std::time::Duration;
+
+use flate2::read::GzDecoder;
+use ipnet::IpNet;
+
+use crate::mrt::MrtReader;
+use crate::rib::{PeerKey, Rib, Route};
+use crate::{collectors, RisClient, RisReceiver, Subscription};
+
+/// How often RIS writes an updates file, in seconds
+const UPDATES_INTERVAL: u64 = 300;
+
+/// How long to wait for an updates file to be published before treating it as missing from the archive
+const UPDATES_GRACE: u64 = 1800;
+
+/// The last temporary download number handed out, which keeps concurrent downloads apart
+static LAST_DOWNLOAD: AtomicU64 = AtomicU64::new(0);
+
+/// Converts a unix timestamp to (year, month, day, hour, minute) in UTC
+fn civil(timestamp: u64) -> (i64, u32, u32, u32, u32) {
+    let days = (timestamp / 86400) as i64;
+    let seconds = timestamp % 86400;
+    // Howard Hinnant's days_from_civil, in reverse
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day, (seconds / 3600) as u32, (seconds % 3600 / 60) as u32)
+}
+
+fn updates_url(archive: &str, collector: &str, timestamp: u64) -> String {
+    let (year, month, day, hour, minute) = civil(timestamp);
+    format!("{}/{}/{:04}.{:02}/updates.{:04}{:02}{:02}.{:02}{:02}.gz", archive, collector, year, month, year, month, day, hour, minute)
+}
+
+/// Downloads `url` to a temporary file, returning `None` if the archive does not have it
+async fn download(url: &str, collector: &str) -> Result<Option<PathBuf>, Box<dyn error::Error + Send + Sync>> {
+    let response = reqwest::get(url).await?;
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+	return Ok(None);
+    }
+    let mut response = response.error_for_status()?;
+    let download = LAST_DOWNLOAD.fetch_add(1, Ordering::Relaxed) + 1;
+    let path = std::env::temp_dir().join(format!("risclient-{}-{}-{}.gz", collector, std::process::id(), download));
+    let mut file = File::create(&path)?;
+    while let Some(chunk) = response.chunk().await? {
+	file.write_all(&chunk)?;
+    }
+    Ok(Some(path))
+}
+
+/// Applies every message in a gzipped MRT file, returning the header time of its first record
+fn load(path: &Path, collector: &str, rib: &RwLock<Rib>) -> Result<Option<u32>, Box<dyn error::Error + Send + Sync>> {
+    let file = BufReader::new(File::open(path)?);
+    let mut reader = MrtReader::new(GzDecoder::new(file), collector.to_string());
+    let mut first = None;
+    let mut rib = rib.write().unwrap_or_else(|e| e.into_inner());
+    while let Some(data) = reader.next() {
+	let data = data?;
+	first = first.or(reader.record_time());
+	rib.apply(&data);
+    }
+    Ok(first.or(reader.record_time()))
+}
+
+async fn load_url(url: &str, collector: &str, rib: &Arc<RwLock<Rib>>) -> Result<Option<Option<u32>>, Box<dyn error::Error + Send + Sync>> {
+    let path = match download(url, collector).await? {
+	Some(path) => path,
+	None => return Ok(None),
+    };
+    let loader_rib = rib.clone();
+    let loader_path = path.clone();
+    let collector = collector.to_string();
+    let result = tokio::task::spawn_blocking(move || load(&loader_path, &collector, &loader_rib)).await;
+    let _ = std::fs::remove_file(&path);
+    match result {
+	Ok(first) => Ok(Some(first?)),
+	Err(e) => Err(Box::new(e)),
+    }
+}
+
+///
+/// A continuously updated table of every route seen by a set of RIS collectors.
+/// Once built, the table is kept current from the live feed in the background.
+///
+pub struct FullTable {
+    rib: Arc<RwLock<Rib>>,
+    consistent: bool,
+}
+
+impl FullTable {
+
+    /// Builds a FullTable for `collectors` using the public RIS archive at <https://data.ris.ripe.net>.
+    /// This downloads a full bview per collector and waits for the archive to catch up with the live
+    /// feed, so expect it to take several minutes.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use risclient::RisClient;
+    /// use risclient::fulltable::FullTable;
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let mut client = RisClient::default().unwrap();
+    /// let table = FullTable::build(&mut client, &["rrc00"]).await.unwrap();
+    /// for (peer, route) in table.routes_for(&"193.0.0.0/21".parse().unwrap()) {
+    ///     println!("{} via {}: {:?}", peer.collector, peer.peer, route.path);
+    /// }
+    /// # }
+    /// ```
+    pub async fn build(client: &mut RisClient, collectors: &[&str]) -> Result<FullTable, Box<dyn error::Error>> {
+	FullTable::build_from(client, collectors, "https://data.ris.ripe.net").await
+    }
+
+    /// Builds a FullTable for `collectors`, fetching archives from a RIS mirror at `archive`.
+    /// Waiting for the archive to catch up is timed with the client's clock.
+    pub async fn build_from(client: &mut RisClient, collectors: &[&str], archive: &str) -> Result<FullTable, Box<dyn error::Error>> {
+	let clock = client.clock.clone();
+	let now = || clock.epoch_seconds() as u64;
+	let subscription = Subscription::new().hosts(collectors);
+	// subscribe before touching the archive, so the live feed overlaps whatever the archive has
+	let rx = client.subscribe(&subscription).await?;
+	let live_start = now();
+	let rib = Arc::new(RwLock::new(Rib::new()));
+	let mut consistent = true;
+	let mut handover = HashMap::new();
+	for collector in collectors {
+	    let url = format!("{}/{}/latest-bview.gz", archive, collector);
+	    let dumped = match load_url(&url, collector, &rib).await {
+		Ok(Some(Some(dumped))) => dumped as u64,
+		Ok(_) => return Err(Box::new(io::Error::new(io::ErrorKind::NotFound, format!("no bview found for {}", collector)))),
+		Err(e) => return Err(e),
+	    };
+	    let mut next = dumped - dumped % UPDATES_INTERVAL;
+	    // keep applying updates until the archive covers the moment the live feed started
+	    while next <= live_start {
+		let url = updates_url(archive, collector, next);
+		match load_url(&url, collector, &rib).await {
+		    Ok(Some(_)) => next += UPDATES_INTERVAL,
+		    Ok(None) if now() > next + UPDATES_INTERVAL + UPDATES_GRACE => {
+			consistent = false;
+			next += UPDATES_INTERVAL;
+		    },
+		    Ok(None) => clock.sleep(Duration::from_secs(60)).await,
+		    Err(e) => return Err(e),
+		}
+	    }
+	    handover.insert(collectors::short_name(collector).to_string(), next as f64);
+	}
+	let live_rib = rib.clone();
+	std::thread::spawn(move || FullTable::follow(rx, live_rib, handover));
+	Ok(FullTable { rib, consistent })
+    }
+
+    /// Applies live messages for the collectors in `handover`, skipping anything the archives already covered
+    fn follow(rx: RisReceiver, rib: Arc<RwLock<Rib>>, handover: HashMap<String, f64>) {
+	for received in rx {
+	    let Ok(message) = received else { continue };
+	    let data = message.data();
+	    match handover.get(collectors::short_name(data.host())) {
+		Some(start) if data.timestamp() >= *start => {
+		    let mut rib = rib.write().unwrap_or_else(|e| e.into_inner());
+		    rib.apply(data);
+		},
+		_ => continue,
+	    }
+	}
+    }
+
+    /// Returns false if an updates file was missing from the archive, in which case some routes may be stale
+    pub fn is_consistent(&self) -> bool {
+	self.consistent
+    }
+
+    /// Returns read access to the underlying table. Live updates wait while the guard is held.
+    pub fn rib(&self) -> RwLockReadGuard<'_, Rib> {
+	self.rib.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Returns the route each peer currently has for exactly `prefix`
+    pub fn routes_for(&self, prefix: &IpNet) -> Vec<(PeerKey, Route)> {
+	self.rib().routes_for(prefix).into_iter().map(|(key, route)| (key.clone(), route.clone())).collect()
+    }
+
+    /// Returns the total number of routes in the table
+    pub fn len(&self) -> usize {
+	self.rib().len()
+    }
+
+    /// Returns true if the table holds no routes
+    pub fn is_empty(&self) -> bool {
+	self.rib().is_empty()
+    }
+}
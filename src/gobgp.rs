@@ -0,0 +1,436 @@
+//! Route injection into GoBGP
+//!
+//! Pushes selected routes learned from RIS into the global RIB of a local GoBGP
+//! instance over its gRPC API, so lab routers peering with GoBGP see real world
+//! routes. Only the handful of API messages needed are modelled here, so no
+//! protobuf toolchain is required to build this module.
+//!
+//! This module requires the `gobgp` feature.
+
+use std::collections::HashMap;
+use std::error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use ipnet::IpNet;
+use prost::Message;
+use prost_types::Any;
+use tonic::codegen::http::uri::PathAndQuery;
+use tonic::transport::Channel;
+
+use crate::{AsPathEntry, RisResponse, RisResponseData};
+
+const TABLE_TYPE_GLOBAL: i32 = 0;
+const AFI_IP: i32 = 1;
+const AFI_IP6: i32 = 2;
+const SAFI_UNICAST: i32 = 1;
+
+#[derive(Clone, PartialEq, Message)]
+struct Family {
+    #[prost(int32, tag = "1")]
+    afi: i32,
+    #[prost(int32, tag = "2")]
+    safi: i32,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct IpAddressPrefix {
+    #[prost(uint32, tag = "1")]
+    prefix_len: u32,
+    #[prost(string, tag = "2")]
+    prefix: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct OriginAttribute {
+    #[prost(uint32, tag = "1")]
+    origin: u32,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct AsSegment {
+    #[prost(uint32, tag = "1")]
+    segment_type: u32,
+    #[prost(uint32, repeated, tag = "2")]
+    numbers: Vec<u32>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct AsPathAttribute {
+    #[prost(message, repeated, tag = "1")]
+    segments: Vec<AsSegment>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct NextHopAttribute {
+    #[prost(string, tag = "1")]
+    next_hop: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct MultiExitDiscAttribute {
+    #[prost(uint32, tag = "1")]
+    med: u32,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct CommunitiesAttribute {
+    #[prost(uint32, repeated, tag = "1")]
+    communities: Vec<u32>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct MpReachNlriAttribute {
+    #[prost(message, optional, tag = "1")]
+    family: Option<Family>,
+    #[prost(string, repeated, tag = "2")]
+    next_hops: Vec<String>,
+    #[prost(message, repeated, tag = "3")]
+    nlris: Vec<Any>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct Path {
+    #[prost(message, optional, tag = "1")]
+    nlri: Option<Any>,
+    #[prost(message, repeated, tag = "2")]
+    pattrs: Vec<Any>,
+    #[prost(message, optional, tag = "9")]
+    family: Option<Family>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct AddPathRequest {
+    #[prost(int32, tag = "1")]
+    table_type: i32,
+    #[prost(string, tag = "2")]
+    vrf_id: String,
+    #[prost(message, optional, tag = "3")]
+    path: Option<Path>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct AddPathResponse {
+    #[prost(bytes = "vec", tag = "1")]
+    uuid: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct DeletePathRequest {
+    #[prost(int32, tag = "1")]
+    table_type: i32,
+    #[prost(string, tag = "2")]
+    vrf_id: String,
+    #[prost(message, optional, tag = "3")]
+    family: Option<Family>,
+    #[prost(message, optional, tag = "4")]
+    path: Option<Path>,
+    #[prost(bytes = "vec", tag = "5")]
+    uuid: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct Empty {}
+
+fn any<M: Message>(name: &str, message: &M) -> Any {
+    Any {
+	type_url: format!("type.googleapis.com/apipb.{}", name),
+	value: message.encode_to_vec(),
+    }
+}
+
+fn family_of(prefix: &IpNet) -> Family {
+    match prefix {
+	IpNet::V4(_) => Family { afi: AFI_IP, safi: SAFI_UNICAST },
+	IpNet::V6(_) => Family { afi: AFI_IP6, safi: SAFI_UNICAST },
+    }
+}
+
+/// Returns the segments of the BGP AS_PATH attribute for `path`, as pairs of segment type and ASNs:
+/// each run of ASNs is one AS_SEQUENCE (2), and each AS_SET one AS_SET segment (1)
+///
+/// # Examples
+///
+/// ```
+/// use risclient::AsPathEntry;
+/// use risclient::gobgp::as_path_segments;
+/// let path = [AsPathEntry::Asn(64500), AsPathEntry::Asn(64501), AsPathEntry::Asn(64502)];
+/// assert_eq!(as_path_segments(&path), vec![(2, vec![64500, 64501, 64502])]);
+/// let path = [AsPathEntry::Asn(64500), AsPathEntry::Set(vec![64501, 64502]), AsPathEntry::Asn(64503)];
+/// assert_eq!(as_path_segments(&path), vec![(2, vec![64500]), (1, vec![64501, 64502]), (2, vec![64503])]);
+/// ```
+pub fn as_path_segments(path: &[AsPathEntry]) -> Vec<(u32, Vec<u32>)> {
+    let mut segments: Vec<(u32, Vec<u32>)> = Vec::new();
+    for entry in path {
+	match (entry, segments.last_mut()) {
+	    (AsPathEntry::Asn(asn), Some((2, numbers))) => numbers.push(*asn),
+	    (AsPathEntry::Asn(asn), _) => segments.push((2, vec![*asn])),
+	    (AsPathEntry::Set(set), _) => segments.push((1, set.clone())),
+	}
+    }
+    segments
+}
+
+/// Selects which routes are injected. An empty filter matches every route.
+#[derive(Debug, Clone, Default)]
+pub struct InjectFilter {
+    prefixes: Vec<IpNet>,
+    origins: Vec<u32>,
+}
+
+impl InjectFilter {
+
+    /// Returns an InjectFilter matching every route
+    pub fn new() -> InjectFilter {
+	InjectFilter::default()
+    }
+
+    /// Only inject routes equal to or more specific than `prefix`. May be given more than once.
+    pub fn prefix(mut self, prefix: IpNet) -> InjectFilter {
+	self.prefixes.push(prefix);
+	self
+    }
+
+    /// Only inject routes originated by `origin`. May be given more than once.
+    pub fn origin(mut self, origin: u32) -> InjectFilter {
+	self.origins.push(origin);
+	self
+    }
+
+    /// Returns true if a route for `prefix` originated by `origin` should be injected
+    pub fn matches(&self, prefix: &IpNet, origin: Option<u32>) -> bool {
+	let prefix_matches = self.prefixes.is_empty() || self.prefixes.iter().any(|filter| filter.contains(prefix));
+	let origin_matches = self.origins.is_empty() || origin.map(|origin| self.origins.contains(&origin)).unwrap_or(false);
+	prefix_matches && origin_matches
+    }
+}
+
+/// A handle which stops a GobgpInjector, withdrawing everything it injected
+#[derive(Debug, Clone)]
+pub struct KillSwitch {
+    killed: Arc<AtomicBool>,
+}
+
+impl KillSwitch {
+    /// Stops injection. The injector withdraws its routes the next time it is used, or on `withdraw_all`.
+    pub fn trigger(&self) {
+	self.killed.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns true once the switch has been triggered
+    pub fn is_triggered(&self) -> bool {
+	self.killed.load(Ordering::SeqCst)
+    }
+}
+
+struct Injected {
+    source: (String, String),
+    path: Path,
+}
+
+///
+/// Mirrors routes from RIS messages into a GoBGP global RIB.
+/// Each prefix is injected once, from whichever RIS peer last announced it,
+/// and withdrawn again when that peer withdraws it.
+///
+pub struct GobgpInjector {
+    grpc: tonic::client::Grpc<Channel>,
+    filter: InjectFilter,
+    kill_switch: KillSwitch,
+    injected: HashMap<IpNet, Injected>,
+    max_routes: usize,
+}
+
+impl GobgpInjector {
+
+    /// Connects to the GoBGP API at `endpoint`, such as "http://127.0.0.1:50051"
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use risclient::{RisClient, Subscription};
+    /// use risclient::gobgp::{GobgpInjector, InjectFilter};
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let filter = InjectFilter::new().prefix("193.0.0.0/16".parse().unwrap());
+    /// let mut injector = GobgpInjector::connect("http://127.0.0.1:50051".to_string()).await.unwrap().with_filter(filter);
+    /// let kill_switch = injector.kill_switch();
+    /// let mut client = RisClient::default().unwrap();
+    /// let rx = client.subscribe(&Subscription::new().prefix("193.0.0.0/16")).await.unwrap();
+    /// while let Ok(message) = rx.recv().await {
+    ///     injector.inject(&message).await.unwrap();
+    /// }
+    /// # }
+    /// ```
+    pub async fn connect(endpoint: String) -> Result<GobgpInjector, Box<dyn error::Error + Send + Sync>> {
+	let channel = Channel::from_shared(endpoint)?.connect().await?;
+	Ok(GobgpInjector {
+	    grpc: tonic::client::Grpc::new(channel),
+	    filter: InjectFilter::default(),
+	    kill_switch: KillSwitch { killed: Arc::new(AtomicBool::new(false)) },
+	    injected: HashMap::new(),
+	    max_routes: 10_000,
+	})
+    }
+
+    /// Only injects routes matching `filter`
+    pub fn with_filter(mut self, filter: InjectFilter) -> GobgpInjector {
+	self.filter = filter;
+	self
+    }
+
+    /// Stops injecting new prefixes once `max_routes` are installed, as a guard against an overly broad filter
+    pub fn with_max_routes(mut self, max_routes: usize) -> GobgpInjector {
+	self.max_routes = max_routes;
+	self
+    }
+
+    /// Returns a KillSwitch which can stop this injector from another task
+    pub fn kill_switch(&self) -> KillSwitch {
+	self.kill_switch.clone()
+    }
+
+    /// Returns the number of prefixes currently injected
+    pub fn injected(&self) -> usize {
+	self.injected.len()
+    }
+
+    async fn add_path(&mut self, path: Path) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+	let request = AddPathRequest {
+	    table_type: TABLE_TYPE_GLOBAL,
+	    vrf_id: String::new(),
+	    path: Some(path),
+	};
+	self.grpc.ready().await?;
+	let codec = tonic::codec::ProstCodec::<AddPathRequest, AddPathResponse>::default();
+	self.grpc.unary(tonic::Request::new(request), PathAndQuery::from_static("/apipb.GobgpApi/AddPath"), codec).await?;
+	Ok(())
+    }
+
+    async fn delete_path(&mut self, path: Path) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+	let request = DeletePathRequest {
+	    table_type: TABLE_TYPE_GLOBAL,
+	    vrf_id: String::new(),
+	    family: path.family.clone(),
+	    path: Some(path),
+	    uuid: Vec::new(),
+	};
+	self.grpc.ready().await?;
+	let codec = tonic::codec::ProstCodec::<DeletePathRequest, Empty>::default();
+	self.grpc.unary(tonic::Request::new(request), PathAndQuery::from_static("/apipb.GobgpApi/DeletePath"), codec).await?;
+	Ok(())
+    }
+
+    fn path_attributes(data: &RisResponseData) -> Vec<Any> {
+	let origin = match data.origin() {
+	    Some("egp") | Some("EGP") => 1,
+	    Some("incomplete") | Some("INCOMPLETE") => 2,
+	    _ => 0,
+	};
+	let segments = as_path_segments(data.path()).into_iter()
+	    .map(|(segment_type, numbers)| AsSegment { segment_type, numbers })
+	    .collect();
+	let mut attributes = vec![
+	    any("OriginAttribute", &OriginAttribute { origin }),
+	    any("AsPathAttribute", &AsPathAttribute { segments }),
+	];
+	if let Some(med) = data.med() {
+	    attributes.push(any("MultiExitDiscAttribute", &MultiExitDiscAttribute { med }));
+	}
+	let communities: Vec<u32> = data.community().iter()
+	    .filter(|(asn, value)| *asn <= 0xffff && *value <= 0xffff)
+	    .map(|(asn, value)| asn << 16 | value)
+	    .collect();
+	if !communities.is_empty() {
+	    attributes.push(any("CommunitiesAttribute", &CommunitiesAttribute { communities }));
+	}
+	attributes
+    }
+
+    fn path_for(prefix: &IpNet, next_hop: &str, attributes: &[Any]) -> Path {
+	let nlri = any("IPAddressPrefix", &IpAddressPrefix {
+	    prefix_len: prefix.prefix_len() as u32,
+	    prefix: prefix.network().to_string(),
+	});
+	let family = family_of(prefix);
+	let mut pattrs = attributes.to_vec();
+	match prefix {
+	    IpNet::V4(_) => pattrs.push(any("NextHopAttribute", &NextHopAttribute { next_hop: next_hop.to_string() })),
+	    IpNet::V6(_) => pattrs.push(any("MpReachNLRIAttribute", &MpReachNlriAttribute {
+		family: Some(family.clone()),
+		next_hops: next_hop.split(',').map(|next_hop| next_hop.trim().to_string()).collect(),
+		nlris: vec![nlri.clone()],
+	    })),
+	}
+	Path {
+	    nlri: Some(nlri),
+	    pattrs,
+	    family: Some(family),
+	}
+    }
+
+    /// Injects the matching announcements of an UPDATE and removes matching withdrawn prefixes
+    /// previously injected from the same peer. Returns the number of paths added or removed.
+    /// Once the kill switch is triggered, this withdraws every injected route instead.
+    pub async fn inject(&mut self, message: &RisResponse) -> Result<usize, Box<dyn error::Error + Send + Sync>> {
+	if self.kill_switch.is_triggered() {
+	    return self.withdraw_all().await;
+	}
+	let data = message.data();
+	if data.data_type() != "UPDATE" {
+	    return Ok(0);
+	}
+	let source = (data.host().to_string(), data.peer().to_string());
+	let origin = data.asns().last().copied();
+	let mut changed = 0;
+	for withdrawal in data.withdrawals() {
+	    let prefix = match withdrawal.parse::<IpNet>() {
+		Ok(prefix) => prefix,
+		Err(_) => continue,
+	    };
+	    let injected_here = self.injected.get(&prefix).map(|injected| injected.source == source).unwrap_or(false);
+	    if injected_here {
+		if let Some(injected) = self.injected.remove(&prefix) {
+		    self.delete_path(injected.path).await?;
+		    changed += 1;
+		}
+	    }
+	}
+	if data.announcements().is_empty() {
+	    return Ok(changed);
+	}
+	let attributes = GobgpInjector::path_attributes(data);
+	for announcement in data.announcements() {
+	    for prefix in announcement.prefixes() {
+		let prefix = match prefix.parse::<IpNet>() {
+		    Ok(prefix) => prefix,
+		    Err(_) => continue,
+		};
+		if !self.filter.matches(&prefix, origin) {
+		    continue;
+		}
+		if !self.injected.contains_key(&prefix) && self.injected.len() >= self.max_routes {
+		    continue;
+		}
+		let path = GobgpInjector::path_for(&prefix, announcement.next_hop(), &attributes);
+		self.add_path(path.clone()).await?;
+		self.injected.insert(prefix, Injected { source: source.clone(), path });
+		changed += 1;
+	    }
+	}
+	Ok(changed)
+    }
+
+    /// Withdraws every route this injector added, returning how many were removed
+    pub async fn withdraw_all(&mut self) -> Result<usize, Box<dyn error::Error + Send + Sync>> {
+	let injected: Vec<IpNet> = self.injected.keys().copied().collect();
+	let mut removed = 0;
+	for prefix in injected {
+	    if let Some(injected) = self.injected.remove(&prefix) {
+		self.delete_path(injected.path).await?;
+		removed += 1;
+	    }
+	}
+	Ok(removed)
+    }
+}
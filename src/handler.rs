@@ -0,0 +1,40 @@
+//! Callback based consumption
+//!
+//! A `RisHandler` is given to `RisClient::run_with_handler`, which owns the
+//! receive loop: it subscribes, feeds every message to the handler, and
+//! reconnects with exponential backoff whenever the connection drops, until
+//! the handler asks it to stop.
+
+use std::error;
+use std::future::Future;
+use std::ops::ControlFlow;
+
+use crate::{RisError, RisResponse};
+
+/// Handles messages for `RisClient::run_with_handler`
+pub trait RisHandler {
+    /// Handles a message. Returning `ControlFlow::Break` stops the run.
+    fn handle(&mut self, message: RisResponse) -> impl Future<Output = ControlFlow<()>>;
+
+    /// Called with errors passed along the stream, such as messages that failed to decode.
+    /// Returning `ControlFlow::Break` stops the run.
+    fn error(&mut self, _error: &RisError) -> ControlFlow<()> {
+	ControlFlow::Continue(())
+    }
+
+    /// Called when subscribing fails, with the error, or when an established stream ends, with `None`.
+    /// Returning `ControlFlow::Break` stops the run instead of reconnecting.
+    fn disconnected(&mut self, _error: Option<&dyn error::Error>) -> ControlFlow<()> {
+	ControlFlow::Continue(())
+    }
+}
+
+impl<F, Fut> RisHandler for F
+where
+    F: FnMut(RisResponse) -> Fut,
+    Fut: Future<Output = ControlFlow<()>>,
+{
+    fn handle(&mut self, message: RisResponse) -> impl Future<Output = ControlFlow<()>> {
+	self(message)
+    }
+}
@@ -0,0 +1,119 @@
+//! Health of a client's connections
+//!
+//! `RisClient::health` reports whether the client is connected, how recently a
+//! message arrived and how far behind the collectors it is, in a form meant for
+//! liveness and readiness probes and internal health endpoints.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::clock::Clock;
+
+/// A snapshot of a client's health, returned by `RisClient::health`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Health {
+    /// Whether any stream from the client is connected
+    pub connected: bool,
+    /// How long ago the last message arrived, or `None` if none has
+    pub since_last_message: Option<Duration>,
+    /// How long after the collector received it the last message arrived, or `None` if none has.
+    /// Messages from `RisClient::stream_as` do not update this.
+    pub lag: Option<Duration>,
+    /// How many times `RisClient::run_with_handler` has reconnected
+    pub reconnects: u64,
+    /// How many subscriptions are active across the connected streams, one per collector subscribed to
+    pub subscriptions: usize,
+}
+
+impl Health {
+    /// Returns true if the client is connected, for a readiness probe
+    pub fn is_ready(&self) -> bool {
+	self.connected
+    }
+
+    /// Returns true if the client is connected and nothing has been silent for longer than
+    /// `max_silence`, for a liveness probe. A stream that has not delivered anything yet counts as live.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use risclient::health::Health;
+    /// let health = Health { connected: true, since_last_message: Some(Duration::from_secs(90)), lag: None, reconnects: 0, subscriptions: 1 };
+    /// assert!(health.is_ready());
+    /// assert!(!health.is_live(Duration::from_secs(60)));
+    /// ```
+    pub fn is_live(&self, max_silence: Duration) -> bool {
+	self.connected && self.since_last_message.is_none_or(|since| since <= max_silence)
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    connections: usize,
+    subscriptions: usize,
+    last_message: Option<Instant>,
+    lag: Option<Duration>,
+    reconnects: u64,
+}
+
+/// The health a client's streams update as they run
+#[derive(Debug, Clone)]
+pub(crate) struct HealthState {
+    state: Arc<Mutex<State>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl HealthState {
+    pub(crate) fn new(clock: Arc<dyn Clock>) -> HealthState {
+	HealthState { state: Arc::default(), clock }
+    }
+
+    /// Returns a snapshot of the current health
+    pub(crate) fn snapshot(&self) -> Health {
+	let state = self.state.lock().unwrap();
+	Health {
+	    connected: state.connections > 0,
+	    since_last_message: state.last_message.map(|last| self.clock.now() - last),
+	    lag: state.lag,
+	    reconnects: state.reconnects,
+	    subscriptions: state.subscriptions,
+	}
+    }
+
+    /// Records a connection with `subscriptions` active, which lasts until the returned guard is dropped
+    pub(crate) fn connected(&self, subscriptions: usize) -> Connection {
+	let mut state = self.state.lock().unwrap();
+	state.connections += 1;
+	state.subscriptions += subscriptions;
+	Connection { health: self.clone(), subscriptions }
+    }
+
+    /// Records a message arriving, with the time the collector received it if known
+    pub(crate) fn message(&self, timestamp: Option<f64>) {
+	let mut state = self.state.lock().unwrap();
+	state.last_message = Some(self.clock.now());
+	if let Some(timestamp) = timestamp.filter(|timestamp| *timestamp > 0.0) {
+	    state.lag = Some(Duration::from_secs_f64((self.clock.epoch_seconds() - timestamp).max(0.0)));
+	}
+    }
+
+    /// Records a reconnection
+    pub(crate) fn reconnected(&self) {
+	self.state.lock().unwrap().reconnects += 1;
+    }
+}
+
+/// A connected stream, which stops counting towards the client's health once dropped
+pub(crate) struct Connection {
+    health: HealthState,
+    subscriptions: usize,
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+	let mut state = self.health.state.lock().unwrap();
+	state.connections -= 1;
+	state.subscriptions -= self.subscriptions;
+    }
+}
@@ -1,12 +1,121 @@
+use std::collections::{BTreeMap, HashSet};
 use std::error;
+use std::sync::Arc;
+use std::time::Duration;
 
 use futures_util::{StreamExt, SinkExt};
+use serde::ser::SerializeStruct;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 use tokio_tungstenite::connect_async;
-use std::sync::mpsc::{channel, Receiver};
+
+use clock::Clock;
+use receiver::Deliver;
 
 #[macro_use] extern crate serde_derive;
 
-fn default_timestamp() -> f32 {
+#[cfg(feature = "analytics")]
+pub mod aggregate;
+#[cfg(feature = "analytics")]
+pub mod alert;
+#[cfg(feature = "analytics")]
+pub mod asrel;
+pub mod attribute;
+pub mod bgp;
+pub mod blocking;
+#[cfg(feature = "sinks-bmp")]
+pub mod bmp;
+pub mod clock;
+pub mod collectors;
+#[cfg(feature = "capi")]
+pub mod capi;
+pub mod community;
+#[cfg(any(feature = "cbor", feature = "msgpack"))]
+pub mod encoding;
+#[cfg(feature = "sinks-exabgp")]
+pub mod exabgp;
+mod errors;
+#[cfg(feature = "rib")]
+pub mod fulltable;
+pub mod handler;
+pub mod health;
+pub mod manager;
+#[cfg(feature = "gobgp")]
+pub mod gobgp;
+#[cfg(feature = "rib")]
+pub mod mrt;
+pub mod parse;
+#[cfg(feature = "analytics")]
+pub mod peeringdb;
+pub mod receiver;
+#[cfg(feature = "python")]
+#[allow(clippy::useless_conversion)] // false positive in code generated by pyo3's macros
+mod python;
+pub mod record;
+pub mod request;
+#[cfg(feature = "rib")]
+pub mod rib;
+#[cfg(feature = "analytics")]
+pub mod rpki;
+#[cfg(feature = "analytics")]
+pub mod rtr;
+#[cfg(feature = "analytics")]
+pub mod session;
+#[cfg(feature = "analytics")]
+pub mod stats;
+pub mod subscription;
+#[cfg(feature = "analytics")]
+pub mod watch;
+pub mod withdrawal;
+
+pub use errors::RisError;
+pub use handler::RisHandler;
+pub use parse::{DeadLetter, DeadLetters, ParseMode, RisMessage};
+pub use receiver::{Envelope, RisReceiver, Sequence, TypedReceiver};
+pub use request::RisRequestBuilder;
+pub use subscription::Subscription;
+pub use tokio_util::sync::CancellationToken;
+pub use tokio_tungstenite::tungstenite;
+
+/// How long a stream's reader waits for its close frame to be sent before dropping the connection
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The first delay before reconnecting in `RisClient::run_with_handler`, doubled after each failure
+const RECONNECT_BACKOFF: Duration = Duration::from_secs(1);
+
+/// The longest delay before reconnecting in `RisClient::run_with_handler`
+const RECONNECT_BACKOFF_MAX: Duration = Duration::from_secs(60);
+
+/// How long `RisClient::subscribe` waits for RIS Live to acknowledge a subscription, unless told otherwise
+const ACK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A connection to RIS Live
+type Socket = tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>;
+
+/// The sending half of a connection from `RisClient::connect_raw`
+pub type RawSink = futures_util::stream::SplitSink<Socket, tungstenite::Message>;
+
+/// The receiving half of a connection from `RisClient::connect_raw`
+pub type RawStream = futures_util::stream::SplitStream<Socket>;
+
+/// Returns the websocket URL of a RIS Live host, identifying the client as `client_id`
+fn endpoint(host: &str, client_id: &str) -> String {
+    // a host given with its scheme, such as a local test server, is taken as is
+    if host.starts_with("ws://") || host.starts_with("wss://") {
+	return format!("{}/v1/ws/?client={}", host, client_id);
+    }
+    format!("wss://{}/v1/ws/?client={}", host, client_id)
+}
+
+/// Attaches a subscription's labels to a message it delivered
+fn label(mut response: RisResponse, labels: &BTreeMap<String, String>) -> RisResponse {
+    if !labels.is_empty() {
+	response.data.labels = labels.clone();
+    }
+    response
+}
+
+fn default_timestamp() -> f64 {
     0.0
 }
 
@@ -14,12 +123,39 @@ fn default_unknown_string() -> String {
     "unknown".to_string()
 }
 
+/// An entry in an AS path, either a single ASN or an unordered AS_SET
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum AsPathEntry {
+    Asn(u32),
+    Set(Vec<u32>),
+}
+
+/// A group of prefixes announced with the same next hop
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Announcement {
+    next_hop: String,
+    prefixes: Vec<String>,
+}
+
+impl Announcement {
+    /// Returns the next hop, which for IPv6 may be a global and link-local address separated by a comma
+    pub fn next_hop(&self) -> &str {
+	&self.next_hop
+    }
+
+    /// Returns the announced prefixes
+    pub fn prefixes(&self) -> &[String] {
+	&self.prefixes
+    }
+}
+
 /// Represents the data portion of a response from the RIS API
 /// Not all messages have data, such as the Ping/Pong messages
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RisResponseData {
     #[serde(default="default_timestamp")]
-    timestamp: f32,
+    timestamp: f64,
     #[serde(default="default_unknown_string")]
     peer: String,
     #[serde(default="default_unknown_string")]
@@ -30,7 +166,47 @@ pub struct RisResponseData {
     host: String,
     #[serde(rename = "type")]
     #[serde(default="default_unknown_string")]
-    data_type: String
+    data_type: String,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    path: Vec<AsPathEntry>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    community: Vec<(u32, u32)>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    origin: Option<String>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    med: Option<u32>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    aggregator: Option<String>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    announcements: Vec<Announcement>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    withdrawals: Vec<String>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    state: Option<String>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    raw: Option<String>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    labels: BTreeMap<String, String>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sequence: Option<Sequence>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    received: Option<f64>,
+    #[serde(skip)]
+    out_of_order: bool,
+    #[serde(flatten)]
+    extra: BTreeMap<String, serde_json::Value>,
 }
 
 impl Default for RisResponseData {
@@ -42,10 +218,163 @@ impl Default for RisResponseData {
 	    id: default_unknown_string(),
 	    host: default_unknown_string(),
 	    data_type: default_unknown_string(),
+	    path: Vec::new(),
+	    community: Vec::new(),
+	    origin: None,
+	    med: None,
+	    aggregator: None,
+	    announcements: Vec::new(),
+	    withdrawals: Vec::new(),
+	    state: None,
+	    raw: None,
+	    labels: BTreeMap::new(),
+	    sequence: None,
+	    received: None,
+	    out_of_order: false,
+	    extra: BTreeMap::new(),
 	}
     }
 }
 
+impl RisResponseData {
+    /// Returns the time the message was received by the collector, in seconds since the epoch
+    pub fn timestamp(&self) -> f64 {
+	self.timestamp
+    }
+
+    /// Returns the address of the peer the message was received from
+    pub fn peer(&self) -> &str {
+	&self.peer
+    }
+
+    /// Returns the ASN of the peer the message was received from
+    pub fn peer_asn(&self) -> &str {
+	&self.peer_asn
+    }
+
+    /// Returns the message identifier assigned by RIS
+    pub fn id(&self) -> &str {
+	&self.id
+    }
+
+    /// Returns the RIS collector which received the message, such as "rrc00"
+    pub fn host(&self) -> &str {
+	&self.host
+    }
+
+    /// Returns the BGP message type, such as "UPDATE" or "RIS_PEER_STATE"
+    pub fn data_type(&self) -> &str {
+	&self.data_type
+    }
+
+    /// Returns the AS path of an UPDATE, starting from the peer and ending at the origin
+    pub fn path(&self) -> &[AsPathEntry] {
+	&self.path
+    }
+
+    /// Returns the AS path with any AS_SETs removed, which is what most path analysis wants
+    pub fn asns(&self) -> Vec<u32> {
+	self.path.iter().filter_map(|entry| match entry {
+	    AsPathEntry::Asn(asn) => Some(*asn),
+	    AsPathEntry::Set(_) => None,
+	}).collect()
+    }
+
+    /// Returns the standard communities attached to an UPDATE, as (ASN, value) pairs
+    pub fn community(&self) -> &[(u32, u32)] {
+	&self.community
+    }
+
+    /// Returns the ORIGIN attribute of an UPDATE, one of "igp", "egp" or "incomplete"
+    pub fn origin(&self) -> Option<&str> {
+	self.origin.as_deref()
+    }
+
+    /// Returns the MULTI_EXIT_DISC attribute of an UPDATE
+    pub fn med(&self) -> Option<u32> {
+	self.med
+    }
+
+    /// Returns the AGGREGATOR attribute of an UPDATE, formatted as "asn:address"
+    pub fn aggregator(&self) -> Option<&str> {
+	self.aggregator.as_deref()
+    }
+
+    /// Returns the prefixes announced by an UPDATE, grouped by next hop
+    pub fn announcements(&self) -> &[Announcement] {
+	&self.announcements
+    }
+
+    /// Returns the prefixes withdrawn by an UPDATE
+    pub fn withdrawals(&self) -> &[String] {
+	&self.withdrawals
+    }
+
+    /// Returns the new session state of a RIS_PEER_STATE message, such as "connected" or "down"
+    pub fn state(&self) -> Option<&str> {
+	self.state.as_deref()
+    }
+
+    /// Returns the hex encoded BGP message, only present when the subscription asked for raw messages
+    pub fn raw(&self) -> Option<&str> {
+	self.raw.as_deref()
+    }
+
+    /// Returns the labels of the subscription that delivered the message, see `Subscription::label`
+    pub fn labels(&self) -> &BTreeMap<String, String> {
+	&self.labels
+    }
+
+    /// Returns the fields RIS Live sent that this client does not model, such as an OPEN's capabilities,
+    /// which are kept so the message serialises back to what was received
+    pub fn extra(&self) -> &BTreeMap<String, serde_json::Value> {
+	&self.extra
+    }
+
+    /// Returns where the message falls in the stream that delivered it, for sinks that need to ingest
+    /// it exactly once. Messages from `RisClient::subscribe` and replays are numbered, and the number
+    /// is kept when the message is serialised, so it is recorded and forwarded along with it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use risclient::RisResponse;
+    /// use risclient::record::{self, Recorder};
+    /// let path = std::env::temp_dir().join("risclient-sequence-example.risjsonl");
+    /// let mut recorder = Recorder::create(&path).unwrap();
+    /// for host in ["rrc00", "rrc21", "rrc00"] {
+    ///     let message: RisResponse = serde_json::from_str(&format!(r#"{{"type": "ris_message", "data": {{"timestamp": 1650000000.25,
+    ///         "peer": "192.0.2.1", "peer_asn": "64500", "id": "1", "host": "{}", "type": "KEEPALIVE"}}}}"#, host)).unwrap();
+    ///     recorder.record(&message).unwrap();
+    /// }
+    /// recorder.flush().unwrap();
+    /// let rx = record::replay(&path, 0.0).unwrap();
+    /// let numbers: Vec<(u64, u64)> = rx.into_iter().map(|message| message.unwrap().data().sequence().unwrap())
+    ///     .map(|sequence| (sequence.message, sequence.collector)).collect();
+    /// assert_eq!(numbers, vec![(1, 1), (2, 1), (3, 2)]);
+    /// # std::fs::remove_file(&path).unwrap();
+    /// ```
+    pub fn sequence(&self) -> Option<Sequence> {
+	self.sequence
+    }
+
+    /// Returns when this client received the message, in seconds since the epoch, for messages from
+    /// `RisClient::subscribe` and replays. Like the sequence number, it is kept when serialised.
+    pub fn received(&self) -> Option<f64> {
+	self.received
+    }
+
+    /// Returns true if the message is older than one delivered before it on the same stream
+    pub fn is_out_of_order(&self) -> bool {
+	self.out_of_order
+    }
+
+    /// Returns the value of one of the subscription's labels
+    pub fn label(&self, key: &str) -> Option<&str> {
+	self.labels.get(key).map(String::as_str)
+    }
+}
+
 /// Represents the data portion of a request to the RIS API
 /// Not all requests require data, so this is optional
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -54,17 +383,121 @@ pub struct RisRequestData {
     #[serde(rename = "type")]
     data_type: Option<String>,
     require: Option<String>,
-    path: Option<Vec<u32>> 
+    path: Option<Vec<u32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    peer: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    prefix: Option<String>,
+    #[serde(rename = "moreSpecific")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    more_specific: Option<bool>,
+    #[serde(rename = "lessSpecific")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    less_specific: Option<bool>,
+    #[serde(rename = "socketOptions")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    socket_options: Option<RisSocketOptions>,
 }
 
-
-/// Represents a response from the RIS API
+/// Options controlling what RIS Live includes in the messages sent on this connection
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RisSocketOptions {
+    #[serde(rename = "includeRaw")]
+    include_raw: bool,
+    #[serde(default)]
+    acknowledge: bool,
+}
+
+
+///
+/// Represents a response from the RIS API.
+/// Serialising a message gives back the RIS Live JSON it was decoded from.
+///
+/// # Examples
+///
+/// ```
+/// use risclient::RisResponse;
+/// let frame = r#"{"type": "ris_message", "data": {"timestamp": 1650000000.25, "peer": "192.0.2.1", "peer_asn": "64500",
+///     "id": "1", "host": "rrc00", "type": "UPDATE", "path": [64500, [64501, 64502]], "community": [[64500, 666]],
+///     "origin": "IGP", "med": 10, "aggregator": "64500:192.0.2.1", "withdrawals": ["203.0.113.0/24"],
+///     "announcements": [{"next_hop": "192.0.2.1", "prefixes": ["198.51.100.0/24"]}]}}"#;
+/// let message: RisResponse = serde_json::from_str(frame).unwrap();
+/// assert_eq!(serde_json::to_value(&message).unwrap(), serde_json::from_str::<serde_json::Value>(frame).unwrap());
+///
+/// let open = r#"{"type": "ris_message", "data": {"timestamp": 1650000000.0, "peer": "192.0.2.1", "peer_asn": "64500",
+///     "id": "2", "host": "rrc00", "type": "OPEN", "direction": "sent", "version": 4, "asn": 12654, "hold_time": 180,
+///     "router_id": "192.0.2.254", "capabilities": {"1": {"name": "multiprotocol", "families": ["ipv4/unicast"]}}}}"#;
+/// let message: RisResponse = serde_json::from_str(open).unwrap();
+/// assert_eq!(message.data().extra()["hold_time"], 180);
+/// assert_eq!(serde_json::to_value(&message).unwrap(), serde_json::from_str::<serde_json::Value>(open).unwrap());
+/// ```
+///
+#[derive(Debug, Clone, Deserialize)]
 pub struct RisResponse {
     #[serde(default="default_unknown_string")]
     #[serde(rename = "type")]
     message_type: String,
     data: RisResponseData,
+    #[serde(skip)]
+    unparsed: Option<serde_json::Value>,
+}
+
+impl Serialize for RisResponse {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+	// a frame that did not decode goes back out as it came in
+	if let Some(value) = &self.unparsed {
+	    return value.serialize(serializer);
+	}
+	let mut state = serializer.serialize_struct("RisResponse", 2)?;
+	state.serialize_field("type", &self.message_type)?;
+	state.serialize_field("data", &self.data)?;
+	state.end()
+    }
+}
+
+impl RisResponse {
+    /// Returns a message holding a frame that did not match the schema, or of an unknown type
+    fn unparsed(value: serde_json::Value) -> RisResponse {
+	RisResponse {
+	    message_type: value["type"].as_str().unwrap_or("unknown").to_string(),
+	    data: RisResponseData::default(),
+	    unparsed: Some(value),
+	}
+    }
+
+    /// Returns the decoded message, or the frame's JSON if it did not match the schema
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use risclient::{RisMessage, RisResponse};
+    /// let message: RisResponse = serde_json::from_str(r#"{"type": "ris_message", "data": {"host": "rrc00", "type": "KEEPALIVE"}}"#).unwrap();
+    /// assert!(matches!(message.message(), RisMessage::Parsed(data) if data.host() == "rrc00"));
+    /// ```
+    pub fn message(&self) -> RisMessage<'_> {
+	match &self.unparsed {
+	    Some(value) if !parse::is_known_type(&self.message_type) => RisMessage::UnknownType { type_name: &self.message_type, payload: &value["data"] },
+	    Some(value) => RisMessage::Unparsed(value),
+	    None => RisMessage::Parsed(&self.data),
+	}
+    }
+
+    /// Returns the RIS Live message type, such as "ris_message"
+    pub fn message_type(&self) -> &str {
+	&self.message_type
+    }
+
+    /// Returns the data portion of the message
+    pub fn data(&self) -> &RisResponseData {
+	&self.data
+    }
+
+    /// Returns the message with a label attached, replacing any label with the same key,
+    /// so that what is learned about a message after it arrives travels with it
+    pub fn with_label(mut self, key: &str, value: &str) -> RisResponse {
+	self.data.labels.insert(key.to_string(), value.to_string());
+	self
+    }
 }
 
 /// Represents a request to the RIS API
@@ -72,13 +505,23 @@ pub struct RisResponse {
 pub struct RisRequest {
     #[serde(rename = "type")]
     message_type: String,
-    data: Option<RisRequestData>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<serde_json::Value>,
 }
 
 /// Represents a RIS client
 pub struct RisClient {
     host: String,
     client_id: String,
+    ack_timeout: Option<Duration>,
+    parse_mode: ParseMode,
+    dead_letters: Option<flume::Sender<DeadLetter>>,
+    cancel: CancellationToken,
+    // stops the streams' readers for `close_and_drain`, which replaces it with a fresh one
+    stop: CancellationToken,
+    readers: Vec<tokio::task::JoinHandle<()>>,
+    health: health::HealthState,
+    clock: Arc<dyn Clock>,
 }	
     
 ///
@@ -92,19 +535,27 @@ impl RisClient {
     ///
     /// # Arguments
     ///
-    /// * `host` - A string which indicates the host to connect todo
+    /// * `host` - A string which indicates the host to connect to, over `wss://` unless it starts with `ws://` or `wss://`
     /// * `client_id` - A custom identifier for your client, this is useful for RIPE tracking and support purposes
     ///
     /// # Examples
     ///
     /// ```
     /// use risclient::RisClient;
-    /// let client = RisClient::new("ris-live.ripe.net", "rust-risclient");
+    /// let client = RisClient::new("ris-live.ripe.net".to_string(), "rust-risclient".to_string());
     /// ```    
     pub fn new(host: String, client_id: String) -> Result<RisClient, Box<dyn error::Error>> {
 	Ok(RisClient {
 	    host,
 	    client_id,
+	    ack_timeout: Some(ACK_TIMEOUT),
+	    parse_mode: ParseMode::Lenient,
+	    dead_letters: None,
+	    cancel: CancellationToken::new(),
+	    stop: CancellationToken::new(),
+	    readers: Vec::new(),
+	    health: health::HealthState::new(clock::system()),
+	    clock: clock::system(),
 	})
     }
 
@@ -116,106 +567,563 @@ impl RisClient {
     /// use risclient::RisClient;
     /// let client = RisClient::default();
     /// ```    
+    #[allow(clippy::should_implement_trait)]
     pub fn default() -> Result<RisClient, Box<dyn error::Error>> {
 	Ok(RisClient {
 	    host: "ris-live.ripe.net".to_string(),
 	    client_id: "rust-risclient".to_string(),
+	    ack_timeout: Some(ACK_TIMEOUT),
+	    parse_mode: ParseMode::Lenient,
+	    dead_letters: None,
+	    cancel: CancellationToken::new(),
+	    stop: CancellationToken::new(),
+	    readers: Vec::new(),
+	    health: health::HealthState::new(clock::system()),
+	    clock: clock::system(),
 	})
     }
 
-    /// Returns an async iterator of streamed RIS messages, using the provided filters.
+    /// Sets how long `subscribe` waits for RIS Live to acknowledge a subscription before failing
+    /// with `RisError::Unacknowledged`. Defaults to 10 seconds, and `None` returns as soon as the
+    /// subscription is sent, without asking for an acknowledgement.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use risclient::RisClient;
+    /// let client = RisClient::default().unwrap().with_ack_timeout(Some(Duration::from_secs(30)));
+    /// ```
+    pub fn with_ack_timeout(mut self, ack_timeout: Option<Duration>) -> RisClient {
+	self.ack_timeout = ack_timeout;
+	self
+    }
+
+    /// Sets how strictly messages are decoded. Defaults to `ParseMode::Lenient`.
+    pub fn with_parse_mode(mut self, parse_mode: ParseMode) -> RisClient {
+	self.parse_mode = parse_mode;
+	self
+    }
+
+    /// Stops the client's work once `cancel` is cancelled, for shutting an application down promptly.
+    /// Connecting and waiting for acknowledgement fail with `RisError::Cancelled`, streams end as though
+    /// the connection had closed, and `run_with_handler` returns `Ok` rather than reconnecting.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use risclient::{CancellationToken, RisClient, Subscription};
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let shutdown = CancellationToken::new();
+    /// let mut client = RisClient::default().unwrap().with_cancellation(shutdown.clone());
+    /// tokio::spawn(async move {
+    ///     tokio::signal::ctrl_c().await.unwrap();
+    ///     shutdown.cancel();
+    /// });
+    /// let rx = client.subscribe(&Subscription::new().host("rrc00")).await.unwrap();
+    /// while let Ok(message) = rx.recv().await {
+    ///     println!("{:?}", message);
+    /// }
+    /// # }
+    /// ```
+    pub fn with_cancellation(mut self, cancel: CancellationToken) -> RisClient {
+	self.cancel = cancel;
+	self
+    }
+
+    /// Times acknowledgements, draining, reconnect backoff, health, dead letters and the adapters of
+    /// the receivers it returns with `clock`, such as a `clock::TokioClock` for tests that pause tokio's clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> RisClient {
+	self.health = health::HealthState::new(clock.clone());
+	self.clock = clock;
+	self
+    }
+
+    /// Sends frames that fail to decode, from every later subscription, to the returned channel
+    /// instead of passing them on as errors, so they can be inspected without disturbing the stream
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use risclient::{ParseMode, RisClient, Subscription};
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let mut client = RisClient::default().unwrap().with_parse_mode(ParseMode::Strict);
+    /// let dead_letters = client.dead_letters();
+    /// tokio::spawn(async move {
+    ///     while let Some(dead_letter) = dead_letters.recv().await {
+    ///         eprintln!("{}: {}", dead_letter.error, dead_letter.frame());
+    ///     }
+    /// });
+    /// let rx = client.subscribe(&Subscription::new().host("rrc00")).await.unwrap();
+    /// # }
+    /// ```
+    ///
+    /// A dead letter keeps the number its frame took, so the gap it leaves in the stream is accounted for
+    ///
+    /// ```
+    /// use futures_util::SinkExt;
+    /// use risclient::{RisClient, Subscription};
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    /// let address = listener.local_addr().unwrap();
+    /// tokio::spawn(async move {
+    ///     let (stream, _) = listener.accept().await.unwrap();
+    ///     let mut socket = tokio_tungstenite::accept_async(stream).await.unwrap();
+    ///     for timestamp in ["1650000000.0", "\"yesterday\"", "1650000001.0"] {
+    ///         let frame = format!(r#"{{"type": "ris_message", "data": {{"timestamp": {}, "peer": "192.0.2.1", "peer_asn": "64500", "id": "1", "host": "rrc00.ripe.net", "type": "KEEPALIVE"}}}}"#, timestamp);
+    ///         socket.send(frame.into()).await.unwrap();
+    ///     }
+    ///     let _ = socket.close(None).await;
+    /// });
+    /// let mut client = RisClient::new(format!("ws://{}", address), "rust-risclient".to_string()).unwrap()
+    ///     .with_ack_timeout(None);
+    /// let dead_letters = client.dead_letters();
+    /// let rx = client.subscribe(&Subscription::new()).await.unwrap();
+    /// let mut delivered = Vec::new();
+    /// for _ in 0..2 {
+    ///     delivered.push(rx.recv().await.unwrap().data().sequence().unwrap().message);
+    /// }
+    /// assert_eq!(delivered, vec![1, 3]);
+    /// let dead_letter = dead_letters.recv().await.unwrap();
+    /// assert_eq!(dead_letter.sequence.map(|sequence| sequence.message), Some(2));
+    /// # }
+    /// ```
+    pub fn dead_letters(&mut self) -> DeadLetters {
+	let (tx, rx) = flume::unbounded();
+	self.dead_letters = Some(tx);
+	DeadLetters { rx }
+    }
+
+    /// Returns a stream of RIS messages, using the provided filters.
     /// If you would like the full stream, you should use the `stream` method instead, to save yourself time.
     ///
     /// # Arguments
     ///
     /// * `host` - Optionally return messages for this RIS collector only. For a list of collectors, see here: https://www.ripe.net/analyse/internet-measurements/routing-information-service-ris/ris-raw-data
     /// * `data_type` - Optionally return messages of this type only. The API accepts "UPDATE", "OPEN", "NOTIFICATION", "KEEPALIVE" and "RIS_PEER_STATE".
-    /// * `require` - Optionally filter on announcements or withdrawl messages. The API accepts "announcement" or "withdrawls". Set to `None` to return both.
+    /// * `require` - Optionally filter on announcements or withdrawal messages. The API accepts "announcements" or "withdrawals". Set to `None` to return both.
     /// * `path` - Optionally return messages about the provided path. Set to `None` to return messages for all paths.
     ///
     /// # Examples
     ///
-    /// ```
+    /// ```no_run
     /// use risclient::RisClient;
-    /// let client = RisClient::default();
-    /// let rx = client.stream_custom(Some("rrc16".to_string()), None, None, None);
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let mut client = RisClient::default().unwrap();
+    /// let rx = client.stream_custom(Some("rrc16".to_string()), None, None, None).await.unwrap();
     /// loop {
-    ///    let data = match rx.recv() {
+    ///    let data = match rx.recv().await {
     ///        Ok(message) => message,
     ///        Err(e) => panic!("receive error: {:?}", e)
     ///    };
     ///    println!("message: {:?}\r", data);
     /// }
+    /// # }
     /// ```    
-    pub async fn stream_custom(&mut self, host: Option<String>, data_type: Option<String>, require: Option<String>, path: Option<Vec<u32>>) -> Result<Receiver<RisResponse>, Box<dyn error::Error>> {
-	let url = format!("wss://{}/v1/ws/?client={}", self.host, self.client_id);
-	let handle = connect_async(url).await;
-	match handle {
-	    Ok(handle) => {
-		let request = RisRequest {
-		    message_type: "ris_subscribe".to_string(),
-		    data: Some(RisRequestData {
-			host,
-			data_type,
-			require,
-			path,
-		    })
-		};
-		let (mut tx, _) = handle;
-		let message = match serde_json::to_string(&request) {
-		    Ok(message) => message,
-		    Err(e) => return Err(Box::new(e))
+    pub async fn stream_custom(&mut self, host: Option<String>, data_type: Option<String>, require: Option<String>, path: Option<Vec<u32>>) -> Result<RisReceiver, Box<dyn error::Error>> {
+	let subscription = Subscription {
+	    hosts: host.into_iter().collect(),
+	    data_type,
+	    require,
+	    path,
+	    ..Subscription::new()
+	};
+	self.subscribe(&subscription).await
+    }
+
+    /// Returns a stream of RIS messages matching the provided subscription.
+    /// The subscription is validated first, failing with `RisError::InvalidFilter` without connecting,
+    /// and then this waits for RIS Live to acknowledge it or reject it with `RisError::Server`,
+    /// so a subscription that returns is known to be active. See `with_ack_timeout`.
+    /// A subscription to several collectors is sent as one `ris_subscribe` per collector on the same
+    /// connection, and their messages arrive on one stream, each carrying the collector in `host()`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use risclient::{RisClient, Subscription};
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let mut client = RisClient::default().unwrap();
+    /// let subscription = Subscription::new().host("rrc00").prefix("193.0.0.0/21").include_raw(true);
+    /// let rx = client.subscribe(&subscription).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn subscribe(&mut self, subscription: &Subscription) -> Result<RisReceiver, Box<dyn error::Error>> {
+	let (socket, early) = self.open(subscription).await?;
+	let labels = subscription.labels.clone();
+	let parse_mode = self.parse_mode;
+	let health = self.health.clone();
+	let decode = move |msg: String| parse::decode(msg, parse_mode)
+	    .map(|received| received.map(|response| {
+		health.message(Some(response.data.timestamp));
+		label(response, &labels)
+	    }));
+	// frames are numbered before they are dead-lettered, so each leaves a gap the dead letter explains
+	let (ctx, crx) = receiver::connection_channel(self.clock.clone());
+	self.spawn_reader(subscription, socket, early, ctx.with_dead_letters(self.dead_letters.clone()), decode);
+	Ok(crx)
+    }
+
+    /// Returns a stream of RIS messages matching the provided subscription, each deserialised into `T`
+    /// rather than `RisResponse`, for modelling only the fields needed or keeping every frame as a
+    /// `serde_json::Value`. `T` is given the whole frame, with its `type` and `data`.
+    /// Subscribing works just as it does for `subscribe`, and acknowledgements and pongs are still
+    /// filtered out and `ris_error` still becomes `RisError::Server`, but the parse mode, dead letters
+    /// and labels do not apply: frames that will not deserialise into `T` arrive as `RisError::Decode`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use risclient::{RisClient, Subscription};
+    /// #[derive(serde_derive::Deserialize)]
+    /// struct Frame {
+    ///     data: Origin,
+    /// }
+    /// #[derive(serde_derive::Deserialize)]
+    /// struct Origin {
+    ///     peer: String,
+    ///     path: Vec<serde_json::Value>,
+    /// }
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let mut client = RisClient::default().unwrap();
+    /// let rx = client.stream_as::<Frame>(&Subscription::new().host("rrc00").data_type("UPDATE")).await.unwrap();
+    /// while let Ok(frame) = rx.recv().await {
+    ///     println!("{} {:?}", frame.data.peer, frame.data.path.last());
+    /// }
+    /// # }
+    /// ```
+    pub async fn stream_as<T: DeserializeOwned + Send + 'static>(&mut self, subscription: &Subscription) -> Result<TypedReceiver<T>, Box<dyn error::Error>> {
+	let (socket, early) = self.open(subscription).await?;
+	let health = self.health.clone();
+	let decode = move |msg: String| parse::decode_as::<T>(msg).map(|(received, timestamp)| {
+	    if received.is_ok() {
+		health.message(timestamp);
+	    }
+	    received
+	});
+	let (ctx, crx) = flume::unbounded();
+	self.spawn_reader(subscription, socket, early, ctx, decode);
+	Ok(TypedReceiver::new(crx))
+    }
+
+    /// Returns a stream of only the withdrawals of UPDATEs matching the provided subscription, for outage
+    /// detection. RIS Live is asked to send only UPDATEs carrying withdrawals, and the rest of each UPDATE,
+    /// announcements included, is skipped over rather than parsed. Otherwise this works as `stream_as` does.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use risclient::{RisClient, Subscription};
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let mut client = RisClient::default().unwrap();
+    /// let rx = client.stream_withdrawals(&Subscription::new().prefix("198.51.100.0/24").more_specific(true)).await.unwrap();
+    /// while let Ok(withdrawal) = rx.recv().await {
+    ///     println!("{} withdrew {:?} at {}", withdrawal.peer, withdrawal.withdrawals, withdrawal.host);
+    /// }
+    /// # }
+    /// ```
+    pub async fn stream_withdrawals(&mut self, subscription: &Subscription) -> Result<TypedReceiver<withdrawal::Withdrawal>, Box<dyn error::Error>> {
+	let subscription = subscription.clone().data_type("UPDATE").require("withdrawals");
+	self.stream_as(&subscription).await
+    }
+
+    /// Returns the client's health, for wiring into liveness and readiness probes
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::time::Duration;
+    /// use risclient::{RisClient, Subscription};
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let mut client = RisClient::default().unwrap();
+    /// let rx = client.subscribe(&Subscription::new().hosts(&["rrc00", "rrc21"])).await.unwrap();
+    /// let health = client.health();
+    /// assert_eq!(health.subscriptions, 2);
+    /// if !health.is_live(Duration::from_secs(60)) {
+    ///     eprintln!("nothing for a minute, last lag {:?}", health.lag);
+    /// }
+    /// # }
+    /// ```
+    pub fn health(&self) -> health::Health {
+	self.health.snapshot()
+    }
+
+    /// Delivers the frames that arrived while subscribing, then spawns the task reading the rest of a
+    /// subscription's connection, decoding each frame with `decode`, until the connection fails, the
+    /// client is cancelled or closed, or the receiver is dropped
+    fn spawn_reader<T, D, F>(&mut self, subscription: &Subscription, mut socket: Socket, early: Vec<String>, mut ctx: D, decode: F)
+    where
+	T: Send + 'static,
+	D: Deliver<T>,
+	F: Fn(String) -> Option<Result<T, RisError>> + Send + 'static,
+    {
+	for received in early.into_iter().filter_map(&decode) {
+	    let _ = ctx.deliver(received);
+	}
+	let (cancel, stop, clock) = (self.cancel.clone(), self.stop.clone(), self.clock.clone());
+	let connection = self.health.connected(subscription.hosts.len().max(1));
+	let reader = tokio::spawn(async move {
+	    let _connection = connection;
+	    loop {
+		let msg = tokio::select! {
+		    _ = cancel.cancelled() => break,
+		    _ = stop.cancelled() => {
+			tokio::select! {
+			    _ = cancel.cancelled() => {},
+			    _ = receiver::flushed(ctx.channel(), clock.as_ref()) => {},
+			}
+			break;
+		    },
+		    msg = socket.next() => match msg {
+			Some(msg) => msg,
+			None => break,
+		    },
 		};
-		match tx.send(message.into()).await {
-		    Ok(_) => {
-			let (ctx, crx) = channel();
-			let _result = tokio::spawn(async move {
-			    while let Some(msg)= tx.next().await {
-				match msg {
-				    Ok(msg) => {
-					let message = msg.to_string();
-					let data: RisResponse = match serde_json::from_str(&message) {
-					    Ok(data) => data,
-					    // eof happens all the time, this usually means an empty line which won't parse as JSON
-					    Err(ref e) if e.is_eof() => continue,
-					    Err(e) => panic!("failed decoding message: {:?}, '{}'", e, message),
-					};
-					match ctx.send(data) {
-					    Ok(_) => continue,
-					    Err(e) => panic!("failed to send decoded message to channel: {:?}", e)
-					}
-				    },
-				    Err(e) => panic!("failed to decode message: {:?}", e),
-				}
-			    }
-			});
-			Ok(crx)
+		let received = match msg {
+		    Ok(msg) => match decode(msg.to_string()) {
+			Some(received) => received,
+			None => continue,
 		    },
-		    Err(e) => Err(Box::new(e))
+		    Err(e) => {
+			let _ = ctx.deliver(Err(RisError::Connection(Box::new(e))));
+			break;
+		    },
+		};
+		// an error means the receiver was dropped, so nobody is listening any more
+		if ctx.deliver(received).is_err() {
+		    break;
 		}
-	    },
-	    Err(e) => Err(Box::new(e))
+	    }
+	    // say goodbye rather than just dropping the connection, unless it has already gone
+	    let _ = clock::timeout_at(clock.as_ref(), clock.now() + CLOSE_TIMEOUT, socket.close(None)).await;
+	});
+	self.track(reader);
+    }
+
+    fn track(&mut self, reader: tokio::task::JoinHandle<()>) {
+	self.readers.retain(|reader| !reader.is_finished());
+	self.readers.push(reader);
+    }
+
+    /// Stops every stream from this client reading new frames, and waits up to `timeout` for the
+    /// messages already decoded to be received before the streams end, so a consumer writing them
+    /// out does not lose the tail on shutdown. Streams still holding messages after `timeout` are
+    /// ended anyway, and `RisError::Timeout` is returned. The client can subscribe again afterwards.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::time::Duration;
+    /// use risclient::{RisClient, Subscription};
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let mut client = RisClient::default().unwrap();
+    /// let rx = client.subscribe(&Subscription::new().host("rrc00")).await.unwrap();
+    /// let archiver = tokio::spawn(async move {
+    ///     while let Ok(message) = rx.recv().await {
+    ///         println!("{:?}", message);
+    ///     }
+    /// });
+    /// tokio::signal::ctrl_c().await.unwrap();
+    /// client.close_and_drain(Duration::from_secs(5)).await.unwrap();
+    /// archiver.await.unwrap();
+    /// # }
+    /// ```
+    pub async fn close_and_drain(&mut self, timeout: Duration) -> Result<(), RisError> {
+	std::mem::take(&mut self.stop).cancel();
+	let deadline = self.clock.now() + timeout;
+	let mut drained = true;
+	for mut reader in std::mem::take(&mut self.readers) {
+	    if clock::timeout_at(self.clock.as_ref(), deadline, &mut reader).await.is_err() {
+		reader.abort();
+		drained = false;
+	    }
+	}
+	if drained {
+	    Ok(())
+	} else {
+	    Err(RisError::Timeout)
 	}
     }
 
-    /// Returns an async iterator of streamed RIS messages, with no filters.
-    /// This is equivalent to calling `stream_custom(None, None, None, None)`
+    /// Connects to RIS Live without subscribing to anything, returning the two halves of the websocket for
+    /// protocols or experiments the rest of the client does not cover. Frames go out and come in exactly as
+    /// written and received: nothing is acknowledged, decoded, numbered or counted towards `health`.
+    /// `Subscription::subscribe_frames` and `Subscription::unsubscribe_frames` build the requests to send.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use futures_util::{SinkExt, StreamExt};
+    /// use risclient::{RisClient, Subscription};
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let client = RisClient::default().unwrap();
+    /// let (mut sink, mut stream) = client.connect_raw().await.unwrap();
+    /// let subscription = Subscription::new().host("rrc00").data_type("UPDATE");
+    /// for frame in subscription.subscribe_frames() {
+    ///     sink.send(frame.into()).await.unwrap();
+    /// }
+    /// while let Some(Ok(frame)) = stream.next().await {
+    ///     println!("{}", frame);
+    /// }
+    /// # }
+    /// ```
+    pub async fn connect_raw(&self) -> Result<(RawSink, RawStream), Box<dyn error::Error>> {
+	let (socket, _) = tokio::select! {
+	    _ = self.cancel.cancelled() => return Err(Box::new(RisError::Cancelled)),
+	    connected = connect_async(endpoint(&self.host, &self.client_id)) => connected?,
+	};
+	Ok(socket.split())
+    }
+
+    /// Connects and sends the subscription, waiting for it to be acknowledged if `with_ack_timeout` asks to.
+    /// Returns the socket along with any frames that arrived while waiting, which are yet to be decoded.
+    async fn open(&self, subscription: &Subscription) -> Result<(Socket, Vec<String>), Box<dyn error::Error>> {
+	tokio::select! {
+	    _ = self.cancel.cancelled() => Err(Box::new(RisError::Cancelled)),
+	    opened = self.connect(subscription) => opened,
+	}
+    }
+
+    async fn connect(&self, subscription: &Subscription) -> Result<(Socket, Vec<String>), Box<dyn error::Error>> {
+	subscription.validate()?;
+	let (mut tx, _) = connect_async(endpoint(&self.host, &self.client_id)).await?;
+	let requests = subscription.requests(self.ack_timeout.is_some());
+	// the collectors still to acknowledge, with None standing for an unfiltered subscription
+	let mut pending: HashSet<Option<String>> = requests.iter()
+	    .map(|data| data.host.as_deref().map(|host| collectors::short_name(host).to_string()))
+	    .collect();
+	for data in requests {
+	    tx.send(RisRequest::new("ris_subscribe", &data).to_frame().into()).await?;
+	}
+	let mut early = Vec::new();
+	let Some(ack_timeout) = self.ack_timeout else {
+	    return Ok((tx, early));
+	};
+	let deadline = self.clock.now() + ack_timeout;
+	while !pending.is_empty() {
+	    let msg = match clock::timeout_at(self.clock.as_ref(), deadline, tx.next()).await {
+		Ok(Some(Ok(msg))) => msg.to_string(),
+		Ok(Some(Err(e))) => return Err(Box::new(RisError::Connection(Box::new(e)))),
+		Ok(None) => return Err(Box::new(RisError::Closed)),
+		Err(_) => return Err(Box::new(RisError::Unacknowledged(ack_timeout))),
+	    };
+	    let value: serde_json::Value = serde_json::from_str(&msg).unwrap_or_default();
+	    match value["type"].as_str() {
+		Some("ris_subscribe_ok") => {
+		    let host = value["data"]["subscription"]["host"].as_str().map(|host| collectors::short_name(host).to_string());
+		    // acknowledgements come one per subscription, even if the echoed host is not recognised
+		    if !pending.remove(&host) {
+			if let Some(host) = pending.iter().next().cloned() {
+			    pending.remove(&host);
+			}
+		    }
+		    continue;
+		},
+		Some("ris_error") => return Err(Box::new(parse::server_error(&msg))),
+		// a matching message is as good as an acknowledgement for its collector
+		Some("ris_message") => {
+		    let host = value["data"]["host"].as_str().unwrap_or_default();
+		    pending.remove(&Some(collectors::short_name(host).to_string()));
+		    pending.remove(&None);
+		},
+		_ => {},
+	    }
+	    early.push(msg);
+	}
+	Ok((tx, early))
+    }
+
+    /// Subscribes and passes every message to `handler` until it returns `ControlFlow::Break`.
+    /// If subscribing fails or the stream ends, this reconnects after a delay that starts at a second and
+    /// doubles up to a minute, resetting once messages arrive again. The handler is told about each
+    /// disconnection and can stop the run from there, in which case a subscription error is returned.
+    /// Cancelling the token given to `with_cancellation` ends the run with `Ok`, even mid-sleep.
     ///
     /// # Examples
     ///
+    /// ```no_run
+    /// use std::ops::ControlFlow;
+    /// use risclient::{RisClient, Subscription};
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let mut client = RisClient::default().unwrap();
+    /// let mut seen = 0;
+    /// client.run_with_handler(&Subscription::new().host("rrc00"), |message| {
+    ///     seen += 1;
+    ///     println!("{:?}", message);
+    ///     async move { if seen < 1000 { ControlFlow::Continue(()) } else { ControlFlow::Break(()) } }
+    /// }).await.unwrap();
+    /// # }
     /// ```
+    pub async fn run_with_handler<H: RisHandler>(&mut self, subscription: &Subscription, mut handler: H) -> Result<(), Box<dyn error::Error>> {
+	let mut backoff = RECONNECT_BACKOFF;
+	loop {
+	    match self.subscribe(subscription).await {
+		Ok(rx) => {
+		    loop {
+			let flow = match rx.recv().await {
+			    Ok(message) => {
+				backoff = RECONNECT_BACKOFF;
+				handler.handle(message).await
+			    },
+			    Err(RisError::Closed) => break,
+			    Err(e) => handler.error(&e),
+			};
+			if flow.is_break() {
+			    return Ok(());
+			}
+		    }
+		    if self.cancel.is_cancelled() || handler.disconnected(None).is_break() {
+			return Ok(());
+		    }
+		},
+		Err(_) if self.cancel.is_cancelled() => return Ok(()),
+		Err(e) => {
+		    if handler.disconnected(Some(e.as_ref())).is_break() {
+			return Err(e);
+		    }
+		},
+	    }
+	    tokio::select! {
+		_ = self.cancel.cancelled() => return Ok(()),
+		_ = self.clock.sleep(backoff) => {},
+	    }
+	    self.health.reconnected();
+	    backoff = (backoff * 2).min(RECONNECT_BACKOFF_MAX);
+	}
+    }
+
+    /// Returns a stream of RIS messages, with no filters.
+    /// This is equivalent to calling `stream_custom(None, None, None, None)`
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
     /// use risclient::RisClient;
-    /// let client = RisClient::default();
-    /// let rx = client.stream();
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let mut client = RisClient::default().unwrap();
+    /// let rx = client.stream().await.unwrap();
     /// loop {
-    ///    let data = match rx.recv() {
+    ///    let data = match rx.recv().await {
     ///        Ok(message) => message,
     ///        Err(e) => panic!("receive error: {:?}", e)
     ///    };
     ///    println!("message: {:?}\r", data);
     /// }
+    /// # }
     /// ```    
-    pub async fn stream(&mut self) -> Result<Receiver<RisResponse>, Box<dyn error::Error>> {
+    pub async fn stream(&mut self) -> Result<RisReceiver, Box<dyn error::Error>> {
 	self.stream_custom(None, None, None, None).await
     }
 }
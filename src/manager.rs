@@ -0,0 +1,283 @@
+//! Sharing connections between components
+//!
+//! Every `RisClient::subscribe` opens a websocket of its own. An application
+//! with many independent components, each wanting its own slice of the feed,
+//! would open dozens of sessions to RIPE that way. `RisConnectionManager`
+//! instead multiplexes subscriptions onto a small pool of connections: each
+//! distinct subscription is sent to RIS Live once, however many components
+//! ask for it, messages are matched back to their subscriptions locally with
+//! `Subscription::matches`, and subscriptions and connections nobody listens
+//! to any more are torn down.
+
+use std::collections::BTreeMap;
+use std::error;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures_util::{SinkExt, StreamExt};
+use tokio_tungstenite::connect_async;
+
+use crate::clock::{self, Clock};
+use crate::receiver::{self, RisSender};
+use crate::{endpoint, label, parse, ParseMode, RisError, RisReceiver, RisResponse, Socket, Subscription};
+
+/// How many distinct subscriptions a connection carries before another is opened, unless told otherwise
+const MAX_SUBSCRIPTIONS: usize = 16;
+
+/// How often a connection checks for receivers that have been dropped while its stream is quiet
+const SWEEP_INTERVAL: Duration = Duration::from_secs(5);
+
+struct Subscriber {
+    tx: RisSender,
+    labels: BTreeMap<String, String>,
+}
+
+// one distinct subscription, and everyone receiving it
+struct Shared {
+    subscription: Subscription,
+    // the subscription's ris_subscribe data, which identifies it regardless of labels
+    key: String,
+    subscribers: Vec<Subscriber>,
+}
+
+struct Upstream {
+    id: u64,
+    // frames for the connection to send
+    commands: flume::Sender<String>,
+    shared: Vec<Shared>,
+}
+
+impl Upstream {
+    fn requests(&self) -> usize {
+	self.shared.iter().map(|shared| shared.subscription.requests(false).len()).sum()
+    }
+}
+
+#[derive(Default)]
+struct Pool {
+    upstreams: Vec<Upstream>,
+    next_id: u64,
+}
+
+///
+/// A pool of RIS Live connections shared by any number of subscriptions.
+/// Clones are cheap handles onto the same pool, so each component can be given its own.
+/// Dropping a receiver from `subscribe` ends its share of a subscription, and once nobody
+/// receives a subscription it is unsubscribed, and once a connection carries none it is closed.
+///
+#[derive(Clone)]
+pub struct RisConnectionManager {
+    host: String,
+    client_id: String,
+    parse_mode: ParseMode,
+    max_subscriptions: usize,
+    pool: Arc<Mutex<Pool>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl RisConnectionManager {
+
+    /// Returns a RisConnectionManager with no connections open yet
+    pub fn new(host: String, client_id: String) -> RisConnectionManager {
+	RisConnectionManager {
+	    host,
+	    client_id,
+	    parse_mode: ParseMode::Lenient,
+	    max_subscriptions: MAX_SUBSCRIPTIONS,
+	    pool: Arc::default(),
+	    clock: clock::system(),
+	}
+    }
+
+    /// Sets how many `ris_subscribe` messages, one per collector of each distinct subscription,
+    /// a connection carries before another is opened
+    pub fn with_max_subscriptions(mut self, max_subscriptions: usize) -> RisConnectionManager {
+	self.max_subscriptions = max_subscriptions.max(1);
+	self
+    }
+
+    /// Sets how strictly frames are decoded, as `RisClient::with_parse_mode` does
+    pub fn with_parse_mode(mut self, parse_mode: ParseMode) -> RisConnectionManager {
+	self.parse_mode = parse_mode;
+	self
+    }
+
+    /// Stamps messages and times sweeps and the receivers' adapters with `clock`, as `RisClient::with_clock` does
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> RisConnectionManager {
+	self.clock = clock;
+	self
+    }
+
+    /// Returns how many connections are open
+    pub fn connections(&self) -> usize {
+	self.pool.lock().unwrap().upstreams.len()
+    }
+
+    /// Returns how many distinct subscriptions are active across the connections
+    pub fn subscriptions(&self) -> usize {
+	self.pool.lock().unwrap().upstreams.iter().map(|upstream| upstream.shared.len()).sum()
+    }
+
+    /// Returns a stream of RIS messages matching the provided subscription, sharing a connection, and
+    /// the subscription itself if another receiver already has it. Subscriptions are not acknowledged,
+    /// `ris_error` messages go to every receiver on the connection, and frames that fail to decode are
+    /// dropped, as they cannot be told apart by subscription. The stream ends with its connection.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use risclient::Subscription;
+    /// use risclient::manager::RisConnectionManager;
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let manager = RisConnectionManager::default();
+    /// let routing = manager.subscribe(&Subscription::new().host("rrc00").data_type("UPDATE")).await.unwrap();
+    /// let sessions = manager.clone().subscribe(&Subscription::new().host("rrc00").data_type("RIS_PEER_STATE")).await.unwrap();
+    /// assert_eq!(manager.connections(), 1);
+    /// drop(sessions);
+    /// while let Ok(message) = routing.recv().await {
+    ///     println!("{:?}", message);
+    /// }
+    /// # }
+    /// ```
+    pub async fn subscribe(&self, subscription: &Subscription) -> Result<RisReceiver, Box<dyn error::Error>> {
+	subscription.validate()?;
+	let key = serde_json::to_string(&subscription.requests(false))?;
+	if let Some(rx) = self.join(subscription, &key) {
+	    return Ok(rx);
+	}
+	let (socket, _) = connect_async(endpoint(&self.host, &self.client_id)).await?;
+	let (commands, queued) = flume::unbounded();
+	let (tx, rx) = receiver::connection_channel(self.clock.clone());
+	{
+	    let mut pool = self.pool.lock().unwrap();
+	    let id = pool.next_id;
+	    pool.next_id += 1;
+	    for frame in subscription.subscribe_frames() {
+		let _ = commands.send(frame);
+	    }
+	    pool.upstreams.push(Upstream {
+		id,
+		commands,
+		shared: vec![Shared { subscription: subscription.clone(), key, subscribers: vec![Subscriber { tx, labels: subscription.labels.clone() }] }],
+	    });
+	    tokio::spawn(run(socket, id, queued, self.pool.clone(), self.parse_mode, self.clock.clone()));
+	}
+	Ok(rx)
+    }
+
+    // adds a receiver to a subscription already active, or to a connection with room for it
+    fn join(&self, subscription: &Subscription, key: &str) -> Option<RisReceiver> {
+	let mut pool = self.pool.lock().unwrap();
+	let subscriber = || {
+	    let (tx, rx) = receiver::connection_channel(self.clock.clone());
+	    (Subscriber { tx, labels: subscription.labels.clone() }, rx)
+	};
+	let existing = pool.upstreams.iter_mut().flat_map(|upstream| upstream.shared.iter_mut()).find(|shared| shared.key == key);
+	if let Some(shared) = existing {
+	    let (subscriber, rx) = subscriber();
+	    shared.subscribers.push(subscriber);
+	    return Some(rx);
+	}
+	let needed = subscription.requests(false).len();
+	let upstream = pool.upstreams.iter_mut().find(|upstream| upstream.requests() + needed <= self.max_subscriptions)?;
+	for frame in subscription.subscribe_frames() {
+	    if upstream.commands.send(frame).is_err() {
+		// the connection is closing, so open another
+		return None;
+	    }
+	}
+	let (subscriber, rx) = subscriber();
+	upstream.shared.push(Shared { subscription: subscription.clone(), key: key.to_string(), subscribers: vec![subscriber] });
+	Some(rx)
+    }
+}
+
+impl Default for RisConnectionManager {
+    fn default() -> RisConnectionManager {
+	RisConnectionManager::new("ris-live.ripe.net".to_string(), "rust-risclient".to_string())
+    }
+}
+
+/// Drops the receivers that have gone away, returning the frames unsubscribing from subscriptions
+/// nobody receives any more, and whether the connection carries nothing at all now.
+/// A connection carrying nothing is taken out of the pool.
+fn sweep(pool: &mut Pool, id: u64) -> (Vec<String>, bool) {
+    let Some(index) = pool.upstreams.iter().position(|upstream| upstream.id == id) else {
+	return (Vec::new(), true);
+    };
+    let upstream = &mut pool.upstreams[index];
+    let mut unsubscribe = Vec::new();
+    upstream.shared.retain_mut(|shared| {
+	shared.subscribers.retain(|subscriber| !subscriber.tx.is_disconnected());
+	if shared.subscribers.is_empty() {
+	    unsubscribe.extend(shared.subscription.unsubscribe_frames());
+	}
+	!shared.subscribers.is_empty()
+    });
+    let empty = upstream.shared.is_empty();
+    if empty {
+	pool.upstreams.remove(index);
+    }
+    (unsubscribe, empty)
+}
+
+/// Passes a frame to every receiver whose subscription it matches
+fn dispatch(pool: &mut Pool, id: u64, received: Result<RisResponse, RisError>) {
+    let Some(upstream) = pool.upstreams.iter_mut().find(|upstream| upstream.id == id) else {
+	return;
+    };
+    let subscribers = upstream.shared.iter_mut();
+    match received {
+	Ok(response) => {
+	    for shared in subscribers.filter(|shared| shared.subscription.matches(&response.data)) {
+		for subscriber in &mut shared.subscribers {
+		    let _ = subscriber.tx.send(Ok(label(response.clone(), &subscriber.labels)));
+		}
+	    }
+	},
+	Err(RisError::Server(reason)) => {
+	    for subscriber in subscribers.flat_map(|shared| shared.subscribers.iter_mut()) {
+		let _ = subscriber.tx.send(Err(RisError::Server(reason.clone())));
+	    }
+	},
+	Err(_) => {},
+    }
+}
+
+/// Runs one connection until it fails or nobody receives from it any more
+async fn run(mut socket: Socket, id: u64, commands: flume::Receiver<String>, pool: Arc<Mutex<Pool>>, parse_mode: ParseMode, clock: Arc<dyn Clock>) {
+    loop {
+	tokio::select! {
+	    command = commands.recv_async() => match command {
+		Ok(frame) => {
+		    if socket.send(frame.into()).await.is_err() {
+			break;
+		    }
+		},
+		Err(_) => break,
+	    },
+	    msg = socket.next() => match msg {
+		Some(Ok(msg)) => {
+		    if let Some(received) = parse::decode(msg.to_string(), parse_mode) {
+			dispatch(&mut pool.lock().unwrap(), id, received);
+		    }
+		},
+		_ => break,
+	    },
+	    _ = clock.sleep(SWEEP_INTERVAL) => {},
+	}
+	let (unsubscribe, empty) = sweep(&mut pool.lock().unwrap(), id);
+	for frame in unsubscribe {
+	    if socket.send(frame.into()).await.is_err() {
+		break;
+	    }
+	}
+	if empty {
+	    let _ = socket.close(None).await;
+	    return;
+	}
+    }
+    // the connection has gone, which ends every stream it carried
+    pool.lock().unwrap().upstreams.retain(|upstream| upstream.id != id);
+}
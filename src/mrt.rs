@@ -0,0 +1,292 @@
+//! MRT (RFC 6396) archive reading
+//!
+//! RIS archives every collector's table as periodic TABLE_DUMP_V2 "bview" files,
+//! and every message received as BGP4MP "updates" files. Records are decoded
+//! into the same `RisResponseData` structure RIS Live sends, so archived and
+//! live data can be handled by the same code: each RIB entry becomes an UPDATE
+//! announcing that prefix from that peer, and BGP4MP state changes become
+//! RIS_PEER_STATE messages.
+
+use std::collections::VecDeque;
+use std::error;
+use std::io::{self, Read};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use crate::bgp;
+use crate::{Announcement, RisResponseData};
+
+const TYPE_TABLE_DUMP_V2: u16 = 13;
+const TYPE_BGP4MP: u16 = 16;
+const TYPE_BGP4MP_ET: u16 = 17;
+
+const SUBTYPE_PEER_INDEX_TABLE: u16 = 1;
+const SUBTYPE_RIB_IPV4_UNICAST: u16 = 2;
+const SUBTYPE_RIB_IPV6_UNICAST: u16 = 4;
+
+const SUBTYPE_STATE_CHANGE: u16 = 0;
+const SUBTYPE_MESSAGE: u16 = 1;
+const SUBTYPE_MESSAGE_AS4: u16 = 4;
+const SUBTYPE_STATE_CHANGE_AS4: u16 = 5;
+
+const BGP_STATE_ESTABLISHED: u16 = 6;
+
+/// The longest record accepted. A RIB entry carries one route per peer of the collector, which
+/// stays far below this, so anything longer is a corrupt header rather than a record worth reading.
+const MAX_RECORD_LENGTH: usize = 16 * 1024 * 1024;
+
+fn invalid(message: String) -> Box<dyn error::Error + Send + Sync> {
+    Box::new(io::Error::new(io::ErrorKind::InvalidData, message))
+}
+
+/// A peer listed in a TABLE_DUMP_V2 peer index table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MrtPeer {
+    pub bgp_id: Ipv4Addr,
+    pub address: IpAddr,
+    pub asn: u32,
+}
+
+/// A cursor over a record body, returning an error rather than panicking when it runs out
+struct Cursor<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], Box<dyn error::Error + Send + Sync>> {
+	if self.bytes.len() < count {
+	    return Err(invalid("truncated MRT record".to_string()));
+	}
+	let (taken, rest) = self.bytes.split_at(count);
+	self.bytes = rest;
+	Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, Box<dyn error::Error + Send + Sync>> {
+	Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, Box<dyn error::Error + Send + Sync>> {
+	let bytes = self.take(2)?;
+	Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, Box<dyn error::Error + Send + Sync>> {
+	let bytes = self.take(4)?;
+	Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn address(&mut self, ipv6: bool) -> Result<IpAddr, Box<dyn error::Error + Send + Sync>> {
+	if ipv6 {
+	    let octets: [u8; 16] = self.take(16)?.try_into()?;
+	    Ok(IpAddr::V6(Ipv6Addr::from(octets)))
+	} else {
+	    let octets: [u8; 4] = self.take(4)?.try_into()?;
+	    Ok(IpAddr::V4(Ipv4Addr::from(octets)))
+	}
+    }
+}
+
+///
+/// Reads RIS messages out of an uncompressed MRT stream.
+/// Record types other than TABLE_DUMP_V2 unicast RIBs and BGP4MP messages and state changes are skipped.
+///
+pub struct MrtReader<R> {
+    reader: R,
+    collector: String,
+    peers: Vec<MrtPeer>,
+    pending: VecDeque<RisResponseData>,
+    record_time: Option<u32>,
+}
+
+impl<R: Read> MrtReader<R> {
+
+    /// Returns an MrtReader over `reader`, labelling every message with `collector` as its host
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use risclient::mrt::MrtReader;
+    /// let reader = MrtReader::new(File::open("bview.20240101.0000").unwrap(), "rrc00".to_string());
+    /// for message in reader {
+    ///     let message = message.unwrap();
+    ///     println!("{} {:?}", message.peer(), message.announcements());
+    /// }
+    /// ```
+    ///
+    /// A record header claiming more data than any record holds is an error, not an allocation:
+    ///
+    /// ```
+    /// use risclient::mrt::MrtReader;
+    /// let header = [0, 0, 0, 0, 0, 13, 0, 2, 0xff, 0xff, 0xff, 0xff];
+    /// let mut reader = MrtReader::new(&header[..], "rrc00".to_string());
+    /// assert!(reader.next().unwrap().is_err());
+    /// ```
+    pub fn new(reader: R, collector: String) -> MrtReader<R> {
+	MrtReader {
+	    reader,
+	    collector,
+	    peers: Vec::new(),
+	    pending: VecDeque::new(),
+	    record_time: None,
+	}
+    }
+
+    /// Returns the header timestamp of the most recently read record, which for a bview is the time of the dump
+    pub fn record_time(&self) -> Option<u32> {
+	self.record_time
+    }
+
+    /// Returns the peers listed in the peer index table, once it has been read
+    pub fn peers(&self) -> &[MrtPeer] {
+	&self.peers
+    }
+
+    /// Reads the next record, returning false at a clean end of stream
+    fn read_record(&mut self) -> Result<bool, Box<dyn error::Error + Send + Sync>> {
+	let mut header = [0u8; 12];
+	match self.reader.read_exact(&mut header) {
+	    Ok(()) => {},
+	    Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(false),
+	    Err(e) => return Err(Box::new(e)),
+	}
+	let timestamp = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+	let record_type = u16::from_be_bytes([header[4], header[5]]);
+	let subtype = u16::from_be_bytes([header[6], header[7]]);
+	let length = u32::from_be_bytes([header[8], header[9], header[10], header[11]]) as usize;
+	if length > MAX_RECORD_LENGTH {
+	    return Err(invalid(format!("MRT record of {} bytes is longer than the {} accepted", length, MAX_RECORD_LENGTH)));
+	}
+	// grow the buffer as the body arrives, so a truncated file cannot claim memory it does not hold
+	let mut body = Vec::new();
+	(&mut self.reader).take(length as u64).read_to_end(&mut body)?;
+	if body.len() < length {
+	    return Err(Box::new(io::Error::new(io::ErrorKind::UnexpectedEof, format!("MRT record truncated after {} of {} bytes", body.len(), length))));
+	}
+	self.record_time = Some(timestamp);
+	let mut cursor = Cursor { bytes: &body };
+	match (record_type, subtype) {
+	    (TYPE_TABLE_DUMP_V2, SUBTYPE_PEER_INDEX_TABLE) => self.read_peer_index(&mut cursor)?,
+	    (TYPE_TABLE_DUMP_V2, SUBTYPE_RIB_IPV4_UNICAST) => self.read_rib(&mut cursor, 1)?,
+	    (TYPE_TABLE_DUMP_V2, SUBTYPE_RIB_IPV6_UNICAST) => self.read_rib(&mut cursor, 2)?,
+	    (TYPE_BGP4MP, _) => self.read_bgp4mp(&mut cursor, subtype, timestamp as f64)?,
+	    (TYPE_BGP4MP_ET, _) => {
+		let micros = cursor.u32()?;
+		self.read_bgp4mp(&mut cursor, subtype, timestamp as f64 + micros as f64 / 1_000_000.0)?
+	    },
+	    _ => {},
+	}
+	Ok(true)
+    }
+
+    fn read_peer_index(&mut self, cursor: &mut Cursor) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+	cursor.take(4)?;
+	let view_name_length = cursor.u16()? as usize;
+	cursor.take(view_name_length)?;
+	let count = cursor.u16()?;
+	self.peers.clear();
+	for _ in 0..count {
+	    let peer_type = cursor.u8()?;
+	    let bgp_id = Ipv4Addr::from(cursor.u32()?);
+	    let address = cursor.address(peer_type & 1 != 0)?;
+	    let asn = if peer_type & 2 != 0 { cursor.u32()? } else { cursor.u16()? as u32 };
+	    self.peers.push(MrtPeer { bgp_id, address, asn });
+	}
+	Ok(())
+    }
+
+    fn read_rib(&mut self, cursor: &mut Cursor, afi: u16) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+	cursor.take(4)?;
+	let (prefix, used) = bgp::decode_prefix(afi, cursor.bytes)?;
+	cursor.take(used)?;
+	let count = cursor.u16()?;
+	for _ in 0..count {
+	    let peer_index = cursor.u16()? as usize;
+	    let originated = cursor.u32()?;
+	    let attributes_length = cursor.u16()? as usize;
+	    let attributes = cursor.take(attributes_length)?;
+	    let peer = match self.peers.get(peer_index) {
+		Some(peer) => peer,
+		None => return Err(invalid(format!("RIB entry for unknown peer index {}", peer_index))),
+	    };
+	    let mut data = RisResponseData {
+		timestamp: originated as f64,
+		peer: peer.address.to_string(),
+		peer_asn: peer.asn.to_string(),
+		host: self.collector.clone(),
+		data_type: "UPDATE".to_string(),
+		..RisResponseData::default()
+	    };
+	    // TABLE_DUMP_V2 always encodes AS paths with four octet ASNs
+	    let decoded = bgp::decode_attributes(attributes, true, true, &mut data)?;
+	    data.announcements.push(Announcement {
+		next_hop: decoded.mp_next_hop.or(decoded.next_hop).unwrap_or_default(),
+		prefixes: vec![prefix.to_string()],
+	    });
+	    self.pending.push_back(data);
+	}
+	Ok(())
+    }
+
+    fn read_bgp4mp(&mut self, cursor: &mut Cursor, subtype: u16, timestamp: f64) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+	let four_octet_as = match subtype {
+	    SUBTYPE_MESSAGE | SUBTYPE_STATE_CHANGE => false,
+	    SUBTYPE_MESSAGE_AS4 | SUBTYPE_STATE_CHANGE_AS4 => true,
+	    // local messages and ADD-PATH variants are not something RIS Live reports either
+	    _ => return Ok(()),
+	};
+	let peer_asn = if four_octet_as { cursor.u32()? } else { cursor.u16()? as u32 };
+	if four_octet_as { cursor.u32()?; } else { cursor.u16()?; }
+	cursor.u16()?;
+	let afi = cursor.u16()?;
+	let peer = cursor.address(afi == 2)?;
+	cursor.address(afi == 2)?;
+	let mut data = match subtype {
+	    SUBTYPE_STATE_CHANGE | SUBTYPE_STATE_CHANGE_AS4 => {
+		let old_state = cursor.u16()?;
+		let new_state = cursor.u16()?;
+		if old_state != BGP_STATE_ESTABLISHED && new_state != BGP_STATE_ESTABLISHED {
+		    return Ok(());
+		}
+		RisResponseData {
+		    data_type: "RIS_PEER_STATE".to_string(),
+		    state: Some(if new_state == BGP_STATE_ESTABLISHED { "connected" } else { "down" }.to_string()),
+		    ..RisResponseData::default()
+		}
+	    },
+	    _ => {
+		let message = cursor.bytes;
+		match message.get(18) {
+		    Some(&bgp::MESSAGE_UPDATE) => bgp::decode_update(message, four_octet_as)?,
+		    Some(&bgp::MESSAGE_OPEN) => RisResponseData { data_type: "OPEN".to_string(), ..RisResponseData::default() },
+		    Some(&bgp::MESSAGE_NOTIFICATION) => RisResponseData { data_type: "NOTIFICATION".to_string(), ..RisResponseData::default() },
+		    Some(&bgp::MESSAGE_KEEPALIVE) => RisResponseData { data_type: "KEEPALIVE".to_string(), ..RisResponseData::default() },
+		    _ => return Ok(()),
+		}
+	    },
+	};
+	data.timestamp = timestamp;
+	data.peer = peer.to_string();
+	data.peer_asn = peer_asn.to_string();
+	data.host = self.collector.clone();
+	self.pending.push_back(data);
+	Ok(())
+    }
+}
+
+impl<R: Read> Iterator for MrtReader<R> {
+    type Item = Result<RisResponseData, Box<dyn error::Error + Send + Sync>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+	loop {
+	    if let Some(data) = self.pending.pop_front() {
+		return Some(Ok(data));
+	    }
+	    match self.read_record() {
+		Ok(true) => continue,
+		Ok(false) => return None,
+		Err(e) => return Some(Err(e)),
+	    }
+	}
+    }
+}
@@ -0,0 +1,314 @@
+//! How strictly frames from RIS Live are decoded
+//!
+//! By default anything that deserialises is delivered, with missing fields
+//! filled in with defaults and unknown fields ignored. Pipelines that care about
+//! data quality can ask for schema drift to be reported instead, either as
+//! errors or as messages holding the frame's JSON.
+//!
+//! To keep working across RIS Live versions, frames using renamed fields or
+//! values of an older or newer type are mapped onto the current schema, and
+//! message types this client does not know are delivered as
+//! `RisMessage::UnknownType` rather than failing. `ParseMode::Strict` rejects both.
+
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::clock::Clock;
+use crate::{RisError, RisResponse, RisResponseData, Sequence};
+
+/// The message types RIS Live sends that this client understands
+const MESSAGE_TYPES: [&str; 4] = ["ris_message", "ris_error", "ris_subscribe_ok", "pong"];
+
+/// Other names `ris_message` fields have gone by, and the names this client uses for them
+const FIELD_ALIASES: [(&str, &str); 4] = [("peer_as", "peer_asn"), ("communities", "community"), ("withdrawn", "withdrawals"), ("collector", "host")];
+
+/// Fields this client holds as strings, which may arrive as numbers
+const STRING_FIELDS: [&str; 4] = ["peer", "peer_asn", "id", "host"];
+
+/// The fields any `ris_message` may carry, whatever its type
+const COMMON_FIELDS: [&str; 7] = ["timestamp", "peer", "peer_asn", "id", "host", "type", "raw"];
+
+/// The fields each BGP message type adds to those in `COMMON_FIELDS`
+const TYPE_FIELDS: [(&str, &[&str]); 5] = [
+    ("UPDATE", &["path", "community", "origin", "med", "aggregator", "announcements", "withdrawals"]),
+    ("OPEN", &["direction", "version", "asn", "hold_time", "router_id", "capabilities"]),
+    ("NOTIFICATION", &["code", "subcode", "data"]),
+    ("KEEPALIVE", &[]),
+    ("RIS_PEER_STATE", &["state"]),
+];
+
+/// The fields every `ris_message` carries
+const REQUIRED_FIELDS: [&str; 6] = ["timestamp", "peer", "peer_asn", "id", "host", "type"];
+
+/// The fields of an announcement
+const ANNOUNCEMENT_FIELDS: [&str; 2] = ["next_hop", "prefixes"];
+
+/// How `RisClient` decodes frames, set with `RisClient::with_parse_mode`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ParseMode {
+    /// Fill in missing fields with defaults, ignore unknown fields, and pass frames that will not
+    /// deserialise on as `RisError::Decode`
+    #[default]
+    Lenient,
+    /// Fail a `ris_message` missing a field every message carries, or with a field its type does
+    /// not have, with `RisError::Schema`
+    Strict,
+    /// Like `Lenient`, but deliver frames that are JSON yet will not deserialise as messages whose
+    /// `RisResponse::message` is `RisMessage::Unparsed`
+    Tolerant,
+}
+
+impl ParseMode {
+    /// Decodes one frame as RIS Live sent it, returning `None` for frames that are not messages,
+    /// such as empty lines and subscription acknowledgements
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use risclient::{ParseMode, RisError, RisMessage};
+    /// let frame = r#"{"type": "ris_message", "data": {"timestamp": 1650000000.0, "peer": "192.0.2.1", "peer_asn": "64500",
+    ///     "id": "1", "host": "rrc00", "type": "KEEPALIVE", "colour": "blue"}}"#;
+    /// assert!(ParseMode::Lenient.decode(frame).unwrap().is_ok());
+    /// assert!(matches!(ParseMode::Strict.decode(frame), Some(Err(RisError::Schema { .. }))));
+    /// // each type of BGP message is checked against its own fields
+    /// let open = r#"{"type": "ris_message", "data": {"timestamp": 1650000000.0, "peer": "192.0.2.1", "peer_asn": "64500",
+    ///     "id": "2", "host": "rrc00", "type": "OPEN", "direction": "sent", "version": 4, "asn": 64500, "hold_time": 180,
+    ///     "router_id": "192.0.2.1", "capabilities": {}}}"#;
+    /// assert!(ParseMode::Strict.decode(open).unwrap().is_ok());
+    /// let state = open.replace(r#""type": "OPEN""#, r#""type": "RIS_PEER_STATE""#);
+    /// assert!(matches!(ParseMode::Strict.decode(&state), Some(Err(RisError::Schema { reason, .. })) if reason == "unknown RIS_PEER_STATE field asn"));
+    /// let garbled = r#"{"type": "ris_message", "data": {"timestamp": "yesterday"}}"#;
+    /// assert!(matches!(ParseMode::Lenient.decode(garbled), Some(Err(RisError::Decode { .. }))));
+    /// let message = ParseMode::Tolerant.decode(garbled).unwrap().unwrap();
+    /// assert!(matches!(message.message(), RisMessage::Unparsed(value) if value["data"]["timestamp"] == "yesterday"));
+    ///
+    /// // renamed and retyped fields are mapped onto the current schema
+    /// let older = r#"{"type": "ris_message", "data": {"timestamp": "1650000000.5", "peer_as": 64500, "collector": "rrc00", "type": "KEEPALIVE"}}"#;
+    /// let message = ParseMode::Lenient.decode(older).unwrap().unwrap();
+    /// assert_eq!((message.data().peer_asn(), message.data().host(), message.data().timestamp()), ("64500", "rrc00", 1650000000.5));
+    /// let renamed = r#"{"type": "ris_message", "data": {"timestamp": 1650000000.5, "peer_as": "64500", "collector": "rrc00", "type": "KEEPALIVE"}}"#;
+    /// let message = ParseMode::Lenient.decode(renamed).unwrap().unwrap();
+    /// assert_eq!((message.data().peer_asn(), message.data().host()), ("64500", "rrc00"));
+    /// assert!(message.data().extra().is_empty());
+    ///
+    /// let newer = r#"{"type": "ris_forecast", "data": {"outlook": "sunny"}}"#;
+    /// let message = ParseMode::Lenient.decode(newer).unwrap().unwrap();
+    /// assert!(matches!(message.message(), RisMessage::UnknownType { type_name: "ris_forecast", payload } if payload["outlook"] == "sunny"));
+    /// ```
+    pub fn decode(self, frame: &str) -> Option<Result<RisResponse, RisError>> {
+	decode(frame.to_string(), self)
+    }
+}
+
+/// What a delivered message holds, returned by `RisResponse::message`
+#[derive(Debug, Clone, Copy)]
+pub enum RisMessage<'a> {
+    /// A message that decoded
+    Parsed(&'a RisResponseData),
+    /// A frame that did not match the schema, only delivered with `ParseMode::Tolerant`
+    Unparsed(&'a Value),
+    /// A message of a type this client does not know, such as one RIS Live added since.
+    /// `payload` is the message's `data`.
+    UnknownType {
+	type_name: &'a str,
+	payload: &'a Value,
+    },
+}
+
+/// Returns true for the message types this client understands
+pub(crate) fn is_known_type(message_type: &str) -> bool {
+    MESSAGE_TYPES.contains(&message_type)
+}
+
+/// Rewrites a frame into the shape this client models where it can, so that frames from older or
+/// newer versions of RIS Live still decode: renamed fields get their current names, and numbers
+/// sent where strings are expected become strings
+fn normalise(value: &mut Value) {
+    let Some(data) = value.get_mut("data").and_then(Value::as_object_mut) else {
+	return;
+    };
+    for (alias, field) in FIELD_ALIASES {
+	if !data.contains_key(field) {
+	    if let Some(renamed) = data.remove(alias) {
+		data.insert(field.to_string(), renamed);
+	    }
+	}
+    }
+    for field in STRING_FIELDS {
+	if let Some(Value::Number(number)) = data.get(field) {
+	    let string = number.to_string();
+	    data.insert(field.to_string(), Value::String(string));
+	}
+    }
+    let timestamp = data.get("timestamp").and_then(Value::as_str).and_then(|timestamp| timestamp.parse::<f64>().ok());
+    if let Some(timestamp) = timestamp {
+	data.insert("timestamp".to_string(), Value::from(timestamp));
+    }
+}
+
+/// Returns true if a message carries fields under names `normalise` would rename
+fn has_aliases(response: &RisResponse) -> bool {
+    FIELD_ALIASES.iter().any(|(alias, _)| response.data.extra.contains_key(*alias))
+}
+
+/// Returns a description of the first way a `ris_message` departs from the schema
+fn check_schema(value: &Value) -> Option<String> {
+    if value["type"] != "ris_message" {
+	return None;
+    }
+    let data = match value["data"].as_object() {
+	Some(data) => data,
+	None => return Some("missing data".to_string()),
+    };
+    if let Some(field) = REQUIRED_FIELDS.iter().find(|field| !data.contains_key(**field)) {
+	return Some(format!("missing field {}", field));
+    }
+    let data_type = data["type"].as_str().unwrap_or_default();
+    let Some((_, fields)) = TYPE_FIELDS.iter().find(|(name, _)| *name == data_type) else {
+	return Some(format!("unknown data type {:?}", data_type));
+    };
+    let known = |field: &str| COMMON_FIELDS.contains(&field) || fields.contains(&field);
+    if let Some(field) = data.keys().find(|field| !known(field)) {
+	return Some(format!("unknown {} field {}", data_type, field));
+    }
+    let announcements = data.get("announcements").and_then(Value::as_array).into_iter().flatten();
+    for announcement in announcements.filter_map(Value::as_object) {
+	if let Some(field) = announcement.keys().find(|field| !ANNOUNCEMENT_FIELDS.contains(&field.as_str())) {
+	    return Some(format!("unknown announcement field {}", field));
+	}
+    }
+    None
+}
+
+/// Returns the error a `ris_error` frame reports
+pub(crate) fn server_error(message: &str) -> RisError {
+    let value: Value = serde_json::from_str(message).unwrap_or_default();
+    RisError::Server(value["data"]["message"].as_str().unwrap_or(message).to_string())
+}
+
+/// Decodes a websocket message, returning `None` for anything that is not a message, such as pings and empty lines
+pub(crate) fn decode(message: String, mode: ParseMode) -> Option<Result<RisResponse, RisError>> {
+    let response: RisResponse = match serde_json::from_str::<RisResponse>(&message) {
+	// renamed fields still decode, into `extra`, so look for them there
+	Ok(response) if is_known_type(&response.message_type) && !has_aliases(&response) => response,
+	// eof happens all the time, this usually means an empty line which won't parse as JSON
+	Err(ref e) if e.is_eof() => return None,
+	// an unknown type, or a shape that needs normalising first
+	_ => {
+	    let mut value: Value = match serde_json::from_str(&message) {
+		Ok(value) => value,
+		Err(error) => return Some(Err(RisError::Decode { error, message })),
+	    };
+	    let message_type = value["type"].as_str().unwrap_or_default().to_string();
+	    if !is_known_type(&message_type) {
+		return Some(match mode {
+		    ParseMode::Strict => Err(RisError::Schema { reason: format!("unknown message type {:?}", message_type), message }),
+		    _ => Ok(RisResponse::unparsed(value)),
+		});
+	    }
+	    normalise(&mut value);
+	    match serde_json::from_value(value.clone()) {
+		Ok(response) => response,
+		Err(_) if mode == ParseMode::Tolerant => return Some(Ok(RisResponse::unparsed(value))),
+		Err(error) => return Some(Err(RisError::Decode { error, message })),
+	    }
+	},
+    };
+    match response.message_type.as_str() {
+	"ris_subscribe_ok" | "pong" => return None,
+	"ris_error" => return Some(Err(server_error(&message))),
+	_ => {},
+    }
+    if mode == ParseMode::Strict {
+	let value: Value = serde_json::from_str(&message).unwrap_or_default();
+	if let Some(reason) = check_schema(&value) {
+	    return Some(Err(RisError::Schema { reason, message }));
+	}
+    }
+    Some(Ok(response))
+}
+
+/// Decodes a websocket message into `T` for `RisClient::stream_as`, along with the message's timestamp if it
+/// has one, returning `None` for anything that is not a message and `RisError::Server` for errors, as `decode` does
+pub(crate) fn decode_as<T: DeserializeOwned>(message: String) -> Option<(Result<T, RisError>, Option<f64>)> {
+    #[derive(Deserialize)]
+    struct Envelope {
+	#[serde(rename = "type")]
+	message_type: String,
+	data: Option<Stamp>,
+    }
+    // only the timestamp, so that the rest of the data is skipped rather than parsed
+    #[derive(Deserialize)]
+    struct Stamp {
+	timestamp: Option<Value>,
+    }
+    let mut timestamp = None;
+    match serde_json::from_str::<Envelope>(&message) {
+	Err(ref e) if e.is_eof() => return None,
+	Ok(envelope) => match envelope.message_type.as_str() {
+	    "ris_subscribe_ok" | "pong" => return None,
+	    "ris_error" => return Some((Err(server_error(&message)), None)),
+	    _ => timestamp = envelope.data.and_then(|data| data.timestamp).and_then(|timestamp| timestamp.as_f64()),
+	},
+	Err(_) => {},
+    }
+    Some((serde_json::from_str(&message).map_err(|error| RisError::Decode { error, message }), timestamp))
+}
+
+/// A frame that could not be decoded, as sent to `RisClient::dead_letters`
+#[derive(Debug)]
+pub struct DeadLetter {
+    /// Why the frame was rejected, either `RisError::Decode` or `RisError::Schema`
+    pub error: RisError,
+    /// When the frame arrived, in seconds since the epoch
+    pub received: f64,
+    /// The number the frame took in the stream of the connection it arrived on
+    pub sequence: Option<Sequence>,
+}
+
+impl DeadLetter {
+    /// Returns the dead letter for a frame that could not be decoded, stamped with the time by `clock`
+    /// and the number it took, or gives back any other result
+    pub(crate) fn of(received: Result<RisResponse, RisError>, clock: &dyn Clock, sequence: Option<Sequence>) -> Result<Result<RisResponse, RisError>, DeadLetter> {
+	match received {
+	    Err(error) if error.frame().is_some() => Err(DeadLetter { error, received: clock.epoch_seconds(), sequence }),
+	    received => Ok(received),
+	}
+    }
+
+    /// Returns the frame exactly as RIS Live sent it
+    pub fn frame(&self) -> &str {
+	self.error.frame().unwrap_or_default()
+    }
+}
+
+/// Frames that could not be decoded, returned by `RisClient::dead_letters`
+#[derive(Debug)]
+pub struct DeadLetters {
+    pub(crate) rx: flume::Receiver<DeadLetter>,
+}
+
+impl DeadLetters {
+    /// Waits for the next dead letter, returning `None` once the client and its streams are gone
+    pub async fn recv(&self) -> Option<DeadLetter> {
+	self.rx.recv_async().await.ok()
+    }
+
+    /// Blocks the thread until the next dead letter arrives, returning `None` once the client and its streams are gone
+    pub fn blocking_recv(&self) -> Option<DeadLetter> {
+	self.rx.recv().ok()
+    }
+
+    /// Returns the next dead letter if one has already arrived
+    pub fn try_recv(&self) -> Option<DeadLetter> {
+	self.rx.try_recv().ok()
+    }
+}
+
+impl Iterator for DeadLetters {
+    type Item = DeadLetter;
+
+    fn next(&mut self) -> Option<DeadLetter> {
+	self.blocking_recv()
+    }
+}
@@ -0,0 +1,213 @@
+//! Peer enrichment from PeeringDB
+//!
+//! Looks up the network behind a peer ASN on <https://www.peeringdb.com> so that
+//! session state and update statistics can be grouped by network type or by the
+//! exchanges a network is present at. Lookups are cached in memory and can be
+//! persisted to disk, as PeeringDB rate limits anonymous API users heavily.
+
+use std::collections::HashMap;
+use std::error;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::clock::{self, Clock};
+use crate::RisResponseData;
+
+/// The subset of a PeeringDB network record useful for grouping peers
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeeringDbNetwork {
+    pub asn: u32,
+    pub name: String,
+    /// The network type, such as "NSP", "Content" or "Cable/DSL/ISP"
+    pub info_type: String,
+    /// Names of the exchanges the network has a port at
+    pub ixps: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CacheEntry {
+    fetched: u64,
+    network: Option<PeeringDbNetwork>,
+}
+
+#[derive(Deserialize)]
+struct NetResponse {
+    data: Vec<NetRecord>,
+}
+
+#[derive(Deserialize)]
+struct NetRecord {
+    asn: u32,
+    name: String,
+    #[serde(default)]
+    info_type: String,
+    #[serde(default)]
+    netixlan_set: Vec<NetIxLanRecord>,
+}
+
+#[derive(Deserialize)]
+struct NetIxLanRecord {
+    #[serde(default)]
+    name: String,
+}
+
+///
+/// Enriches peer ASNs with PeeringDB data.
+/// Both found and missing networks are cached for `ttl`, so a busy stream
+/// results in at most one request per peer per `ttl`.
+///
+pub struct PeeringDbEnricher {
+    base_url: String,
+    api_key: Option<String>,
+    ttl: Duration,
+    cache_path: Option<PathBuf>,
+    cache: Mutex<HashMap<u32, CacheEntry>>,
+    http: reqwest::Client,
+    clock: Arc<dyn Clock>,
+}
+
+impl PeeringDbEnricher {
+
+    /// Returns a PeeringDbEnricher using the public PeeringDB API and a one day cache lifetime
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use risclient::peeringdb::PeeringDbEnricher;
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let enricher = PeeringDbEnricher::new().with_cache_file("peeringdb.json".into());
+    /// enricher.load_cache().unwrap();
+    /// if let Some(network) = enricher.lookup(3333).await.unwrap() {
+    ///     println!("{} is a {} network at {} exchanges", network.name, network.info_type, network.ixps.len());
+    /// }
+    /// enricher.save_cache().unwrap();
+    /// # }
+    /// ```
+    pub fn new() -> PeeringDbEnricher {
+	PeeringDbEnricher {
+	    base_url: "https://www.peeringdb.com/api".to_string(),
+	    api_key: None,
+	    ttl: Duration::from_secs(86400),
+	    cache_path: None,
+	    cache: Mutex::new(HashMap::new()),
+	    http: reqwest::Client::new(),
+	    clock: clock::system(),
+	}
+    }
+
+    /// Uses a different API endpoint, such as a local PeeringDB mirror
+    pub fn with_base_url(mut self, base_url: String) -> PeeringDbEnricher {
+	self.base_url = base_url;
+	self
+    }
+
+    /// Authenticates requests with a PeeringDB API key, which raises the rate limit
+    pub fn with_api_key(mut self, api_key: String) -> PeeringDbEnricher {
+	self.api_key = Some(api_key);
+	self
+    }
+
+    /// Sets how long lookups are cached before being fetched again
+    pub fn with_ttl(mut self, ttl: Duration) -> PeeringDbEnricher {
+	self.ttl = ttl;
+	self
+    }
+
+    /// Ages cached lookups with `clock`
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> PeeringDbEnricher {
+	self.clock = clock;
+	self
+    }
+
+    /// Persists the cache to `path` when `save_cache` is called, and reads it back with `load_cache`
+    pub fn with_cache_file(mut self, path: PathBuf) -> PeeringDbEnricher {
+	self.cache_path = Some(path);
+	self
+    }
+
+    /// Loads previously saved lookups from the cache file, if one is configured and exists
+    pub fn load_cache(&self) -> Result<(), Box<dyn error::Error>> {
+	let path = match &self.cache_path {
+	    Some(path) if path.exists() => path,
+	    _ => return Ok(()),
+	};
+	let json = std::fs::read_to_string(path)?;
+	let entries: HashMap<u32, CacheEntry> = serde_json::from_str(&json)?;
+	let mut cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+	cache.extend(entries);
+	Ok(())
+    }
+
+    /// Writes the current cache to the cache file, if one is configured
+    pub fn save_cache(&self) -> Result<(), Box<dyn error::Error>> {
+	let path = match &self.cache_path {
+	    Some(path) => path,
+	    None => return Ok(()),
+	};
+	let json = {
+	    let cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+	    serde_json::to_string(&*cache)?
+	};
+	std::fs::write(path, json)?;
+	Ok(())
+    }
+
+    // seconds since the epoch, as cache entries are stamped
+    fn now(&self) -> u64 {
+	self.clock.epoch_seconds() as u64
+    }
+
+    /// Returns the cached network for `asn` without making a request, if the cache entry is still fresh
+    pub fn cached(&self, asn: u32) -> Option<Option<PeeringDbNetwork>> {
+	let cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+	match cache.get(&asn) {
+	    Some(entry) if self.now().saturating_sub(entry.fetched) < self.ttl.as_secs() => Some(entry.network.clone()),
+	    _ => None,
+	}
+    }
+
+    /// Returns the PeeringDB network for `asn`, or `None` if the ASN has no PeeringDB record
+    pub async fn lookup(&self, asn: u32) -> Result<Option<PeeringDbNetwork>, Box<dyn error::Error>> {
+	if let Some(network) = self.cached(asn) {
+	    return Ok(network);
+	}
+	let url = format!("{}/net?asn={}&depth=2", self.base_url, asn);
+	let mut request = self.http.get(url);
+	if let Some(api_key) = &self.api_key {
+	    request = request.header("Authorization", format!("Api-Key {}", api_key));
+	}
+	let response = request.send().await?.error_for_status()?;
+	let body = response.text().await?;
+	let parsed: NetResponse = serde_json::from_str(&body)?;
+	let network = parsed.data.into_iter().next().map(|record| {
+	    let mut ixps: Vec<String> = record.netixlan_set.into_iter().map(|ixlan| ixlan.name).collect();
+	    ixps.sort();
+	    ixps.dedup();
+	    PeeringDbNetwork {
+		asn: record.asn,
+		name: record.name,
+		info_type: record.info_type,
+		ixps,
+	    }
+	});
+	let mut cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+	cache.insert(asn, CacheEntry { fetched: self.now(), network: network.clone() });
+	Ok(network)
+    }
+
+    /// Looks up the peer a RIS message was received from
+    pub async fn enrich(&self, data: &RisResponseData) -> Result<Option<PeeringDbNetwork>, Box<dyn error::Error>> {
+	match data.peer_asn().parse::<u32>() {
+	    Ok(asn) => self.lookup(asn).await,
+	    Err(_) => Ok(None),
+	}
+    }
+}
+
+impl Default for PeeringDbEnricher {
+    fn default() -> PeeringDbEnricher {
+	PeeringDbEnricher::new()
+    }
+}
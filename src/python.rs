@@ -0,0 +1,254 @@
+//! Python bindings
+//!
+//! Exposes `RisClient`, `Subscription` and decoded messages as the `risclient`
+//! Python module, built with maturin from `pyproject.toml`. Streams are async
+//! iterators, so they fit straight into asyncio code:
+//!
+//! ```python
+//! import asyncio, risclient
+//!
+//! async def main():
+//!     client = risclient.RisClient()
+//!     stream = await client.subscribe(risclient.Subscription(host="rrc00", type="UPDATE"))
+//!     async for message in stream:
+//!         print(message.peer, message.path, message.announcements)
+//!
+//! asyncio.run(main())
+//! ```
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use pyo3::exceptions::{PyRuntimeError, PyStopAsyncIteration};
+use pyo3::prelude::*;
+
+use crate::{AsPathEntry, RisClient, RisError, RisReceiver, RisResponse, Subscription};
+
+/// A RIS Live client, connecting to ris-live.ripe.net unless told otherwise
+#[pyclass(name = "RisClient", module = "risclient")]
+struct PyRisClient {
+    host: String,
+    client_id: String,
+}
+
+#[pymethods]
+impl PyRisClient {
+    #[new]
+    #[pyo3(signature = (host = "ris-live.ripe.net".to_string(), client_id = "python-risclient".to_string()))]
+    fn new(host: String, client_id: String) -> PyRisClient {
+	PyRisClient { host, client_id }
+    }
+
+    /// Connects and subscribes, resolving to an async iterator of messages
+    #[pyo3(signature = (subscription = None))]
+    fn subscribe<'py>(&self, py: Python<'py>, subscription: Option<PySubscription>) -> PyResult<Bound<'py, PyAny>> {
+	let host = self.host.clone();
+	let client_id = self.client_id.clone();
+	let subscription = subscription.map(|subscription| subscription.inner).unwrap_or_default();
+	pyo3_async_runtimes::tokio::future_into_py(py, async move {
+	    let rx = subscribe(host, client_id, subscription).await.map_err(PyRuntimeError::new_err)?;
+	    Ok(PyRisStream { rx: Arc::new(rx) })
+	})
+    }
+}
+
+async fn subscribe(host: String, client_id: String, subscription: Subscription) -> Result<RisReceiver, String> {
+    let mut client = RisClient::new(host, client_id).map_err(|e| e.to_string())?;
+    client.subscribe(&subscription).await.map_err(|e| e.to_string())
+}
+
+/// Filters for a RIS Live subscription, given as keyword arguments
+#[pyclass(name = "Subscription", module = "risclient")]
+#[derive(Clone)]
+struct PySubscription {
+    inner: Subscription,
+}
+
+#[pymethods]
+impl PySubscription {
+    #[new]
+    #[pyo3(signature = (*, host = None, hosts = None, r#type = None, require = None, path = None, peer = None, prefix = None, more_specific = None, less_specific = None, include_raw = false, labels = None))]
+    #[allow(clippy::too_many_arguments)]
+    fn new(host: Option<&str>, hosts: Option<Vec<String>>, r#type: Option<&str>, require: Option<&str>, path: Option<Vec<u32>>, peer: Option<&str>, prefix: Option<&str>,
+	   more_specific: Option<bool>, less_specific: Option<bool>, include_raw: bool, labels: Option<BTreeMap<String, String>>) -> PySubscription {
+	let mut inner = Subscription::new().include_raw(include_raw);
+	if let Some(host) = host {
+	    inner = inner.host(host);
+	}
+	if let Some(hosts) = hosts {
+	    inner = inner.hosts(&hosts.iter().map(String::as_str).collect::<Vec<_>>());
+	}
+	if let Some(data_type) = r#type {
+	    inner = inner.data_type(data_type);
+	}
+	if let Some(require) = require {
+	    inner = inner.require(require);
+	}
+	if let Some(path) = path {
+	    inner = inner.path(path);
+	}
+	if let Some(peer) = peer {
+	    inner = inner.peer(peer);
+	}
+	if let Some(prefix) = prefix {
+	    inner = inner.prefix(prefix);
+	}
+	if let Some(more_specific) = more_specific {
+	    inner = inner.more_specific(more_specific);
+	}
+	if let Some(less_specific) = less_specific {
+	    inner = inner.less_specific(less_specific);
+	}
+	for (key, value) in labels.unwrap_or_default() {
+	    inner = inner.label(&key, &value);
+	}
+	PySubscription { inner }
+    }
+}
+
+/// An async iterator of messages from a subscription
+#[pyclass(name = "RisStream", module = "risclient")]
+struct PyRisStream {
+    rx: Arc<RisReceiver>,
+}
+
+#[pymethods]
+impl PyRisStream {
+    fn __aiter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+	slf
+    }
+
+    fn __anext__<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+	let rx = self.rx.clone();
+	pyo3_async_runtimes::tokio::future_into_py(py, async move {
+	    match rx.recv().await {
+		Ok(message) => Ok(PyRisMessage { inner: message }),
+		Err(RisError::Closed) => Err(PyStopAsyncIteration::new_err("stream closed")),
+		Err(e) => Err(PyRuntimeError::new_err(e.to_string())),
+	    }
+	})
+    }
+}
+
+/// A message received from RIS Live
+#[pyclass(name = "RisMessage", module = "risclient")]
+struct PyRisMessage {
+    inner: RisResponse,
+}
+
+#[pymethods]
+impl PyRisMessage {
+    /// The message type, such as "ris_message"
+    #[getter]
+    fn message_type(&self) -> &str {
+	self.inner.message_type()
+    }
+
+    /// The BGP message type, such as "UPDATE"
+    #[getter]
+    fn r#type(&self) -> &str {
+	self.inner.data().data_type()
+    }
+
+    #[getter]
+    fn timestamp(&self) -> f64 {
+	self.inner.data().timestamp()
+    }
+
+    #[getter]
+    fn peer(&self) -> &str {
+	self.inner.data().peer()
+    }
+
+    #[getter]
+    fn peer_asn(&self) -> &str {
+	self.inner.data().peer_asn()
+    }
+
+    #[getter]
+    fn id(&self) -> &str {
+	self.inner.data().id()
+    }
+
+    #[getter]
+    fn host(&self) -> &str {
+	self.inner.data().host()
+    }
+
+    /// The AS path, with AS_SETs as nested lists
+    #[getter]
+    fn path(&self, py: Python<'_>) -> Vec<PyObject> {
+	self.inner.data().path().iter().map(|entry| match entry {
+	    AsPathEntry::Asn(asn) => asn.into_py(py),
+	    AsPathEntry::Set(set) => set.clone().into_py(py),
+	}).collect()
+    }
+
+    #[getter]
+    fn community(&self) -> Vec<(u32, u32)> {
+	self.inner.data().community().to_vec()
+    }
+
+    #[getter]
+    fn origin(&self) -> Option<&str> {
+	self.inner.data().origin()
+    }
+
+    #[getter]
+    fn med(&self) -> Option<u32> {
+	self.inner.data().med()
+    }
+
+    #[getter]
+    fn aggregator(&self) -> Option<&str> {
+	self.inner.data().aggregator()
+    }
+
+    /// Announcements as (next_hop, [prefixes]) tuples
+    #[getter]
+    fn announcements(&self) -> Vec<(String, Vec<String>)> {
+	self.inner.data().announcements().iter()
+	    .map(|announcement| (announcement.next_hop().to_string(), announcement.prefixes().to_vec()))
+	    .collect()
+    }
+
+    #[getter]
+    fn withdrawals(&self) -> Vec<String> {
+	self.inner.data().withdrawals().to_vec()
+    }
+
+    #[getter]
+    fn state(&self) -> Option<&str> {
+	self.inner.data().state()
+    }
+
+    #[getter]
+    fn raw(&self) -> Option<&str> {
+	self.inner.data().raw()
+    }
+
+    /// The labels of the subscription the message came from
+    #[getter]
+    fn labels(&self) -> BTreeMap<String, String> {
+	self.inner.data().labels().clone()
+    }
+
+    /// Returns the message re-serialised as RIS Live JSON
+    fn json(&self) -> PyResult<String> {
+	serde_json::to_string(&self.inner).map_err(|e| PyRuntimeError::new_err(e.to_string()))
+    }
+
+    fn __repr__(&self) -> String {
+	let data = self.inner.data();
+	format!("RisMessage(type={:?}, host={:?}, peer={:?}, timestamp={})", data.data_type(), data.host(), data.peer(), data.timestamp())
+    }
+}
+
+#[pymodule]
+fn risclient(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyRisClient>()?;
+    m.add_class::<PySubscription>()?;
+    m.add_class::<PyRisStream>()?;
+    m.add_class::<PyRisMessage>()?;
+    Ok(())
+}
@@ -0,0 +1,764 @@
+//! The receiving end of a subscription
+
+use std::cmp;
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::clock::{self, Clock};
+use crate::{collectors, DeadLetter, RisError, RisResponse};
+
+/// How often `RisReceiver::reorder` checks for held messages to release while the stream is quiet
+const REORDER_POLL: Duration = Duration::from_millis(100);
+
+/// How often a stream being drained checks whether its messages have all been received
+const DRAIN_POLL: Duration = Duration::from_millis(10);
+
+/// How often an adapter checks whether the receiver it feeds has been dropped, while its upstream is quiet
+const CLOSED_POLL: Duration = Duration::from_millis(100);
+
+/// The last connection identifier handed out, see `Sequence::connection`
+static LAST_CONNECTION: AtomicU64 = AtomicU64::new(0);
+
+///
+/// Where a message falls in the stream that delivered it, for sinks that need to ingest exactly once.
+/// `(connection, message)` identifies a message uniquely, and a gap in `message` or `collector` means
+/// messages were lost in between. A frame that failed to decode still takes a number, and one sent to
+/// `RisClient::dead_letters` carries it in `DeadLetter::sequence`, so its gap can be told apart from a loss.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Sequence {
+    /// Identifies the connection: the time it opened, in milliseconds since the epoch, made unique
+    /// within the process. A later connection, including one after a restart, has a larger identifier.
+    pub connection: u64,
+    /// The message's place among everything the connection delivered, from 1
+    pub message: u64,
+    /// The message's place among those from the same collector, from 1, or 0 for a dead letter,
+    /// whose collector is not known
+    #[serde(alias = "subscription")]
+    pub collector: u64,
+}
+
+///
+/// A delivered message with where and when it was received, from `RisReceiver::enveloped`,
+/// so that processing and audit downstream know its provenance without side channels.
+///
+/// # Examples
+///
+/// ```
+/// use risclient::RisResponse;
+/// use risclient::record::{self, Recorder};
+/// let path = std::env::temp_dir().join("risclient-envelope-example.risjsonl");
+/// let message: RisResponse = serde_json::from_str(r#"{"type": "ris_message", "data": {"timestamp": 1650000000.25,
+///     "peer": "192.0.2.1", "peer_asn": "64500", "id": "1", "host": "rrc21.ripe.net", "type": "KEEPALIVE"}}"#).unwrap();
+/// let mut recorder = Recorder::create(&path).unwrap();
+/// recorder.record_at(&message, 1650000001.5).unwrap();
+/// recorder.flush().unwrap();
+/// let envelope = record::replay(&path, 0.0).unwrap().enveloped().blocking_recv().unwrap();
+/// assert_eq!((envelope.collector.as_str(), envelope.received, envelope.sequence.message), ("rrc21", 1650000001.5, 1));
+/// # std::fs::remove_file(&path).unwrap();
+/// ```
+#[derive(Debug, Clone, Serialize)]
+pub struct Envelope {
+    /// When this client received the message, in seconds since the epoch
+    pub received: f64,
+    /// The collector that received the message from its peer, such as "rrc00"
+    pub collector: String,
+    /// The labels of the subscription that delivered the message
+    pub labels: BTreeMap<String, String>,
+    /// The connection the message arrived on and its place in that connection's stream
+    pub sequence: Sequence,
+    pub message: RisResponse,
+}
+
+impl From<RisResponse> for Envelope {
+    fn from(message: RisResponse) -> Envelope {
+	let data = &message.data;
+	Envelope {
+	    received: data.received.unwrap_or_default(),
+	    collector: collectors::short_name(&data.host).to_string(),
+	    labels: data.labels.clone(),
+	    sequence: data.sequence.unwrap_or(Sequence { connection: 0, message: 0, collector: 0 }),
+	    message,
+	}
+    }
+}
+
+// numbers the messages of one connection
+struct Numbering {
+    connection: u64,
+    sent: u64,
+    collectors: HashMap<String, u64>,
+}
+
+/// The sending end of a RisReceiver, which flags messages older than one it already sent, and
+/// numbers and stamps with when they were received those that are not numbered yet if it belongs to a connection
+pub(crate) struct RisSender {
+    tx: flume::Sender<Result<RisResponse, RisError>>,
+    latest: f64,
+    numbering: Option<Numbering>,
+    clock: Arc<dyn Clock>,
+    // where frames that fail to decode go instead once numbered, see `RisClient::dead_letters`
+    dead_letters: Option<flume::Sender<DeadLetter>>,
+}
+
+impl RisSender {
+    /// Sends frames that fail to decode to `dead_letters` rather than the receiver, if given
+    pub(crate) fn with_dead_letters(mut self, dead_letters: Option<flume::Sender<DeadLetter>>) -> RisSender {
+	self.dead_letters = dead_letters;
+	self
+    }
+
+    /// Sends a message, failing with `RisError::Closed` once the receiver has been dropped
+    pub(crate) fn send(&mut self, mut received: Result<RisResponse, RisError>) -> Result<(), RisError> {
+	if let Some(numbering) = &mut self.numbering {
+	    numbering.sent += 1;
+	}
+	if let Some(dead_letters) = &self.dead_letters {
+	    let sequence = self.numbering.as_ref().map(|numbering| Sequence { connection: numbering.connection, message: numbering.sent, collector: 0 });
+	    received = match DeadLetter::of(received, self.clock.as_ref(), sequence) {
+		Ok(received) => received,
+		Err(dead_letter) => {
+		    let _ = dead_letters.send(dead_letter);
+		    return Ok(());
+		},
+	    };
+	}
+	if let Ok(response) = &mut received {
+	    let timestamp = response.data.timestamp;
+	    response.data.out_of_order = timestamp > 0.0 && timestamp < self.latest;
+	    self.latest = self.latest.max(timestamp);
+	    if let (Some(numbering), None) = (&mut self.numbering, response.data.sequence) {
+		let collector = numbering.collectors.entry(collectors::short_name(&response.data.host).to_string()).or_default();
+		*collector += 1;
+		response.data.sequence = Some(Sequence { connection: numbering.connection, message: numbering.sent, collector: *collector });
+	    }
+	    if let (Some(_), None) = (&self.numbering, response.data.received) {
+		response.data.received = Some(self.clock.epoch_seconds());
+	    }
+	}
+	self.tx.send(received).map_err(|_| RisError::Closed)
+    }
+
+    /// Returns true once the receiver has been dropped
+    pub(crate) fn is_disconnected(&self) -> bool {
+	self.tx.is_disconnected()
+    }
+
+}
+
+/// The sending end of a stream a connection's reader delivers to
+pub(crate) trait Deliver<T>: Send + 'static {
+    /// Sends a message, failing with `RisError::Closed` once the receiver has been dropped
+    fn deliver(&mut self, received: Result<T, RisError>) -> Result<(), RisError>;
+
+    /// Returns the channel the messages go through, to wait on with `flushed`
+    fn channel(&self) -> &flume::Sender<Result<T, RisError>>;
+}
+
+impl Deliver<RisResponse> for RisSender {
+    fn deliver(&mut self, received: Result<RisResponse, RisError>) -> Result<(), RisError> {
+	self.send(received)
+    }
+
+    fn channel(&self) -> &flume::Sender<Result<RisResponse, RisError>> {
+	&self.tx
+    }
+}
+
+impl<T: Send + 'static> Deliver<T> for flume::Sender<Result<T, RisError>> {
+    fn deliver(&mut self, received: Result<T, RisError>) -> Result<(), RisError> {
+	self.send(received).map_err(|_| RisError::Closed)
+    }
+
+    fn channel(&self) -> &flume::Sender<Result<T, RisError>> {
+	self
+    }
+}
+
+/// Waits until every message sent on `tx` has been received, or the receiver has been dropped
+pub(crate) async fn flushed<T>(tx: &flume::Sender<T>, clock: &dyn Clock) {
+    while !tx.is_empty() && !tx.is_disconnected() {
+	clock.sleep(DRAIN_POLL).await;
+    }
+}
+
+/// Waits until the receiver of `tx` has been dropped, so that an adapter stops even if its upstream never sends again
+async fn closed<T>(tx: &flume::Sender<T>, clock: &dyn Clock) {
+    while !tx.is_disconnected() {
+	clock.sleep(CLOSED_POLL).await;
+    }
+}
+
+/// Returns a channel whose receiver times its adapters with `clock`
+pub(crate) fn channel(clock: Arc<dyn Clock>) -> (RisSender, RisReceiver) {
+    let (tx, rx) = flume::unbounded();
+    (RisSender { tx, latest: 0.0, numbering: None, clock: clock.clone(), dead_letters: None }, RisReceiver { rx, clock })
+}
+
+/// Returns a channel for a new connection, which numbers the messages sent on it and stamps them
+/// with when they were received
+pub(crate) fn connection_channel(clock: Arc<dyn Clock>) -> (RisSender, RisReceiver) {
+    let opened = (clock.epoch_seconds() * 1000.0) as u64;
+    let previous = LAST_CONNECTION.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |last| Some(opened.max(last + 1))).unwrap_or_default();
+    let (mut tx, rx) = channel(clock);
+    tx.numbering = Some(Numbering { connection: opened.max(previous + 1), sent: 0, collectors: HashMap::new() });
+    (tx, rx)
+}
+
+/// A message held by `RisReceiver::reorder`, ordered by timestamp and then arrival
+struct Held {
+    timestamp: f64,
+    sequence: u64,
+    arrived: Instant,
+    response: RisResponse,
+}
+
+impl PartialEq for Held {
+    fn eq(&self, other: &Held) -> bool {
+	self.cmp(other) == cmp::Ordering::Equal
+    }
+}
+
+impl Eq for Held {}
+
+impl PartialOrd for Held {
+    fn partial_cmp(&self, other: &Held) -> Option<cmp::Ordering> {
+	Some(self.cmp(other))
+    }
+}
+
+impl Ord for Held {
+    // reversed, so the BinaryHeap pops the earliest message first
+    fn cmp(&self, other: &Held) -> cmp::Ordering {
+	other.timestamp.total_cmp(&self.timestamp).then(other.sequence.cmp(&self.sequence))
+    }
+}
+
+///
+/// Messages from a subscription, in the order RIS Live sent them.
+/// Messages that fail to decode are passed on as errors without ending the stream,
+/// and the stream ends when the connection does. Messages are flagged with
+/// `RisResponseData::is_out_of_order` when they are older than one delivered before them,
+/// which happens across collectors; `reorder` puts them back in order at the cost of a delay.
+///
+#[derive(Debug)]
+pub struct RisReceiver {
+    rx: flume::Receiver<Result<RisResponse, RisError>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl RisReceiver {
+
+    /// Times the adapters built on this receiver, such as `idle_timeout` and `reorder`, with `clock`
+    /// rather than the clock of the client or replay it came from
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> RisReceiver {
+	self.clock = clock;
+	self
+    }
+
+    /// Waits for the next message, returning `RisError::Closed` once the stream has ended
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use risclient::{RisClient, RisError, Subscription};
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let mut client = RisClient::default().unwrap();
+    /// let rx = client.subscribe(&Subscription::new().host("rrc00")).await.unwrap();
+    /// loop {
+    ///     match rx.recv().await {
+    ///         Ok(message) => println!("{:?}", message),
+    ///         Err(RisError::Closed) => break,
+    ///         Err(e) => eprintln!("{}", e),
+    ///     }
+    /// }
+    /// # }
+    /// ```
+    pub async fn recv(&self) -> Result<RisResponse, RisError> {
+	self.rx.recv_async().await.unwrap_or(Err(RisError::Closed))
+    }
+
+    /// Blocks the thread until the next message arrives, returning `RisError::Closed` once the stream has ended.
+    /// This must not be called from async code, where `recv` should be awaited instead.
+    pub fn blocking_recv(&self) -> Result<RisResponse, RisError> {
+	self.rx.recv().unwrap_or(Err(RisError::Closed))
+    }
+
+    /// Blocks for up to `timeout` waiting for the next message, returning `RisError::Timeout` if none arrives
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<RisResponse, RisError> {
+	match self.rx.recv_timeout(timeout) {
+	    Ok(received) => received,
+	    Err(flume::RecvTimeoutError::Timeout) => Err(RisError::Timeout),
+	    Err(flume::RecvTimeoutError::Disconnected) => Err(RisError::Closed),
+	}
+    }
+
+    /// Returns the next message if one has already arrived, or `RisError::Empty`
+    pub fn try_recv(&self) -> Result<RisResponse, RisError> {
+	match self.rx.try_recv() {
+	    Ok(received) => received,
+	    Err(flume::TryRecvError::Empty) => Err(RisError::Empty),
+	    Err(flume::TryRecvError::Disconnected) => Err(RisError::Closed),
+	}
+    }
+
+    /// Returns a blocking iterator over messages, which ends with the stream
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use risclient::{RisClient, Subscription};
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let mut client = RisClient::default().unwrap();
+    /// let rx = client.subscribe(&Subscription::new().host("rrc00")).await.unwrap();
+    /// for message in rx.iter() {
+    ///     match message {
+    ///         Ok(message) => println!("{:?}", message),
+    ///         Err(e) => eprintln!("{}", e),
+    ///     }
+    /// }
+    /// # }
+    /// ```
+    pub fn iter(&self) -> Iter<'_> {
+	Iter { rx: self }
+    }
+
+    /// Calls `f` with each message, waiting for the future it returns before taking the next,
+    /// until the stream ends. Messages that fail to decode are skipped.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use risclient::{RisClient, Subscription};
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let mut client = RisClient::default().unwrap();
+    /// let rx = client.subscribe(&Subscription::new().host("rrc00")).await.unwrap();
+    /// rx.for_each(|message| async move {
+    ///     println!("{:?}", message);
+    /// }).await;
+    /// # }
+    /// ```
+    pub async fn for_each<F, Fut>(self, mut f: F)
+    where
+	F: FnMut(RisResponse) -> Fut,
+	Fut: Future<Output = ()>,
+    {
+	loop {
+	    match self.recv().await {
+		Ok(message) => f(message).await,
+		Err(RisError::Closed) => break,
+		Err(_) => continue,
+	    }
+	}
+    }
+
+    /// Returns a receiver that passes on this one's messages, ending with `RisError::Idle`
+    /// if nothing arrives for `idle`, so a stalled connection does not hang a pipeline forever
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::time::Duration;
+    /// use risclient::{RisClient, RisError, Subscription};
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let mut client = RisClient::default().unwrap();
+    /// let rx = client.subscribe(&Subscription::new().host("rrc00")).await.unwrap().idle_timeout(Duration::from_secs(30));
+    /// loop {
+    ///     match rx.recv().await {
+    ///         Ok(message) => println!("{:?}", message),
+    ///         Err(e @ RisError::Idle(_)) => panic!("{}", e),
+    ///         Err(_) => break,
+    ///     }
+    /// }
+    /// # }
+    /// ```
+    ///
+    /// Adapters run as tasks timed by the receiver's clock, so a paused `clock::TokioClock` decides when they fire:
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use std::time::Duration;
+    /// use risclient::{RisError, RisResponse};
+    /// use risclient::clock::{Clock, TokioClock};
+    /// use risclient::record::{self, Recorder};
+    /// # #[tokio::main(flavor = "current_thread", start_paused = true)]
+    /// # async fn main() {
+    /// let path = std::env::temp_dir().join("risclient-idle-example.risjsonl");
+    /// let message: RisResponse = serde_json::from_str(r#"{"type": "ris_message", "data": {"host": "rrc00", "type": "KEEPALIVE"}}"#).unwrap();
+    /// let mut recorder = Recorder::create(&path).unwrap();
+    /// recorder.record_at(&message, 1650000000.0).unwrap();
+    /// recorder.record_at(&message, 1650000060.0).unwrap();
+    /// recorder.flush().unwrap();
+    /// let clock = Arc::new(TokioClock::new());
+    /// let rx = record::replay_with_clock(&path, 1.0, clock.clone()).unwrap().idle_timeout(Duration::from_secs(30));
+    /// let started = clock.now();
+    /// assert!(rx.recv().await.is_ok());
+    /// assert!(matches!(rx.recv().await, Err(RisError::Idle(_))));
+    /// assert_eq!(clock.now() - started, Duration::from_secs(30));
+    /// # std::fs::remove_file(&path).unwrap();
+    /// # }
+    /// ```
+    pub fn idle_timeout(self, idle: Duration) -> RisReceiver {
+	let (mut tx, rx) = channel(self.clock.clone());
+	clock::spawn(async move {
+	    loop {
+		let received = tokio::select! {
+		    received = self.recv() => match received {
+			Err(RisError::Closed) => break,
+			received => received,
+		    },
+		    _ = self.clock.sleep(idle) => Err(RisError::Idle(idle)),
+		    _ = closed(&tx.tx, self.clock.as_ref()) => break,
+		};
+		let idled = matches!(received, Err(RisError::Idle(_)));
+		if tx.send(received).is_err() || idled {
+		    break;
+		}
+	    }
+	});
+	rx
+    }
+
+    /// Returns a receiver that passes on this one's messages until `deadline` by the receiver's clock,
+    /// such as `clock.now() + Duration::from_secs(60)`, and then ends
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use std::time::Duration;
+    /// use risclient::RisResponse;
+    /// use risclient::clock::{Clock, TokioClock};
+    /// use risclient::record::{self, Recorder};
+    /// # #[tokio::main(flavor = "current_thread", start_paused = true)]
+    /// # async fn main() {
+    /// let path = std::env::temp_dir().join("risclient-take-until-example.risjsonl");
+    /// let message: RisResponse = serde_json::from_str(r#"{"type": "ris_message", "data": {"host": "rrc00", "type": "KEEPALIVE"}}"#).unwrap();
+    /// let mut recorder = Recorder::create(&path).unwrap();
+    /// for second in [0.0, 10.0, 20.0, 90.0] {
+    ///     recorder.record_at(&message, 1650000000.0 + second).unwrap();
+    /// }
+    /// recorder.flush().unwrap();
+    /// let clock = Arc::new(TokioClock::new());
+    /// let started = clock.now();
+    /// let rx = record::replay_with_clock(&path, 1.0, clock.clone()).unwrap().take_until(started + Duration::from_secs(60));
+    /// let mut received = 0;
+    /// while rx.recv().await.is_ok() {
+    ///     received += 1;
+    /// }
+    /// assert_eq!((received, clock.now() - started), (3, Duration::from_secs(60)));
+    /// # std::fs::remove_file(&path).unwrap();
+    /// # }
+    /// ```
+    pub fn take_until(self, deadline: Instant) -> RisReceiver {
+	let (mut tx, rx) = channel(self.clock.clone());
+	clock::spawn(async move {
+	    let mut deadline = self.clock.sleep(deadline.saturating_duration_since(self.clock.now()));
+	    loop {
+		let received = tokio::select! {
+		    received = self.recv() => match received {
+			Err(RisError::Closed) => break,
+			received => received,
+		    },
+		    _ = &mut deadline => break,
+		    _ = closed(&tx.tx, self.clock.as_ref()) => break,
+		};
+		if tx.send(received).is_err() {
+		    break;
+		}
+	    }
+	});
+	rx
+    }
+
+    /// Returns a receiver of the same messages, each wrapped in an `Envelope` carrying when and where it
+    /// was received
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use risclient::{RisClient, Subscription};
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let mut client = RisClient::default().unwrap();
+    /// let rx = client.subscribe(&Subscription::new().host("rrc00").label("team", "noc")).await.unwrap().enveloped();
+    /// while let Ok(envelope) = rx.recv().await {
+    ///     println!("{} from {} at {}: {:?}", envelope.sequence.message, envelope.collector, envelope.received, envelope.labels);
+    /// }
+    /// # }
+    /// ```
+    pub fn enveloped(self) -> TypedReceiver<Envelope> {
+	let (tx, rx) = flume::unbounded();
+	clock::spawn(async move {
+	    loop {
+		let received = tokio::select! {
+		    received = self.recv() => match received {
+			Err(RisError::Closed) => break,
+			received => received,
+		    },
+		    _ = closed(&tx, self.clock.as_ref()) => break,
+		};
+		if tx.send(received.map(Envelope::from)).is_err() {
+		    break;
+		}
+	    }
+	});
+	TypedReceiver::new(rx)
+    }
+
+    /// Returns a receiver that holds each message for up to `window`, delivering them in timestamp order.
+    /// A message is released once one at least `window` newer has arrived, or once it has been held for
+    /// `window`, so a message delayed by more than that is still delivered, flagged as out of order.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::time::Duration;
+    /// use risclient::{RisClient, Subscription};
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let mut client = RisClient::default().unwrap();
+    /// let rx = client.subscribe(&Subscription::new().hosts(&["rrc00", "rrc21"])).await.unwrap().reorder(Duration::from_secs(2));
+    /// while let Ok(message) = rx.recv().await {
+    ///     assert!(!message.data().is_out_of_order());
+    /// }
+    /// # }
+    /// ```
+    pub fn reorder(self, window: Duration) -> RisReceiver {
+	let (mut tx, rx) = channel(self.clock.clone());
+	clock::spawn(async move {
+	    let mut held = BinaryHeap::new();
+	    let mut sequence = 0;
+	    let mut newest = 0.0f64;
+	    loop {
+		// nothing held, so nothing to release until the next message
+		let poll = if held.is_empty() { CLOSED_POLL } else { window.min(REORDER_POLL) };
+		let received = tokio::select! {
+		    received = self.recv() => received,
+		    _ = self.clock.sleep(poll) => Err(RisError::Timeout),
+		};
+		if tx.is_disconnected() {
+		    return;
+		}
+		match received {
+		    Ok(response) => {
+			newest = newest.max(response.data.timestamp);
+			held.push(Held { timestamp: response.data.timestamp, sequence, arrived: self.clock.now(), response });
+			sequence += 1;
+		    },
+		    Err(RisError::Closed) => break,
+		    Err(RisError::Timeout) => {},
+		    Err(e) => {
+			if tx.send(Err(e)).is_err() {
+			    return;
+			}
+		    },
+		}
+		let now = self.clock.now();
+		while let Some(next) = held.peek() {
+		    if next.timestamp > newest - window.as_secs_f64() && now.saturating_duration_since(next.arrived) < window {
+			break;
+		    }
+		    let next = held.pop().map(|next| next.response);
+		    if next.is_some_and(|next| tx.send(Ok(next)).is_err()) {
+			return;
+		    }
+		}
+	    }
+	    while let Some(next) = held.pop() {
+		if tx.send(Ok(next.response)).is_err() {
+		    return;
+		}
+	    }
+	});
+	rx
+    }
+
+    /// Groups messages into batches of up to `size`, handing a batch on early once `timeout`
+    /// has passed since its first message. Messages that fail to decode are skipped.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::time::Duration;
+    /// use risclient::{RisClient, Subscription};
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let mut client = RisClient::default().unwrap();
+    /// let rx = client.subscribe(&Subscription::new().host("rrc00")).await.unwrap();
+    /// let batches = rx.chunks_timeout(1000, Duration::from_secs(5));
+    /// while let Some(batch) = batches.recv().await {
+    ///     println!("writing {} messages", batch.len());
+    /// }
+    /// # }
+    /// ```
+    pub fn chunks_timeout(self, size: usize, timeout: Duration) -> Chunks {
+	let size = size.max(1);
+	let (tx, rx) = flume::unbounded();
+	clock::spawn(async move {
+	    let mut chunk = Vec::with_capacity(size);
+	    let mut started = self.clock.now();
+	    loop {
+		let received = if chunk.is_empty() {
+		    tokio::select! {
+			received = self.recv() => received,
+			_ = closed(&tx, self.clock.as_ref()) => return,
+		    }
+		} else {
+		    let remaining = timeout.saturating_sub(self.clock.now().saturating_duration_since(started));
+		    tokio::select! {
+			received = self.recv() => received,
+			_ = self.clock.sleep(remaining) => Err(RisError::Timeout),
+		    }
+		};
+		match received {
+		    Ok(message) => {
+			if chunk.is_empty() {
+			    started = self.clock.now();
+			}
+			chunk.push(message);
+			if chunk.len() < size {
+			    continue;
+			}
+		    },
+		    Err(RisError::Closed) => break,
+		    Err(RisError::Timeout) => {},
+		    Err(_) => continue,
+		}
+		if tx.send(std::mem::replace(&mut chunk, Vec::with_capacity(size))).is_err() {
+		    return;
+		}
+	    }
+	    if !chunk.is_empty() {
+		let _ = tx.send(chunk);
+	    }
+	});
+	Chunks { rx }
+    }
+}
+
+/// Batches of messages from `RisReceiver::chunks_timeout`, ending with the stream
+#[derive(Debug)]
+pub struct Chunks {
+    rx: flume::Receiver<Vec<RisResponse>>,
+}
+
+impl Chunks {
+    /// Waits for the next batch, returning `None` once the stream has ended
+    pub async fn recv(&self) -> Option<Vec<RisResponse>> {
+	self.rx.recv_async().await.ok()
+    }
+
+    /// Blocks the thread until the next batch is ready, returning `None` once the stream has ended
+    pub fn blocking_recv(&self) -> Option<Vec<RisResponse>> {
+	self.rx.recv().ok()
+    }
+}
+
+impl Iterator for Chunks {
+    type Item = Vec<RisResponse>;
+
+    fn next(&mut self) -> Option<Vec<RisResponse>> {
+	self.blocking_recv()
+    }
+}
+
+/// A blocking iterator over a RisReceiver's messages, returned by `RisReceiver::iter`
+pub struct Iter<'a> {
+    rx: &'a RisReceiver,
+}
+
+impl Iterator for Iter<'_> {
+    type Item = Result<RisResponse, RisError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+	self.rx.rx.recv().ok()
+    }
+}
+
+/// A blocking iterator that owns a RisReceiver, returned by `RisReceiver::into_iter`
+pub struct IntoIter {
+    rx: RisReceiver,
+}
+
+impl Iterator for IntoIter {
+    type Item = Result<RisResponse, RisError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+	self.rx.rx.recv().ok()
+    }
+}
+
+impl IntoIterator for RisReceiver {
+    type Item = Result<RisResponse, RisError>;
+    type IntoIter = IntoIter;
+
+    fn into_iter(self) -> IntoIter {
+	IntoIter { rx: self }
+    }
+}
+
+impl<'a> IntoIterator for &'a RisReceiver {
+    type Item = Result<RisResponse, RisError>;
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Iter<'a> {
+	self.iter()
+    }
+}
+
+/// Messages from `RisClient::stream_as`, each deserialised into `T`.
+/// Frames that will not deserialise are passed on as `RisError::Decode` without ending the stream.
+#[derive(Debug)]
+pub struct TypedReceiver<T> {
+    rx: flume::Receiver<Result<T, RisError>>,
+}
+
+impl<T> TypedReceiver<T> {
+    pub(crate) fn new(rx: flume::Receiver<Result<T, RisError>>) -> TypedReceiver<T> {
+	TypedReceiver { rx }
+    }
+
+    /// Waits for the next message, returning `RisError::Closed` once the stream has ended
+    pub async fn recv(&self) -> Result<T, RisError> {
+	self.rx.recv_async().await.unwrap_or(Err(RisError::Closed))
+    }
+
+    /// Blocks the thread until the next message arrives, returning `RisError::Closed` once the stream has ended
+    pub fn blocking_recv(&self) -> Result<T, RisError> {
+	self.rx.recv().unwrap_or(Err(RisError::Closed))
+    }
+
+    /// Blocks for up to `timeout` waiting for the next message, returning `RisError::Timeout` if none arrives
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RisError> {
+	match self.rx.recv_timeout(timeout) {
+	    Ok(received) => received,
+	    Err(flume::RecvTimeoutError::Timeout) => Err(RisError::Timeout),
+	    Err(flume::RecvTimeoutError::Disconnected) => Err(RisError::Closed),
+	}
+    }
+
+    /// Returns the next message if one has already arrived, or `RisError::Empty`
+    pub fn try_recv(&self) -> Result<T, RisError> {
+	match self.rx.try_recv() {
+	    Ok(received) => received,
+	    Err(flume::TryRecvError::Empty) => Err(RisError::Empty),
+	    Err(flume::TryRecvError::Disconnected) => Err(RisError::Closed),
+	}
+    }
+}
+
+impl<T> Iterator for TypedReceiver<T> {
+    type Item = Result<T, RisError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+	self.rx.recv().ok()
+    }
+}
@@ -0,0 +1,212 @@
+//! Recording and replaying streams
+//!
+//! Captures are JSON lines files, conventionally named `*.risjsonl`, holding
+//! one `RecordedMessage` per line: the message exactly as RIS Live sent it,
+//! alongside the time it was received. Replaying a capture produces the same
+//! `Receiver` a live subscription does, optionally paced to match the original
+//! arrival times, so code can be exercised against a past incident unchanged.
+
+use std::error;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::clock::{self, Clock};
+use crate::{receiver, RisReceiver, RisResponse};
+
+/// A message as stored in a capture
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedMessage {
+    /// When the message was received, in seconds since the epoch
+    pub received: f64,
+    pub message: RisResponse,
+}
+
+/// Writes messages to a capture
+pub struct Recorder<W: Write> {
+    out: W,
+    recorded: u64,
+    clock: Arc<dyn Clock>,
+}
+
+impl Recorder<BufWriter<File>> {
+    /// Creates a capture file at `path`, replacing any existing file
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Recorder<BufWriter<File>>> {
+	Ok(Recorder::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W: Write> Recorder<W> {
+
+    /// Returns a Recorder writing to `out`
+    pub fn new(out: W) -> Recorder<W> {
+	Recorder { out, recorded: 0, clock: clock::system() }
+    }
+
+    /// Takes the time messages passed to `record` are received from `clock`
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Recorder<W> {
+	self.clock = clock;
+	self
+    }
+
+    /// Records a message as received now
+    pub fn record(&mut self, message: &RisResponse) -> io::Result<()> {
+	self.record_at(message, self.clock.epoch_seconds())
+    }
+
+    /// Records a message as received at `received`, in seconds since the epoch
+    pub fn record_at(&mut self, message: &RisResponse, received: f64) -> io::Result<()> {
+	let recorded = RecordedMessage { received, message: message.clone() };
+	serde_json::to_writer(&mut self.out, &recorded)?;
+	self.out.write_all(b"\n")?;
+	self.recorded += 1;
+	Ok(())
+    }
+
+    /// Returns how many messages have been recorded
+    pub fn recorded(&self) -> u64 {
+	self.recorded
+    }
+
+    /// Flushes any buffered messages to the underlying writer
+    pub fn flush(&mut self) -> io::Result<()> {
+	self.out.flush()
+    }
+}
+
+///
+/// Reads messages back from a capture, in the order they were recorded.
+/// Blank lines are skipped.
+///
+pub struct Replay<R: BufRead> {
+    lines: io::Lines<R>,
+}
+
+impl Replay<BufReader<File>> {
+    /// Opens the capture file at `path`
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Replay<BufReader<File>>> {
+	Ok(Replay::new(BufReader::new(File::open(path)?)))
+    }
+}
+
+impl<R: BufRead> Replay<R> {
+    /// Returns a Replay reading from `reader`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use risclient::RisResponse;
+    /// use risclient::record::{Recorder, Replay};
+    /// let message: RisResponse = serde_json::from_str(r#"{"type": "ris_message", "data": {"host": "rrc00", "type": "KEEPALIVE"}}"#).unwrap();
+    /// let mut capture = Vec::new();
+    /// let mut recorder = Recorder::new(&mut capture);
+    /// recorder.record_at(&message, 1650000000.0).unwrap();
+    /// let replayed: Vec<_> = Replay::new(capture.as_slice()).map(|recorded| recorded.unwrap()).collect();
+    /// assert_eq!(replayed[0].received, 1650000000.0);
+    /// assert_eq!(replayed[0].message.data().host(), "rrc00");
+    /// ```
+    pub fn new(reader: R) -> Replay<R> {
+	Replay { lines: reader.lines() }
+    }
+}
+
+impl<R: BufRead> Iterator for Replay<R> {
+    type Item = Result<RecordedMessage, Box<dyn error::Error + Send + Sync>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+	loop {
+	    let line = match self.lines.next()? {
+		Ok(line) => line,
+		Err(e) => return Some(Err(Box::new(e))),
+	    };
+	    if line.trim().is_empty() {
+		continue;
+	    }
+	    return Some(serde_json::from_str(&line).map_err(|e| e.into()));
+	}
+    }
+}
+
+/// Replays the capture at `path` in the background, returning a RisReceiver just like `RisClient::subscribe`.
+/// With a `speed` of 1.0 messages are delivered with their original spacing, 10.0 is ten times faster,
+/// and 0.0 delivers them as fast as they can be read. The stream ends at the end of the capture,
+/// or at the first line that fails to parse.
+///
+/// # Examples
+///
+/// ```no_run
+/// let rx = risclient::record::replay("incident.risjsonl", 10.0).unwrap();
+/// while let Ok(message) = rx.blocking_recv() {
+///     println!("{:?}", message);
+/// }
+/// ```
+pub fn replay<P: AsRef<Path>>(path: P, speed: f64) -> Result<RisReceiver, Box<dyn error::Error>> {
+    replay_with_clock(path, speed, clock::system())
+}
+
+/// Replays a capture as `replay` does, pacing the messages with `clock`, which the receiver's adapters
+/// are timed with too
+///
+/// # Examples
+///
+/// ```
+/// use std::sync::Arc;
+/// use std::time::Duration;
+/// use risclient::RisResponse;
+/// use risclient::clock::{Clock, TokioClock};
+/// use risclient::record::{self, Recorder};
+/// # #[tokio::main(flavor = "current_thread", start_paused = true)]
+/// # async fn main() {
+/// let path = std::env::temp_dir().join("risclient-replay-clock-example.risjsonl");
+/// let message: RisResponse = serde_json::from_str(r#"{"type": "ris_message", "data": {"host": "rrc00", "type": "KEEPALIVE"}}"#).unwrap();
+/// let mut recorder = Recorder::create(&path).unwrap();
+/// recorder.record_at(&message, 1650000000.0).unwrap();
+/// recorder.record_at(&message, 1650000060.0).unwrap();
+/// recorder.flush().unwrap();
+/// let clock = Arc::new(TokioClock::new());
+/// let rx = record::replay_with_clock(&path, 1.0, clock.clone()).unwrap();
+/// let started = clock.now();
+/// rx.recv().await.unwrap();
+/// tokio::task::yield_now().await;
+/// assert!(rx.try_recv().is_err());
+/// rx.recv().await.unwrap();
+/// assert_eq!(clock.now() - started, Duration::from_secs(60));
+/// # std::fs::remove_file(&path).unwrap();
+/// # }
+/// ```
+pub fn replay_with_clock<P: AsRef<Path>>(path: P, speed: f64, clock: Arc<dyn Clock>) -> Result<RisReceiver, Box<dyn error::Error>> {
+    let replay = Replay::open(path)?;
+    // messages recorded with their sequence numbers and receive times keep them, and any others are
+    // numbered as a new connection and take the time they were recorded as received
+    let (mut tx, rx) = receiver::connection_channel(clock.clone());
+    clock::spawn(async move {
+	// the first message's recorded and actual delivery times, which later messages are paced against
+	let mut start: Option<(f64, Instant)> = None;
+	for recorded in replay {
+	    let recorded = match recorded {
+		Ok(recorded) => recorded,
+		Err(_) => break,
+	    };
+	    match start {
+		Some((first, started)) if speed > 0.0 => {
+		    let due = Duration::from_secs_f64(((recorded.received - first) / speed).max(0.0));
+		    let elapsed = clock.now().saturating_duration_since(started);
+		    if due > elapsed {
+			clock.sleep(due - elapsed).await;
+		    }
+		},
+		None if speed > 0.0 => start = Some((recorded.received, clock.now())),
+		// let the receiving end in, as nothing else here waits
+		_ => tokio::task::yield_now().await,
+	    }
+	    let mut message = recorded.message;
+	    message.data.received.get_or_insert(recorded.received);
+	    if tx.send(Ok(message)).is_err() {
+		break;
+	    }
+	}
+    });
+    Ok(rx)
+}
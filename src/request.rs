@@ -0,0 +1,115 @@
+//! Arbitrary RIS Live requests
+//!
+//! The client models `ris_subscribe`, but RIS Live accepts other request types
+//! and gains options over time. `RisRequest::builder` puts together a request
+//! of any type with any payload, which `RisRequest::send` writes to a
+//! connection from `RisClient::connect_raw`, so that new server-side features
+//! can be tried before this crate knows about them.
+
+use std::error;
+
+use futures_util::SinkExt;
+use serde_json::{Map, Value};
+
+use crate::{RawSink, RisRequest, RisRequestData};
+
+/// Builds a `RisRequest`, see `RisRequest::builder`
+#[derive(Debug, Clone)]
+pub struct RisRequestBuilder {
+    message_type: String,
+    data: Option<Value>,
+}
+
+impl RisRequestBuilder {
+
+    /// Sets the request's type, "ris_subscribe" unless set
+    pub fn message_type(mut self, message_type: &str) -> RisRequestBuilder {
+	self.message_type = message_type.to_string();
+	self
+    }
+
+    /// Sets the whole data payload, replacing any fields set before
+    pub fn data(mut self, data: Value) -> RisRequestBuilder {
+	self.data = Some(data);
+	self
+    }
+
+    /// Sets one field of the data payload, which becomes an object if it was not one
+    pub fn field(mut self, name: &str, value: Value) -> RisRequestBuilder {
+	match &mut self.data {
+	    Some(Value::Object(fields)) => {
+		fields.insert(name.to_string(), value);
+	    },
+	    data => *data = Some(Value::Object(Map::from_iter([(name.to_string(), value)]))),
+	}
+	self
+    }
+
+    /// Returns the request
+    pub fn build(self) -> RisRequest {
+	RisRequest { message_type: self.message_type, data: self.data }
+    }
+}
+
+impl RisRequest {
+
+    /// Returns a builder for a request of any type, with any payload
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use risclient::RisRequest;
+    /// use serde_json::json;
+    /// let request = RisRequest::builder()
+    ///     .data(json!({"host": "rrc00", "type": "UPDATE"}))
+    ///     .field("socketOptions", json!({"includeRaw": true, "futureOption": 1}))
+    ///     .build();
+    /// assert_eq!(request.message_type(), "ris_subscribe");
+    /// assert_eq!(request.to_frame(), r#"{"type":"ris_subscribe","data":{"host":"rrc00","socketOptions":{"futureOption":1,"includeRaw":true},"type":"UPDATE"}}"#);
+    /// assert_eq!(RisRequest::builder().message_type("ping").build().to_frame(), r#"{"type":"ping"}"#);
+    /// ```
+    pub fn builder() -> RisRequestBuilder {
+	RisRequestBuilder { message_type: "ris_subscribe".to_string(), data: None }
+    }
+
+    /// Returns a request carrying the data of one collector of a subscription
+    pub(crate) fn new(message_type: &str, data: &RisRequestData) -> RisRequest {
+	RisRequest { message_type: message_type.to_string(), data: serde_json::to_value(data).ok() }
+    }
+
+    /// Returns the request's type, such as "ris_subscribe"
+    pub fn message_type(&self) -> &str {
+	&self.message_type
+    }
+
+    /// Returns the request's data payload, if it has one
+    pub fn data(&self) -> Option<&Value> {
+	self.data.as_ref()
+    }
+
+    /// Returns the request as the JSON frame sent to RIS Live
+    pub fn to_frame(&self) -> String {
+	// a request is a string and a JSON value, which always serialise
+	serde_json::to_string(self).unwrap_or_default()
+    }
+
+    /// Sends the request over a connection from `RisClient::connect_raw`
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use futures_util::StreamExt;
+    /// use risclient::{RisClient, RisRequest};
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let client = RisClient::default().unwrap();
+    /// let (mut sink, mut stream) = client.connect_raw().await.unwrap();
+    /// RisRequest::builder().message_type("ping").build().send(&mut sink).await.unwrap();
+    /// println!("{:?}", stream.next().await);
+    /// # }
+    /// ```
+    pub async fn send(&self, sink: &mut RawSink) -> Result<(), Box<dyn error::Error>> {
+	sink.send(self.to_frame().into()).await?;
+	Ok(())
+    }
+}
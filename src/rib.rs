@@ -0,0 +1,240 @@
+//! Routing table state built from RIS messages
+//!
+//! A `Rib` holds the current best knowledge of every route each RIS peer has
+//! announced, per collector. It is updated by applying messages in order, from
+//! RIS Live, from MRT archives, or from both.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use ipnet::IpNet;
+
+use crate::{collectors, AsPathEntry, RisResponseData};
+
+/// Identifies a peer session at a collector
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PeerKey {
+    pub collector: String,
+    pub peer: String,
+}
+
+impl PeerKey {
+    /// Returns the PeerKey for the session a message was received on, naming the collector
+    /// by its short name whether the message gave "rrc00" or "rrc00.ripe.net"
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use risclient::RisResponse;
+    /// use risclient::rib::PeerKey;
+    /// let message: RisResponse = serde_json::from_str(r#"{"type": "ris_message", "data": {"peer": "192.0.2.1",
+    ///     "host": "rrc00.ripe.net", "type": "KEEPALIVE"}}"#).unwrap();
+    /// assert_eq!(PeerKey::of(message.data()), PeerKey { collector: "rrc00".to_string(), peer: "192.0.2.1".to_string() });
+    /// ```
+    pub fn of(data: &RisResponseData) -> PeerKey {
+	PeerKey {
+	    collector: collectors::short_name(data.host()).to_string(),
+	    peer: data.peer().to_string(),
+	}
+    }
+}
+
+/// The attributes of a route as last announced by a peer
+#[derive(Debug, Clone, PartialEq)]
+pub struct Route {
+    pub path: Vec<AsPathEntry>,
+    pub next_hop: String,
+    pub origin: Option<String>,
+    pub med: Option<u32>,
+    pub community: Vec<(u32, u32)>,
+    pub aggregator: Option<String>,
+    /// When the route was announced, in seconds since the epoch
+    pub timestamp: f64,
+}
+
+impl Route {
+    /// Returns the origin ASN, if the path ends in a single ASN rather than an AS_SET
+    pub fn origin_asn(&self) -> Option<u32> {
+	match self.path.last() {
+	    Some(AsPathEntry::Asn(asn)) => Some(*asn),
+	    _ => None,
+	}
+    }
+}
+
+/// A route whose next hop an UPDATE changes, returned by `Rib::next_hop_changes`
+#[derive(Debug, Clone, PartialEq)]
+pub struct NextHopChange {
+    pub key: PeerKey,
+    pub prefix: IpNet,
+    pub previous: String,
+    pub next_hop: String,
+    /// When the change was announced, in seconds since the epoch
+    pub timestamp: f64,
+}
+
+///
+/// Routes per peer session, keyed by prefix.
+/// Routes announced in the same UPDATE share their attributes.
+///
+#[derive(Debug, Clone, Default)]
+pub struct Rib {
+    tables: HashMap<PeerKey, HashMap<IpNet, Arc<Route>>>,
+}
+
+impl Rib {
+
+    /// Returns an empty Rib
+    pub fn new() -> Rib {
+	Rib::default()
+    }
+
+    /// Applies a message to the table. UPDATEs replace or remove routes for their prefixes,
+    /// and a RIS_PEER_STATE reporting a session as down clears everything learned on it.
+    /// Returns the number of prefixes changed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use risclient::RisResponse;
+    /// use risclient::rib::Rib;
+    /// let message: RisResponse = serde_json::from_str(r#"{"type": "ris_message", "data": {"timestamp": 1650000000.25,
+    ///     "peer": "192.0.2.1", "peer_asn": "64500", "id": "1", "host": "rrc00", "type": "UPDATE", "path": [64500, 64501],
+    ///     "announcements": [{"next_hop": "192.0.2.1", "prefixes": ["198.51.100.0/24"]}]}}"#).unwrap();
+    /// let mut rib = Rib::new();
+    /// rib.apply(message.data());
+    /// let routes = rib.routes_for(&"198.51.100.0/24".parse().unwrap());
+    /// assert_eq!(routes[0].1.origin_asn(), Some(64501));
+    /// ```
+    pub fn apply(&mut self, data: &RisResponseData) -> usize {
+	match data.data_type() {
+	    "UPDATE" => {},
+	    "RIS_PEER_STATE" if data.state() == Some("down") => return self.clear_peer(&PeerKey::of(data)),
+	    _ => return 0,
+	}
+	let key = PeerKey::of(data);
+	let mut changed = 0;
+	if !data.withdrawals().is_empty() {
+	    if let Some(table) = self.tables.get_mut(&key) {
+		for withdrawal in data.withdrawals() {
+		    if let Ok(prefix) = withdrawal.parse::<IpNet>() {
+			if table.remove(&prefix).is_some() {
+			    changed += 1;
+			}
+		    }
+		}
+	    }
+	}
+	if data.announcements().is_empty() {
+	    return changed;
+	}
+	let table = self.tables.entry(key).or_default();
+	for announcement in data.announcements() {
+	    let route = Arc::new(Route {
+		path: data.path().to_vec(),
+		next_hop: announcement.next_hop().to_string(),
+		origin: data.origin().map(|origin| origin.to_string()),
+		med: data.med(),
+		community: data.community().to_vec(),
+		aggregator: data.aggregator().map(|aggregator| aggregator.to_string()),
+		timestamp: data.timestamp(),
+	    });
+	    for prefix in announcement.prefixes() {
+		if let Ok(prefix) = prefix.parse::<IpNet>() {
+		    table.insert(prefix, route.clone());
+		    changed += 1;
+		}
+	    }
+	}
+	changed
+    }
+
+    /// Returns the routes whose next hop applying `data` would change, so call this before `apply`.
+    /// Newly announced prefixes are not changes.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use risclient::RisResponse;
+    /// use risclient::rib::Rib;
+    /// let update = |next_hop: &str| -> RisResponse { serde_json::from_str(&format!(r#"{{"type": "ris_message", "data": {{"timestamp": 1650000000.25,
+    ///     "peer": "192.0.2.1", "peer_asn": "64500", "id": "1", "host": "rrc00", "type": "UPDATE", "path": [64500, 64501],
+    ///     "announcements": [{{"next_hop": "{}", "prefixes": ["198.51.100.0/24"]}}]}}}}"#, next_hop)).unwrap() };
+    /// let mut rib = Rib::new();
+    /// rib.apply(update("192.0.2.1").data());
+    /// let changes = rib.next_hop_changes(update("192.0.2.9").data());
+    /// assert_eq!((changes[0].previous.as_str(), changes[0].next_hop.as_str()), ("192.0.2.1", "192.0.2.9"));
+    /// ```
+    pub fn next_hop_changes(&self, data: &RisResponseData) -> Vec<NextHopChange> {
+	let key = PeerKey::of(data);
+	let Some(table) = self.tables.get(&key).filter(|_| data.data_type() == "UPDATE") else {
+	    return Vec::new();
+	};
+	let mut changes = Vec::new();
+	for announcement in data.announcements() {
+	    for prefix in announcement.prefixes().iter().filter_map(|prefix| prefix.parse::<IpNet>().ok()) {
+		match table.get(&prefix) {
+		    Some(route) if route.next_hop != announcement.next_hop() => changes.push(NextHopChange {
+			key: key.clone(),
+			prefix,
+			previous: route.next_hop.clone(),
+			next_hop: announcement.next_hop().to_string(),
+			timestamp: data.timestamp(),
+		    }),
+		    _ => {},
+		}
+	    }
+	}
+	changes
+    }
+
+    /// Removes every route learned on a session, returning how many were removed
+    pub fn clear_peer(&mut self, key: &PeerKey) -> usize {
+	self.tables.remove(key).map(|table| table.len()).unwrap_or(0)
+    }
+
+    /// Removes every route learned at a collector, given as "rrc00" or "rrc00.ripe.net", returning how many were removed
+    pub fn clear_collector(&mut self, collector: &str) -> usize {
+	let collector = collectors::short_name(collector);
+	let mut removed = 0;
+	self.tables.retain(|key, table| {
+	    if key.collector == collector {
+		removed += table.len();
+		false
+	    } else {
+		true
+	    }
+	});
+	removed
+    }
+
+    /// Returns the route each peer has for exactly `prefix`
+    pub fn routes_for(&self, prefix: &IpNet) -> Vec<(&PeerKey, &Route)> {
+	self.tables.iter().filter_map(|(key, table)| table.get(prefix).map(|route| (key, route.as_ref()))).collect()
+    }
+
+    /// Returns the route a single peer has for `prefix`
+    pub fn route(&self, key: &PeerKey, prefix: &IpNet) -> Option<&Route> {
+	self.tables.get(key).and_then(|table| table.get(prefix)).map(|route| route.as_ref())
+    }
+
+    /// Returns every route learned on a session
+    pub fn peer_routes(&self, key: &PeerKey) -> impl Iterator<Item = (&IpNet, &Route)> {
+	self.tables.get(key).into_iter().flatten().map(|(prefix, route)| (prefix, route.as_ref()))
+    }
+
+    /// Returns every session with at least one route
+    pub fn peers(&self) -> impl Iterator<Item = &PeerKey> {
+	self.tables.keys()
+    }
+
+    /// Returns the total number of routes across all sessions
+    pub fn len(&self) -> usize {
+	self.tables.values().map(|table| table.len()).sum()
+    }
+
+    /// Returns true if no session has any routes
+    pub fn is_empty(&self) -> bool {
+	self.tables.values().all(|table| table.is_empty())
+    }
+}
@@ -0,0 +1,360 @@
+//! RPKI Route Origin Authorisation (ROA) handling
+//!
+//! This module loads validated ROA payloads from the JSON exports produced by
+//! relying party software such as Routinator, rpki-client or FORT, and keeps
+//! them up to date in the background so messages from RIS Live can be checked
+//! against the current RPKI view.
+
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use ipnet::IpNet;
+use serde::de::{self, Deserialize, Deserializer};
+
+use crate::clock::{self, Clock};
+
+/// A single validated ROA payload
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Roa {
+    #[serde(deserialize_with = "deserialize_asn")]
+    pub asn: u32,
+    pub prefix: IpNet,
+    #[serde(rename = "maxLength")]
+    pub max_length: u8,
+    #[serde(default)]
+    pub ta: String,
+}
+
+impl Roa {
+    /// Returns true if this ROA authorises `origin` to announce `prefix`
+    pub fn matches(&self, prefix: &IpNet, origin: u32) -> bool {
+	self.asn == origin && self.prefix.contains(prefix) && prefix.prefix_len() <= self.max_length
+    }
+}
+
+/// Exports disagree on whether the ASN is a number or an "AS" prefixed string, so accept both
+fn deserialize_asn<'de, D>(deserializer: D) -> Result<u32, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Asn {
+	Number(u32),
+	Text(String),
+    }
+    match Asn::deserialize(deserializer)? {
+	Asn::Number(asn) => Ok(asn),
+	Asn::Text(text) => {
+	    let digits = text.trim_start_matches("AS").trim_start_matches("as");
+	    digits.parse().map_err(|_| de::Error::custom(format!("invalid ASN '{}'", text)))
+	}
+    }
+}
+
+/// The outcome of validating a route origin against a set of ROAs, as described in RFC 6811
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ValidationState {
+    Valid,
+    Invalid,
+    NotFound,
+}
+
+impl fmt::Display for ValidationState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	match self {
+	    ValidationState::Valid => write!(f, "valid"),
+	    ValidationState::Invalid => write!(f, "invalid"),
+	    ValidationState::NotFound => write!(f, "not-found"),
+	}
+    }
+}
+
+#[derive(Deserialize)]
+struct RoaExport {
+    roas: Vec<Roa>,
+}
+
+/// An immutable, indexed set of ROAs
+#[derive(Debug, Clone, Default)]
+pub struct RoaSet {
+    roas: HashMap<IpNet, Vec<Roa>>,
+    len: usize,
+}
+
+impl RoaSet {
+
+    /// Returns a RoaSet containing the provided ROAs
+    pub fn new(roas: Vec<Roa>) -> RoaSet {
+	let mut set = RoaSet::default();
+	for roa in roas {
+	    set.insert(roa);
+	}
+	set
+    }
+
+    /// Parses a RoaSet from a JSON export, as produced by `routinator vrps --format json` or rpki-client
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use risclient::rpki::{RoaSet, ValidationState};
+    /// let set = RoaSet::from_json(r#"{"roas": [{"asn": "AS64500", "prefix": "192.0.2.0/24", "maxLength": 24, "ta": "test"}]}"#).unwrap();
+    /// let prefix = "192.0.2.0/24".parse().unwrap();
+    /// assert_eq!(set.roas_for(&prefix).len(), 1);
+    /// assert_eq!(set.validate(&prefix, 64500), ValidationState::Valid);
+    /// assert_eq!(set.validate(&prefix, 64501), ValidationState::Invalid);
+    /// ```
+    pub fn from_json(json: &str) -> Result<RoaSet, Box<dyn error::Error>> {
+	match serde_json::from_str::<RoaExport>(json) {
+	    Ok(export) => Ok(RoaSet::new(export.roas)),
+	    Err(e) => Err(Box::new(e))
+	}
+    }
+
+    pub(crate) fn insert(&mut self, roa: Roa) -> bool {
+	let entries = self.roas.entry(roa.prefix.trunc()).or_default();
+	if entries.contains(&roa) {
+	    return false;
+	}
+	entries.push(roa);
+	self.len += 1;
+	true
+    }
+
+    pub(crate) fn remove(&mut self, roa: &Roa) -> bool {
+	let key = roa.prefix.trunc();
+	let removed = match self.roas.get_mut(&key) {
+	    Some(entries) => {
+		let before = entries.len();
+		entries.retain(|entry| entry != roa);
+		before != entries.len()
+	    },
+	    None => false
+	};
+	if removed {
+	    self.len -= 1;
+	    if self.roas.get(&key).map(|entries| entries.is_empty()).unwrap_or(false) {
+		self.roas.remove(&key);
+	    }
+	}
+	removed
+    }
+
+    /// Returns the number of ROAs in the set
+    pub fn len(&self) -> usize {
+	self.len
+    }
+
+    /// Returns true if the set contains no ROAs
+    pub fn is_empty(&self) -> bool {
+	self.len == 0
+    }
+
+    /// Returns an iterator over every ROA in the set
+    pub fn iter(&self) -> impl Iterator<Item = &Roa> {
+	self.roas.values().flatten()
+    }
+
+    /// Returns every ROA whose prefix covers (is equal to or less specific than) `prefix`
+    pub fn roas_for(&self, prefix: &IpNet) -> Vec<Roa> {
+	let mut covering = Vec::new();
+	for len in 0..=prefix.prefix_len() {
+	    let candidate = match IpNet::new(prefix.addr(), len) {
+		Ok(candidate) => candidate.trunc(),
+		Err(_) => continue,
+	    };
+	    if let Some(entries) = self.roas.get(&candidate) {
+		covering.extend(entries.iter().cloned());
+	    }
+	}
+	covering
+    }
+
+    /// Performs route origin validation of `prefix` announced by `origin`
+    pub fn validate(&self, prefix: &IpNet, origin: u32) -> ValidationState {
+	let covering = self.roas_for(prefix);
+	if covering.is_empty() {
+	    ValidationState::NotFound
+	} else if covering.iter().any(|roa| roa.matches(prefix, origin)) {
+	    ValidationState::Valid
+	} else {
+	    ValidationState::Invalid
+	}
+    }
+}
+
+/// Where a RoaManager loads its ROAs from
+#[derive(Debug, Clone)]
+pub enum RoaSource {
+    /// Fetch a JSON export over HTTP(S)
+    Url(String),
+    /// Read a JSON export from the local filesystem
+    File(PathBuf),
+}
+
+impl RoaSource {
+    async fn load(&self) -> Result<RoaSet, Box<dyn error::Error>> {
+	let json = match self {
+	    RoaSource::Url(url) => {
+		let response = match reqwest::get(url).await {
+		    Ok(response) => response,
+		    Err(e) => return Err(Box::new(e))
+		};
+		match response.error_for_status() {
+		    Ok(response) => match response.text().await {
+			Ok(text) => text,
+			Err(e) => return Err(Box::new(e))
+		    },
+		    Err(e) => return Err(Box::new(e))
+		}
+	    },
+	    RoaSource::File(path) => match tokio::fs::read_to_string(path).await {
+		Ok(text) => text,
+		Err(e) => return Err(Box::new(e))
+	    },
+	};
+	RoaSet::from_json(&json)
+    }
+}
+
+/// Emitted when the ROAs covering a watched prefix change after a refresh
+#[derive(Debug, Clone)]
+pub struct RoaEvent {
+    pub prefix: IpNet,
+    pub previous: Vec<Roa>,
+    pub current: Vec<Roa>,
+}
+
+struct Watch {
+    prefix: IpNet,
+    tx: Sender<RoaEvent>,
+}
+
+///
+/// Keeps a validated ROA set up to date.
+/// Refreshed sets are swapped in atomically, so lookups always see either the
+/// old or the new set in its entirety, never a partially loaded one.
+///
+#[derive(Clone)]
+pub struct RoaManager {
+    source: Option<RoaSource>,
+    current: Arc<RwLock<Arc<RoaSet>>>,
+    watches: Arc<Mutex<Vec<Watch>>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl RoaManager {
+
+    /// Returns a RoaManager which loads ROAs from the provided source. The set is empty until `refresh` is called.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::time::Duration;
+    /// use risclient::rpki::{RoaManager, RoaSource};
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let manager = RoaManager::new(RoaSource::Url("https://rpki.example.net/json".to_string()));
+    /// manager.refresh().await.unwrap();
+    /// manager.spawn(Duration::from_secs(600));
+    /// let roas = manager.roas_for(&"193.0.0.0/21".parse().unwrap());
+    /// # }
+    /// ```
+    pub fn new(source: RoaSource) -> RoaManager {
+	RoaManager {
+	    source: Some(source),
+	    current: Arc::new(RwLock::new(Arc::new(RoaSet::default()))),
+	    watches: Arc::new(Mutex::new(Vec::new())),
+	    clock: clock::system(),
+	}
+    }
+
+    /// Returns a RoaManager with no source of its own, which is only updated through `replace`
+    pub fn detached() -> RoaManager {
+	RoaManager {
+	    source: None,
+	    current: Arc::new(RwLock::new(Arc::new(RoaSet::default()))),
+	    watches: Arc::new(Mutex::new(Vec::new())),
+	    clock: clock::system(),
+	}
+    }
+
+    /// Times the refreshes of `spawn` with `clock`
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> RoaManager {
+	self.clock = clock;
+	self
+    }
+
+    /// Returns the current ROA set. The snapshot is unaffected by later refreshes.
+    pub fn snapshot(&self) -> Arc<RoaSet> {
+	self.current.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Returns every ROA in the current set covering `prefix`
+    pub fn roas_for(&self, prefix: &IpNet) -> Vec<Roa> {
+	self.snapshot().roas_for(prefix)
+    }
+
+    /// Validates `prefix` announced by `origin` against the current set
+    pub fn validate(&self, prefix: &IpNet, origin: u32) -> ValidationState {
+	self.snapshot().validate(prefix, origin)
+    }
+
+    /// Returns a Receiver of events which fire whenever the ROAs covering `prefix` change
+    pub fn watch(&self, prefix: IpNet) -> Receiver<RoaEvent> {
+	let (tx, rx) = channel();
+	let mut watches = self.watches.lock().unwrap_or_else(|e| e.into_inner());
+	watches.push(Watch { prefix, tx });
+	rx
+    }
+
+    /// Loads the source once and swaps the result in, leaving the current set untouched on failure
+    pub async fn refresh(&self) -> Result<(), Box<dyn error::Error>> {
+	let set = match &self.source {
+	    Some(source) => source.load().await?,
+	    None => return Ok(()),
+	};
+	self.replace(set);
+	Ok(())
+    }
+
+    /// Atomically replaces the current set, notifying watchers whose covering ROAs changed
+    pub fn replace(&self, set: RoaSet) {
+	let new = Arc::new(set);
+	let old = {
+	    let mut current = self.current.write().unwrap_or_else(|e| e.into_inner());
+	    std::mem::replace(&mut *current, new.clone())
+	};
+	let mut watches = self.watches.lock().unwrap_or_else(|e| e.into_inner());
+	watches.retain(|watch| {
+	    let mut previous = old.roas_for(&watch.prefix);
+	    let mut current = new.roas_for(&watch.prefix);
+	    previous.sort_by_key(|roa| (roa.prefix, roa.asn, roa.max_length));
+	    current.sort_by_key(|roa| (roa.prefix, roa.asn, roa.max_length));
+	    if previous == current {
+		return true;
+	    }
+	    // a failed send means the receiver was dropped, so stop watching
+	    watch.tx.send(RoaEvent { prefix: watch.prefix, previous, current }).is_ok()
+	});
+    }
+
+    /// Spawns a task which refreshes the set every `interval`.
+    /// A failed refresh keeps the previous set in place and is retried at the next interval.
+    pub fn spawn(&self, interval: Duration) -> tokio::task::JoinHandle<()> {
+	let manager = self.clone();
+	tokio::spawn(async move {
+	    loop {
+		manager.clock.sleep(interval).await;
+		let _ = manager.refresh().await;
+	    }
+	})
+    }
+}
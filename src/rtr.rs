@@ -0,0 +1,361 @@
+//! RPKI-to-Router protocol client (RFC 8210, falling back to RFC 6810)
+//!
+//! Keeps a [`RoaManager`] synchronised with a local relying party cache, applying
+//! incremental updates as the cache announces new serials rather than polling
+//! full JSON exports.
+
+use std::error;
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use ipnet::{IpNet, Ipv4Net, Ipv6Net};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use crate::clock::{self, Clock};
+use crate::rpki::{Roa, RoaManager, RoaSet};
+
+const PDU_SERIAL_NOTIFY: u8 = 0;
+const PDU_SERIAL_QUERY: u8 = 1;
+const PDU_RESET_QUERY: u8 = 2;
+const PDU_CACHE_RESPONSE: u8 = 3;
+const PDU_IPV4_PREFIX: u8 = 4;
+const PDU_IPV6_PREFIX: u8 = 6;
+const PDU_END_OF_DATA: u8 = 7;
+const PDU_CACHE_RESET: u8 = 8;
+const PDU_ROUTER_KEY: u8 = 9;
+const PDU_ERROR_REPORT: u8 = 10;
+
+const ERROR_NO_DATA_AVAILABLE: u16 = 2;
+const ERROR_UNSUPPORTED_VERSION: u16 = 4;
+
+/// Largest PDU we are willing to buffer, anything bigger is treated as a protocol error
+const MAX_PDU_LENGTH: usize = 64 * 1024;
+
+/// The ranges RFC 8210 allows for the refresh, retry and expire intervals, in seconds. A cache
+/// sending values outside them, such as a refresh of 0, gets the nearest allowed value instead.
+const REFRESH_RANGE: (u64, u64) = (1, 86400);
+const RETRY_RANGE: (u64, u64) = (1, 7200);
+const EXPIRE_RANGE: (u64, u64) = (600, 172800);
+
+fn protocol_error(message: String) -> Box<dyn error::Error + Send + Sync> {
+    Box::new(io::Error::new(io::ErrorKind::InvalidData, message))
+}
+
+struct Pdu {
+    version: u8,
+    pdu_type: u8,
+    session: u16,
+    body: Vec<u8>,
+}
+
+/// The synchronisation state reported by the cache in its last End of Data PDU
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RtrState {
+    pub session_id: u16,
+    pub serial: u32,
+    pub refresh: Duration,
+    pub retry: Duration,
+    pub expire: Duration,
+}
+
+///
+/// A client for the RPKI-to-Router protocol.
+/// Every completed update from the cache is applied to the provided RoaManager
+/// with a single atomic swap, so watchers see each serial exactly once.
+///
+pub struct RtrClient {
+    addr: String,
+    manager: RoaManager,
+    version: u8,
+    state: Option<RtrState>,
+    // when the last update completed, to stop serving its ROAs once `expire` has passed
+    synchronised: Option<Instant>,
+    clock: Arc<dyn Clock>,
+}
+
+impl RtrClient {
+
+    /// Returns an RtrClient which connects to the cache at `addr` and feeds `manager`
+    ///
+    /// # Arguments
+    ///
+    /// * `addr` - The `host:port` of the RTR cache, for example `localhost:3323` for Routinator
+    /// * `manager` - The RoaManager to keep synchronised, usually one created with `RoaManager::detached`
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use risclient::rpki::RoaManager;
+    /// use risclient::rtr::RtrClient;
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let manager = RoaManager::detached();
+    /// let client = RtrClient::new("localhost:3323".to_string(), manager.clone());
+    /// client.spawn();
+    /// let state = manager.validate(&"193.0.0.0/21".parse().unwrap(), 3333);
+    /// # }
+    /// ```
+    pub fn new(addr: String, manager: RoaManager) -> RtrClient {
+	RtrClient {
+	    addr,
+	    manager,
+	    version: 1,
+	    state: None,
+	    synchronised: None,
+	    clock: clock::system(),
+	}
+    }
+
+    /// Times the refresh, retry and expire intervals with `clock`
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> RtrClient {
+	self.clock = clock;
+	self
+    }
+
+    /// Returns the state reported by the cache at the end of the last successful update
+    pub fn state(&self) -> Option<RtrState> {
+	self.state
+    }
+
+    /// Connects to the cache and keeps the manager synchronised until the connection fails.
+    /// A reconnect after failure resumes from the last known serial where the cache allows it.
+    pub async fn run(&mut self) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+	loop {
+	    let mut stream = TcpStream::connect(&self.addr).await?;
+	    // synchronise only returns successfully when the cache asked us to downgrade
+	    self.synchronise(&mut stream).await?;
+	}
+    }
+
+    async fn synchronise(&mut self, stream: &mut TcpStream) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+	match self.state {
+	    Some(state) => self.send_serial_query(stream, state).await?,
+	    None => self.send_reset_query(stream).await?,
+	}
+	loop {
+	    let timeout = match self.state {
+		Some(state) => state.refresh,
+		None => Duration::from_secs(3600),
+	    };
+	    let pdu = match clock::timeout_at(self.clock.as_ref(), self.clock.now() + timeout, read_pdu(stream)).await {
+		Ok(pdu) => pdu?,
+		Err(_) => {
+		    self.expire_if_stale();
+		    // refresh interval expired without a notify, poll the cache ourselves
+		    if let Some(state) = self.state {
+			self.send_serial_query(stream, state).await?;
+		    }
+		    continue;
+		}
+	    };
+	    match pdu.pdu_type {
+		PDU_SERIAL_NOTIFY => {
+		    if let Some(state) = self.state {
+			self.send_serial_query(stream, state).await?;
+		    }
+		},
+		PDU_CACHE_RESPONSE => {
+		    let full = self.state.map(|state| state.session_id != pdu.session).unwrap_or(true);
+		    self.receive_update(stream, pdu.session, full).await?;
+		},
+		PDU_CACHE_RESET => {
+		    self.state = None;
+		    self.send_reset_query(stream).await?;
+		},
+		PDU_ERROR_REPORT => {
+		    match pdu.session {
+			ERROR_UNSUPPORTED_VERSION if self.version > 0 => {
+			    self.version -= 1;
+			    self.state = None;
+			    return Ok(());
+			},
+			// the cache is still starting up, wait before asking again
+			ERROR_NO_DATA_AVAILABLE => {
+			    self.clock.sleep(Duration::from_secs(30)).await;
+			    self.send_reset_query(stream).await?;
+			},
+			code => return Err(protocol_error(format!("cache reported error {}: {}", code, error_text(&pdu.body)))),
+		    }
+		},
+		other => return Err(protocol_error(format!("unexpected PDU type {} outside of an update", other))),
+	    }
+	}
+    }
+
+    /// Spawns a task which runs the client forever, reconnecting after the cache's retry interval on failure.
+    /// If the cache cannot be synchronised with for its expire interval, the manager's ROAs are cleared
+    /// rather than served indefinitely, and every route validates as not found until the cache is back.
+    pub fn spawn(mut self) -> tokio::task::JoinHandle<()> {
+	tokio::spawn(async move {
+	    loop {
+		let _ = self.run().await;
+		self.expire_if_stale();
+		let mut retry = match self.state {
+		    Some(state) => state.retry,
+		    None => Duration::from_secs(600),
+		};
+		// wake up in time to clear the ROAs when they expire
+		if let (Some(state), Some(synchronised)) = (self.state, self.synchronised) {
+		    retry = retry.min((synchronised + state.expire).saturating_duration_since(self.clock.now()));
+		}
+		self.clock.sleep(retry).await;
+	    }
+	})
+    }
+
+    /// Clears the manager's ROAs if the last update is older than the cache's expire interval, and
+    /// forgets the session so that the next connection starts afresh with a Reset Query
+    fn expire_if_stale(&mut self) {
+	let (Some(state), Some(synchronised)) = (self.state, self.synchronised) else {
+	    return;
+	};
+	if self.clock.now().saturating_duration_since(synchronised) >= state.expire {
+	    self.manager.replace(RoaSet::default());
+	    self.state = None;
+	    self.synchronised = None;
+	}
+    }
+
+    async fn receive_update(&mut self, stream: &mut TcpStream, session: u16, full: bool) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+	let mut set = if full {
+	    Default::default()
+	} else {
+	    (*self.manager.snapshot()).clone()
+	};
+	loop {
+	    let pdu = read_pdu(stream).await?;
+	    match pdu.pdu_type {
+		PDU_IPV4_PREFIX | PDU_IPV6_PREFIX => {
+		    let (announce, roa) = parse_prefix(&pdu)?;
+		    if announce {
+			set.insert(roa);
+		    } else {
+			set.remove(&roa);
+		    }
+		},
+		PDU_ROUTER_KEY => continue,
+		PDU_END_OF_DATA => {
+		    self.state = Some(parse_end_of_data(&pdu, session)?);
+		    self.synchronised = Some(self.clock.now());
+		    self.manager.replace(set);
+		    return Ok(());
+		},
+		PDU_CACHE_RESET => {
+		    self.state = None;
+		    return self.send_reset_query(stream).await;
+		},
+		PDU_ERROR_REPORT => return Err(protocol_error(format!("cache reported error {}: {}", pdu.session, error_text(&pdu.body)))),
+		other => return Err(protocol_error(format!("unexpected PDU type {} during update", other))),
+	    }
+	}
+    }
+
+    async fn send_reset_query(&self, stream: &mut TcpStream) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+	let mut pdu = vec![self.version, PDU_RESET_QUERY, 0, 0];
+	pdu.extend_from_slice(&8u32.to_be_bytes());
+	stream.write_all(&pdu).await?;
+	Ok(())
+    }
+
+    async fn send_serial_query(&self, stream: &mut TcpStream, state: RtrState) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+	let mut pdu = vec![self.version, PDU_SERIAL_QUERY];
+	pdu.extend_from_slice(&state.session_id.to_be_bytes());
+	pdu.extend_from_slice(&12u32.to_be_bytes());
+	pdu.extend_from_slice(&state.serial.to_be_bytes());
+	stream.write_all(&pdu).await?;
+	Ok(())
+    }
+}
+
+async fn read_pdu(stream: &mut TcpStream) -> Result<Pdu, Box<dyn error::Error + Send + Sync>> {
+    let mut header = [0u8; 8];
+    stream.read_exact(&mut header).await?;
+    let length = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+    if !(8..=MAX_PDU_LENGTH).contains(&length) {
+	return Err(protocol_error(format!("invalid PDU length {}", length)));
+    }
+    let mut body = vec![0u8; length - 8];
+    stream.read_exact(&mut body).await?;
+    Ok(Pdu {
+	version: header[0],
+	pdu_type: header[1],
+	session: u16::from_be_bytes([header[2], header[3]]),
+	body,
+    })
+}
+
+fn parse_prefix(pdu: &Pdu) -> Result<(bool, Roa), Box<dyn error::Error + Send + Sync>> {
+    let address_length = if pdu.pdu_type == PDU_IPV4_PREFIX { 4 } else { 16 };
+    if pdu.body.len() != 4 + address_length + 4 {
+	return Err(protocol_error(format!("invalid prefix PDU length {}", pdu.body.len() + 8)));
+    }
+    let flags = pdu.body[0];
+    let prefix_length = pdu.body[1];
+    let max_length = pdu.body[2];
+    let address = &pdu.body[4..4 + address_length];
+    let asn = &pdu.body[4 + address_length..];
+    let prefix = if address_length == 4 {
+	let octets: [u8; 4] = address.try_into()?;
+	Ipv4Net::new(Ipv4Addr::from(octets), prefix_length).map(IpNet::V4)
+    } else {
+	let octets: [u8; 16] = address.try_into()?;
+	Ipv6Net::new(Ipv6Addr::from(octets), prefix_length).map(IpNet::V6)
+    };
+    let prefix = match prefix {
+	Ok(prefix) => prefix,
+	Err(e) => return Err(protocol_error(format!("invalid prefix in PDU: {}", e))),
+    };
+    Ok((flags & 1 == 1, Roa {
+	asn: u32::from_be_bytes([asn[0], asn[1], asn[2], asn[3]]),
+	prefix,
+	max_length,
+	ta: String::new(),
+    }))
+}
+
+/// Returns an interval from an End of Data PDU, brought within the range RFC 8210 allows
+fn interval(seconds: u32, (min, max): (u64, u64)) -> Duration {
+    Duration::from_secs((seconds as u64).clamp(min, max))
+}
+
+fn parse_end_of_data(pdu: &Pdu, session: u16) -> Result<RtrState, Box<dyn error::Error + Send + Sync>> {
+    let field = |offset: usize| u32::from_be_bytes([pdu.body[offset], pdu.body[offset + 1], pdu.body[offset + 2], pdu.body[offset + 3]]);
+    if pdu.session != session {
+	return Err(protocol_error(format!("session changed from {} to {} during update", session, pdu.session)));
+    }
+    // version 0 End of Data PDUs carry only the serial, so fall back to the RFC 8210 defaults
+    match (pdu.version, pdu.body.len()) {
+	(0, 4) => Ok(RtrState {
+	    session_id: session,
+	    serial: field(0),
+	    refresh: Duration::from_secs(3600),
+	    retry: Duration::from_secs(600),
+	    expire: Duration::from_secs(7200),
+	}),
+	(_, 16) => Ok(RtrState {
+	    session_id: session,
+	    serial: field(0),
+	    refresh: interval(field(4), REFRESH_RANGE),
+	    retry: interval(field(8), RETRY_RANGE),
+	    expire: interval(field(12), EXPIRE_RANGE),
+	}),
+	(_, length) => Err(protocol_error(format!("invalid End of Data PDU length {}", length + 8))),
+    }
+}
+
+fn error_text(body: &[u8]) -> String {
+    // body is: encapsulated PDU length, encapsulated PDU, text length, text
+    if body.len() < 4 {
+	return String::new();
+    }
+    let pdu_length = u32::from_be_bytes([body[0], body[1], body[2], body[3]]) as usize;
+    let text_offset = 4 + pdu_length;
+    if body.len() < text_offset + 4 {
+	return String::new();
+    }
+    let text = &body[text_offset + 4..];
+    String::from_utf8_lossy(text).to_string()
+}
@@ -0,0 +1,207 @@
+//! Peer session state
+//!
+//! RIS Live reports the sessions between collectors and their peers coming up
+//! with OPEN and RIS_PEER_STATE "connected" messages, and going down with
+//! NOTIFICATION and RIS_PEER_STATE "down" messages. `SessionTracker` follows
+//! each session through those, and reports peers that keep cycling between
+//! the two, since a flapping peer skews every statistic downstream of it.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::clock::{self, Clock};
+use crate::rib::PeerKey;
+use crate::RisResponseData;
+
+/// Whether a session is up
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionState {
+    Established,
+    Down,
+}
+
+/// A change to a session, carrying the labels of the subscription it was seen on
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum SessionEvent {
+    /// The session came up
+    Established {
+	collector: String,
+	peer: String,
+	timestamp: f64,
+	#[serde(skip_serializing_if = "BTreeMap::is_empty")]
+	labels: BTreeMap<String, String>,
+    },
+    /// The session went down
+    Down {
+	collector: String,
+	peer: String,
+	timestamp: f64,
+	#[serde(skip_serializing_if = "BTreeMap::is_empty")]
+	labels: BTreeMap<String, String>,
+    },
+    /// The session went down at least the configured number of times within the window
+    PeerFlapping {
+	collector: String,
+	peer: String,
+	/// How many times the session went down within the window
+	flaps: usize,
+	/// How long, in seconds, between the first and the latest of those
+	over: f64,
+	/// How long, in seconds, the session stayed up before the latest
+	last_up: f64,
+	timestamp: f64,
+	#[serde(skip_serializing_if = "BTreeMap::is_empty")]
+	labels: BTreeMap<String, String>,
+    },
+}
+
+#[derive(Debug, Clone)]
+struct Session {
+    state: SessionState,
+    since: Instant,
+    // when the session went down, within the flap window
+    downs: VecDeque<Instant>,
+    flapping: bool,
+}
+
+///
+/// Follows every peer session's state, optionally detecting flapping peers.
+/// Messages other than OPEN, NOTIFICATION and RIS_PEER_STATE are ignored, and a session that
+/// has not been seen yet is counted from its first state change.
+///
+#[derive(Debug, Clone)]
+pub struct SessionTracker {
+    sessions: HashMap<PeerKey, Session>,
+    flap_threshold: usize,
+    flap_window: Duration,
+    clock: Arc<dyn Clock>,
+}
+
+impl SessionTracker {
+
+    /// Returns a SessionTracker that does not detect flapping
+    pub fn new() -> SessionTracker {
+	SessionTracker {
+	    sessions: HashMap::new(),
+	    flap_threshold: 0,
+	    flap_window: Duration::ZERO,
+	    clock: clock::system(),
+	}
+    }
+
+    /// Reports a session as flapping once it has gone down `threshold` times within `window`.
+    /// It is reported again after going `window` without flapping.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use risclient::RisResponse;
+    /// use risclient::rib::PeerKey;
+    /// use risclient::session::{SessionEvent, SessionTracker};
+    /// let state = |state: &str| -> RisResponse { serde_json::from_str(&format!(r#"{{"type": "ris_message", "data": {{"timestamp": 1650000000.25,
+    ///     "peer": "192.0.2.1", "peer_asn": "64500", "id": "1", "host": "rrc00", "type": "RIS_PEER_STATE", "state": "{}"}}}}"#, state)).unwrap() };
+    /// let mut sessions = SessionTracker::new().with_flap_detection(3, Duration::from_secs(600));
+    /// let mut events = Vec::new();
+    /// for _ in 0..3 {
+    ///     events.extend(sessions.observe(state("connected").data()));
+    ///     events.extend(sessions.observe(state("down").data()));
+    /// }
+    /// assert!(matches!(events.last(), Some(SessionEvent::PeerFlapping { flaps: 3, .. })));
+    /// assert!(sessions.is_flapping(&PeerKey { collector: "rrc00".to_string(), peer: "192.0.2.1".to_string() }));
+    /// ```
+    pub fn with_flap_detection(mut self, threshold: usize, window: Duration) -> SessionTracker {
+	self.flap_threshold = threshold;
+	self.flap_window = window;
+	self
+    }
+
+    /// Times sessions and flap windows with `clock`
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> SessionTracker {
+	self.clock = clock;
+	self
+    }
+
+    /// Returns the state of a session, if it has been seen
+    pub fn state(&self, key: &PeerKey) -> Option<SessionState> {
+	self.sessions.get(key).map(|session| session.state)
+    }
+
+    /// Returns how long a session has been in its current state
+    pub fn state_for(&self, key: &PeerKey) -> Option<Duration> {
+	self.sessions.get(key).map(|session| self.clock.now() - session.since)
+    }
+
+    /// Returns true if a session is currently reported as flapping
+    pub fn is_flapping(&self, key: &PeerKey) -> bool {
+	self.sessions.get(key).is_some_and(|session| session.flapping)
+    }
+
+    /// Returns every session currently reported as flapping
+    pub fn flapping(&self) -> impl Iterator<Item = &PeerKey> {
+	self.sessions.iter().filter(|(_, session)| session.flapping).map(|(key, _)| key)
+    }
+
+    /// Updates the sessions with a message, returning the changes it caused
+    pub fn observe(&mut self, data: &RisResponseData) -> Vec<SessionEvent> {
+	let state = match (data.data_type(), data.state()) {
+	    ("OPEN", _) | ("RIS_PEER_STATE", Some("connected")) => SessionState::Established,
+	    ("NOTIFICATION", _) | ("RIS_PEER_STATE", Some("down")) => SessionState::Down,
+	    _ => return Vec::new(),
+	};
+	let now = self.clock.now();
+	let key = PeerKey::of(data);
+	let session = self.sessions.entry(key).or_insert_with(|| Session {
+	    state: SessionState::Down,
+	    since: now,
+	    downs: VecDeque::new(),
+	    flapping: false,
+	});
+	let (collector, peer, timestamp, labels) = (data.host().to_string(), data.peer().to_string(), data.timestamp(), data.labels().clone());
+	let mut events = Vec::new();
+	while session.downs.front().is_some_and(|down| now.duration_since(*down) > self.flap_window) {
+	    session.downs.pop_front();
+	}
+	if session.downs.is_empty() {
+	    session.flapping = false;
+	}
+	if state == session.state {
+	    return events;
+	}
+	let up_for = now - session.since;
+	session.state = state;
+	session.since = now;
+	if state == SessionState::Established {
+	    events.push(SessionEvent::Established { collector, peer, timestamp, labels });
+	    return events;
+	}
+	events.push(SessionEvent::Down { collector: collector.clone(), peer: peer.clone(), timestamp, labels: labels.clone() });
+	if self.flap_threshold == 0 {
+	    return events;
+	}
+	session.downs.push_back(now);
+	if !session.flapping && session.downs.len() >= self.flap_threshold {
+	    session.flapping = true;
+	    let over = session.downs.front().map(|first| now - *first).unwrap_or_default();
+	    events.push(SessionEvent::PeerFlapping {
+		collector,
+		peer,
+		flaps: session.downs.len(),
+		over: over.as_secs_f64(),
+		last_up: up_for.as_secs_f64(),
+		timestamp,
+		labels,
+	    });
+	}
+	events
+    }
+}
+
+impl Default for SessionTracker {
+    fn default() -> SessionTracker {
+	SessionTracker::new()
+    }
+}
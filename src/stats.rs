@@ -0,0 +1,174 @@
+//! Message rate statistics
+//!
+//! `StreamStats` counts messages as they are observed, broken down by
+//! collector, message type, prefix and origin ASN, and turns the counts into
+//! rates over the window since it was last reset. It is meant for quick churn
+//! and health checks rather than long term accounting, so reports are taken
+//! periodically and the window started afresh.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::clock::{self, Clock};
+use crate::RisResponseData;
+
+fn top<K: Clone + Ord>(counts: &HashMap<K, u64>, limit: usize) -> Vec<(K, u64)> {
+    let mut sorted: Vec<(K, u64)> = counts.iter().map(|(key, count)| (key.clone(), *count)).collect();
+    sorted.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    sorted.truncate(limit);
+    sorted
+}
+
+fn increment<K: Eq + Hash>(counts: &mut HashMap<K, u64>, key: K) {
+    *counts.entry(key).or_insert(0) += 1;
+}
+
+/// The counts gathered over one window, with the busiest entries of each breakdown first
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatsReport {
+    pub window: Duration,
+    pub messages: u64,
+    pub announcements: u64,
+    pub withdrawals: u64,
+    pub collectors: Vec<(String, u64)>,
+    pub types: Vec<(String, u64)>,
+    /// Prefixes by number of announcements and withdrawals
+    pub prefixes: Vec<(String, u64)>,
+    /// Origin ASNs by number of prefixes announced
+    pub origins: Vec<(u32, u64)>,
+}
+
+impl StatsReport {
+    /// Returns `count` as a rate per second over the report's window
+    pub fn rate(&self, count: u64) -> f64 {
+	let seconds = self.window.as_secs_f64();
+	if seconds > 0.0 {
+	    count as f64 / seconds
+	} else {
+	    0.0
+	}
+    }
+}
+
+///
+/// Counts messages by collector, type, prefix and origin.
+/// Every prefix and origin seen in the window is kept, so take reports regularly on busy streams.
+///
+#[derive(Debug, Clone)]
+pub struct StreamStats {
+    clock: Arc<dyn Clock>,
+    started: Instant,
+    messages: u64,
+    announcements: u64,
+    withdrawals: u64,
+    collectors: HashMap<String, u64>,
+    types: HashMap<String, u64>,
+    prefixes: HashMap<String, u64>,
+    origins: HashMap<u32, u64>,
+}
+
+impl StreamStats {
+
+    /// Returns a StreamStats with its window starting now
+    pub fn new() -> StreamStats {
+	StreamStats::with_clock(clock::system())
+    }
+
+    /// Returns a StreamStats timing its windows with `clock`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use std::time::Duration;
+    /// use risclient::clock::TokioClock;
+    /// use risclient::stats::StreamStats;
+    /// # #[tokio::main(flavor = "current_thread", start_paused = true)]
+    /// # async fn main() {
+    /// let stats = StreamStats::with_clock(Arc::new(TokioClock::new()));
+    /// tokio::time::advance(Duration::from_secs(60)).await;
+    /// assert_eq!(stats.report(10).window, Duration::from_secs(60));
+    /// # }
+    /// ```
+    pub fn with_clock(clock: Arc<dyn Clock>) -> StreamStats {
+	StreamStats {
+	    started: clock.now(),
+	    clock,
+	    messages: 0,
+	    announcements: 0,
+	    withdrawals: 0,
+	    collectors: HashMap::new(),
+	    types: HashMap::new(),
+	    prefixes: HashMap::new(),
+	    origins: HashMap::new(),
+	}
+    }
+
+    /// Counts a message
+    pub fn observe(&mut self, data: &RisResponseData) {
+	self.messages += 1;
+	increment(&mut self.collectors, data.host().to_string());
+	increment(&mut self.types, data.data_type().to_string());
+	let origin = data.asns().last().copied();
+	for prefix in data.announcements().iter().flat_map(|announcement| announcement.prefixes()) {
+	    self.announcements += 1;
+	    increment(&mut self.prefixes, prefix.clone());
+	    if let Some(origin) = origin {
+		increment(&mut self.origins, origin);
+	    }
+	}
+	for prefix in data.withdrawals() {
+	    self.withdrawals += 1;
+	    increment(&mut self.prefixes, prefix.clone());
+	}
+    }
+
+    /// Returns the counts so far, keeping up to `limit` entries of the prefix and origin breakdowns
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use risclient::RisResponse;
+    /// use risclient::stats::StreamStats;
+    /// let message: RisResponse = serde_json::from_str(r#"{"type": "ris_message", "data": {"timestamp": 1650000000.25,
+    ///     "peer": "192.0.2.1", "peer_asn": "64500", "id": "1", "host": "rrc00", "type": "UPDATE", "path": [64500, 64501],
+    ///     "announcements": [{"next_hop": "192.0.2.1", "prefixes": ["198.51.100.0/24"]}]}}"#).unwrap();
+    /// let mut stats = StreamStats::new();
+    /// stats.observe(message.data());
+    /// let report = stats.report(10);
+    /// assert_eq!(report.collectors, vec![("rrc00".to_string(), 1)]);
+    /// assert_eq!(report.origins, vec![(64501, 1)]);
+    /// ```
+    pub fn report(&self, limit: usize) -> StatsReport {
+	StatsReport {
+	    window: self.clock.now() - self.started,
+	    messages: self.messages,
+	    announcements: self.announcements,
+	    withdrawals: self.withdrawals,
+	    collectors: top(&self.collectors, usize::MAX),
+	    types: top(&self.types, usize::MAX),
+	    prefixes: top(&self.prefixes, limit),
+	    origins: top(&self.origins, limit),
+	}
+    }
+
+    /// Clears every count and starts a new window
+    pub fn reset(&mut self) {
+	*self = StreamStats::with_clock(self.clock.clone());
+    }
+
+    /// Returns the counts so far and starts a new window
+    pub fn take_report(&mut self, limit: usize) -> StatsReport {
+	let report = self.report(limit);
+	self.reset();
+	report
+    }
+}
+
+impl Default for StreamStats {
+    fn default() -> StreamStats {
+	StreamStats::new()
+    }
+}
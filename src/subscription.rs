@@ -0,0 +1,304 @@
+//! Subscription filters for RIS Live
+
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+
+use ipnet::IpNet;
+
+use crate::collectors;
+use crate::{RisError, RisRequest, RisRequestData, RisResponseData, RisSocketOptions};
+
+/// The message types RIS Live accepts for `Subscription::data_type`
+pub const DATA_TYPES: [&str; 5] = ["UPDATE", "OPEN", "NOTIFICATION", "KEEPALIVE", "RIS_PEER_STATE"];
+
+/// The values RIS Live accepts for `Subscription::require`
+pub const REQUIRES: [&str; 2] = ["announcements", "withdrawals"];
+
+///
+/// Describes which messages RIS Live should send.
+/// Every filter is optional, and an empty subscription receives the full stream.
+/// See <https://ris-live.ripe.net/manual/> for the server side semantics of each filter.
+///
+#[derive(Debug, Clone, Default)]
+pub struct Subscription {
+    pub(crate) hosts: Vec<String>,
+    pub(crate) data_type: Option<String>,
+    pub(crate) require: Option<String>,
+    pub(crate) path: Option<Vec<u32>>,
+    pub(crate) peer: Option<String>,
+    pub(crate) prefix: Option<String>,
+    pub(crate) more_specific: Option<bool>,
+    pub(crate) less_specific: Option<bool>,
+    pub(crate) include_raw: bool,
+    pub(crate) labels: BTreeMap<String, String>,
+}
+
+impl Subscription {
+
+    /// Returns a Subscription with no filters
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use risclient::Subscription;
+    /// let subscription = Subscription::new().host("rrc16").data_type("UPDATE").require("withdrawals");
+    /// ```
+    pub fn new() -> Subscription {
+	Subscription::default()
+    }
+
+    /// Returns a Subscription to every RIS collector. RIS Live fans an unfiltered subscription out
+    /// to all of them server side, so collectors RIPE adds start appearing on the stream, and retired
+    /// ones stop, without resubscribing. `collectors::CollectorRegistry` says which to expect, and
+    /// its `changes` reports collectors as RIPE adds and retires them.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use risclient::Subscription;
+    /// let subscription = Subscription::all_collectors().data_type("UPDATE");
+    /// ```
+    pub fn all_collectors() -> Subscription {
+	Subscription::new()
+    }
+
+    /// Only return messages from this RIS collector, such as "rrc00"
+    pub fn host(mut self, host: &str) -> Subscription {
+	self.hosts = vec![host.to_string()];
+	self
+    }
+
+    /// Only return messages from these RIS collectors, merged into one stream.
+    /// RIS Live takes one collector per subscription, so this sends a subscription for each.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use risclient::Subscription;
+    /// let subscription = Subscription::new().hosts(&["rrc00", "rrc16", "rrc21"]).data_type("UPDATE");
+    /// ```
+    pub fn hosts(mut self, hosts: &[&str]) -> Subscription {
+	self.hosts = hosts.iter().map(|host| host.to_string()).collect();
+	self
+    }
+
+    /// Only return messages of this type: "UPDATE", "OPEN", "NOTIFICATION", "KEEPALIVE" or "RIS_PEER_STATE"
+    pub fn data_type(mut self, data_type: &str) -> Subscription {
+	self.data_type = Some(data_type.to_string());
+	self
+    }
+
+    /// Only return UPDATEs containing "announcements" or "withdrawals"
+    pub fn require(mut self, require: &str) -> Subscription {
+	self.require = Some(require.to_string());
+	self
+    }
+
+    /// Only return UPDATEs whose AS path contains this sequence of ASNs
+    pub fn path(mut self, path: Vec<u32>) -> Subscription {
+	self.path = Some(path);
+	self
+    }
+
+    /// Only return messages received from the peer with this address
+    pub fn peer(mut self, peer: &str) -> Subscription {
+	self.peer = Some(peer.to_string());
+	self
+    }
+
+    /// Only return UPDATEs announcing or withdrawing this prefix, or more specifics of it by default
+    pub fn prefix(mut self, prefix: &str) -> Subscription {
+	self.prefix = Some(prefix.to_string());
+	self
+    }
+
+    /// Whether a prefix filter also matches more specific prefixes. RIS Live defaults this to true.
+    pub fn more_specific(mut self, more_specific: bool) -> Subscription {
+	self.more_specific = Some(more_specific);
+	self
+    }
+
+    /// Whether a prefix filter also matches less specific prefixes. RIS Live defaults this to false.
+    pub fn less_specific(mut self, less_specific: bool) -> Subscription {
+	self.less_specific = Some(less_specific);
+	self
+    }
+
+    /// Ask RIS Live to include the raw BGP message, hex encoded, with each message
+    pub fn include_raw(mut self, include_raw: bool) -> Subscription {
+	self.include_raw = include_raw;
+	self
+    }
+
+    /// Attaches a label to the subscription, which every message it delivers carries in
+    /// `RisResponseData::labels`, and so do the alerts raised from them. Labels never leave the client.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use risclient::Subscription;
+    /// let subscription = Subscription::new().prefix("193.0.0.0/21").label("customer", "acme").label("purpose", "hijack-watch");
+    /// assert_eq!(subscription.labels()["customer"], "acme");
+    /// ```
+    pub fn label(mut self, key: &str, value: &str) -> Subscription {
+	self.labels.insert(key.to_string(), value.to_string());
+	self
+    }
+
+    /// Returns the labels attached with `label`
+    pub fn labels(&self) -> &BTreeMap<String, String> {
+	&self.labels
+    }
+
+    /// Checks the filters locally, returning `RisError::InvalidFilter` for the first one RIS Live
+    /// would reject or silently ignore. `RisClient::subscribe` does this before sending anything.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use risclient::{RisError, Subscription};
+    /// assert!(Subscription::new().host("rrc00").prefix("193.0.0.0/21").validate().is_ok());
+    /// let result = Subscription::new().require("withdrawls").validate();
+    /// assert!(matches!(result, Err(RisError::InvalidFilter { filter: "require", .. })));
+    /// ```
+    pub fn validate(&self) -> Result<(), RisError> {
+	let invalid = |filter, reason: String| Err(RisError::InvalidFilter { filter, reason });
+	for host in &self.hosts {
+	    let number = collectors::short_name(host).strip_prefix("rrc").unwrap_or_default();
+	    if number.is_empty() || !number.bytes().all(|byte| byte.is_ascii_digit()) {
+		return invalid("host", format!("{:?} is not a RIS collector, which are named like \"rrc00\"", host));
+	    }
+	}
+	if let Some(data_type) = &self.data_type {
+	    if !DATA_TYPES.contains(&data_type.as_str()) {
+		return invalid("data_type", format!("{:?} is not one of {}", data_type, DATA_TYPES.join(", ")));
+	    }
+	}
+	if let Some(require) = &self.require {
+	    if !REQUIRES.contains(&require.as_str()) {
+		return invalid("require", format!("{:?} is not one of {}", require, REQUIRES.join(", ")));
+	    }
+	}
+	if let Some(peer) = &self.peer {
+	    if peer.parse::<IpAddr>().is_err() {
+		return invalid("peer", format!("{:?} is not an IP address", peer));
+	    }
+	}
+	if let Some(prefix) = &self.prefix {
+	    if prefix.parse::<IpNet>().is_err() && prefix.parse::<IpAddr>().is_err() {
+		return invalid("prefix", format!("{:?} is not a prefix, such as \"193.0.0.0/21\"", prefix));
+	    }
+	}
+	if self.prefix.is_none() && (self.more_specific.is_some() || self.less_specific.is_some()) {
+	    return invalid("prefix", "more_specific and less_specific only apply with a prefix filter".to_string());
+	}
+	Ok(())
+    }
+
+    /// Returns true if RIS Live would send `data` for this subscription, which is how connections shared
+    /// by several subscriptions tell whose a message is. A path matches wherever the ASNs appear in order
+    /// in the AS path, and prefix filters default as RIS Live's do: more specific prefixes match unless
+    /// `more_specific(false)`, and less specific ones only with `less_specific(true)`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use risclient::{RisResponse, Subscription};
+    /// let message: RisResponse = serde_json::from_str(r#"{"type": "ris_message", "data": {"timestamp": 1650000000.25,
+    ///     "peer": "192.0.2.1", "peer_asn": "64500", "id": "1", "host": "rrc00.ripe.net", "type": "UPDATE", "path": [64500, 64501],
+    ///     "announcements": [{"next_hop": "192.0.2.1", "prefixes": ["193.0.4.0/23"]}]}}"#).unwrap();
+    /// assert!(Subscription::new().host("rrc00").prefix("193.0.0.0/21").matches(message.data()));
+    /// assert!(!Subscription::new().prefix("193.0.0.0/21").more_specific(false).matches(message.data()));
+    /// assert!(!Subscription::new().require("withdrawals").matches(message.data()));
+    /// ```
+    pub fn matches(&self, data: &RisResponseData) -> bool {
+	let host = collectors::short_name(data.host());
+	if !self.hosts.is_empty() && !self.hosts.iter().any(|wanted| collectors::short_name(wanted) == host) {
+	    return false;
+	}
+	if self.data_type.as_deref().is_some_and(|data_type| data_type != data.data_type()) {
+	    return false;
+	}
+	match self.require.as_deref() {
+	    Some("announcements") if data.announcements().is_empty() => return false,
+	    Some("withdrawals") if data.withdrawals().is_empty() => return false,
+	    _ => {},
+	}
+	if let Some(peer) = &self.peer {
+	    let same = match (peer.parse::<IpAddr>(), data.peer().parse::<IpAddr>()) {
+		(Ok(peer), Ok(other)) => peer == other,
+		_ => peer == data.peer(),
+	    };
+	    if !same {
+		return false;
+	    }
+	}
+	if let Some(path) = self.path.as_ref().filter(|path| !path.is_empty()) {
+	    if !data.asns().windows(path.len()).any(|asns| asns == path.as_slice()) {
+		return false;
+	    }
+	}
+	if let Some(prefix) = &self.prefix {
+	    let Some(wanted) = prefix.parse::<IpNet>().ok().or_else(|| prefix.parse::<IpAddr>().ok().map(IpNet::from)) else {
+		return false;
+	    };
+	    let (more, less) = (self.more_specific.unwrap_or(true), self.less_specific.unwrap_or(false));
+	    let prefixes = data.announcements().iter().flat_map(|announcement| announcement.prefixes()).chain(data.withdrawals());
+	    return prefixes.filter_map(|prefix| prefix.parse::<IpNet>().ok()).any(|prefix| {
+		prefix == wanted || (more && wanted.contains(&prefix)) || (less && prefix.contains(&wanted))
+	    });
+	}
+	true
+    }
+
+    /// Returns the `ris_subscribe` frames for the subscription, one per collector, for sending over a
+    /// connection from `RisClient::connect_raw`. RIS Live is not asked to acknowledge them.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use risclient::Subscription;
+    /// let frames = Subscription::new().hosts(&["rrc00", "rrc21"]).data_type("UPDATE").subscribe_frames();
+    /// assert_eq!(frames.len(), 2);
+    /// let frame: serde_json::Value = serde_json::from_str(&frames[1]).unwrap();
+    /// assert_eq!((frame["type"].as_str(), frame["data"]["host"].as_str()), (Some("ris_subscribe"), Some("rrc21")));
+    /// ```
+    pub fn subscribe_frames(&self) -> Vec<String> {
+	self.frames("ris_subscribe")
+    }
+
+    /// Returns the `ris_unsubscribe` frames undoing `subscribe_frames`
+    pub fn unsubscribe_frames(&self) -> Vec<String> {
+	self.frames("ris_unsubscribe")
+    }
+
+    fn frames(&self, message_type: &str) -> Vec<String> {
+	self.requests(false).iter().map(|data| RisRequest::new(message_type, data).to_frame()).collect()
+    }
+
+    /// Returns the data for each `ris_subscribe` message needed, one per collector
+    pub(crate) fn requests(&self, acknowledge: bool) -> Vec<RisRequestData> {
+	if self.hosts.is_empty() {
+	    return vec![self.request_data(None, acknowledge)];
+	}
+	self.hosts.iter().map(|host| self.request_data(Some(host.clone()), acknowledge)).collect()
+    }
+
+    fn request_data(&self, host: Option<String>, acknowledge: bool) -> RisRequestData {
+	RisRequestData {
+	    host,
+	    data_type: self.data_type.clone(),
+	    require: self.require.clone(),
+	    path: self.path.clone(),
+	    peer: self.peer.clone(),
+	    prefix: self.prefix.clone(),
+	    more_specific: self.more_specific,
+	    less_specific: self.less_specific,
+	    socket_options: if self.include_raw || acknowledge {
+		Some(RisSocketOptions { include_raw: self.include_raw, acknowledge })
+	    } else {
+		None
+	    },
+	}
+    }
+}
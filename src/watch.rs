@@ -0,0 +1,246 @@
+//! Prefix monitoring
+//!
+//! `PrefixWatch` follows one prefix of your own across the feed and reports
+//! the things an operator wants to hear about straight away: an announcement
+//! of it from an unexpected origin, an unexpected more specific inside it, or
+//! a drop in how many RIS peers can still see it. Changes of the next hop a
+//! peer uses for it are reported too, as they often mean a transit change.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use ipnet::IpNet;
+
+use crate::rib::{PeerKey, Rib};
+use crate::{AsPathEntry, RisResponseData};
+
+/// Something noteworthy about a watched prefix, carrying the labels of the subscription it was seen on
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum WatchEvent {
+    /// The prefix was announced with an origin that is not expected
+    OriginChange {
+	collector: String,
+	peer: String,
+	prefix: String,
+	origin: Option<u32>,
+	path: Vec<AsPathEntry>,
+	timestamp: f64,
+	#[serde(skip_serializing_if = "BTreeMap::is_empty")]
+	labels: BTreeMap<String, String>,
+    },
+    /// A more specific of the prefix was announced, longer than allowed or from an unexpected origin
+    MoreSpecific {
+	collector: String,
+	peer: String,
+	prefix: String,
+	origin: Option<u32>,
+	path: Vec<AsPathEntry>,
+	timestamp: f64,
+	#[serde(skip_serializing_if = "BTreeMap::is_empty")]
+	labels: BTreeMap<String, String>,
+    },
+    /// A peer that already had a route for the prefix announced it with a different next hop
+    NextHopChange {
+	collector: String,
+	peer: String,
+	prefix: String,
+	previous: String,
+	next_hop: String,
+	timestamp: f64,
+	#[serde(skip_serializing_if = "BTreeMap::is_empty")]
+	labels: BTreeMap<String, String>,
+    },
+    /// The number of peers seeing the prefix fell below the threshold
+    VisibilityLoss {
+	visible: usize,
+	peak: usize,
+	timestamp: f64,
+	#[serde(skip_serializing_if = "BTreeMap::is_empty")]
+	labels: BTreeMap<String, String>,
+    },
+    /// Visibility recovered to the threshold after a loss
+    VisibilityRestored {
+	visible: usize,
+	peak: usize,
+	timestamp: f64,
+	#[serde(skip_serializing_if = "BTreeMap::is_empty")]
+	labels: BTreeMap<String, String>,
+    },
+}
+
+impl WatchEvent {
+    /// Returns true for events that indicate something wrong, rather than a recovery or a change worth a look
+    pub fn is_violation(&self) -> bool {
+	!matches!(self, WatchEvent::VisibilityRestored { .. } | WatchEvent::NextHopChange { .. })
+    }
+}
+
+///
+/// Watches a prefix for origin changes, more specifics and loss of visibility.
+/// Visibility is measured as the number of peer sessions currently announcing exactly the prefix,
+/// against the most seen so far. RIS Live only reports changes, so seed the watch from a `Rib`
+/// if the peers already announcing the prefix should count too.
+///
+#[derive(Debug, Clone)]
+pub struct PrefixWatch {
+    prefix: IpNet,
+    origins: HashSet<u32>,
+    max_length: u8,
+    min_visibility: f64,
+    // the sessions announcing the prefix, and the next hop each uses
+    visible: HashMap<PeerKey, String>,
+    peak: usize,
+    lost: bool,
+}
+
+impl PrefixWatch {
+
+    /// Returns a PrefixWatch for `prefix`, expecting it to be originated by one of `origins`.
+    /// With no origins any origin is accepted, and only visibility is watched.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use risclient::RisResponse;
+    /// use risclient::watch::{PrefixWatch, WatchEvent};
+    /// let message: RisResponse = serde_json::from_str(r#"{"type": "ris_message", "data": {"timestamp": 1650000000.25,
+    ///     "peer": "192.0.2.1", "peer_asn": "64500", "id": "1", "host": "rrc00", "type": "UPDATE", "path": [64500, 64666],
+    ///     "announcements": [{"next_hop": "192.0.2.1", "prefixes": ["203.0.113.0/24"]}]}}"#).unwrap();
+    /// let mut watch = PrefixWatch::new("203.0.113.0/24".parse().unwrap(), &[64501]);
+    /// let events = watch.observe(message.data());
+    /// assert!(matches!(events[0], WatchEvent::OriginChange { origin: Some(64666), .. }));
+    /// ```
+    pub fn new(prefix: IpNet, origins: &[u32]) -> PrefixWatch {
+	PrefixWatch {
+	    max_length: prefix.prefix_len(),
+	    prefix: prefix.trunc(),
+	    origins: origins.iter().copied().collect(),
+	    min_visibility: 0.5,
+	    visible: HashMap::new(),
+	    peak: 0,
+	    lost: false,
+	}
+    }
+
+    /// Accept more specifics up to this length from an expected origin, as a ROA's maxLength would
+    pub fn with_max_length(mut self, max_length: u8) -> PrefixWatch {
+	self.max_length = max_length.max(self.prefix.prefix_len());
+	self
+    }
+
+    /// Report a visibility loss when fewer than this fraction of the peak number of peers see the prefix. Defaults to 0.5.
+    pub fn with_min_visibility(mut self, min_visibility: f64) -> PrefixWatch {
+	self.min_visibility = min_visibility;
+	self
+    }
+
+    /// Counts the sessions that already have a route for the prefix in `rib` as seeing it
+    pub fn with_rib(mut self, rib: &Rib) -> PrefixWatch {
+	self.visible.extend(rib.routes_for(&self.prefix).into_iter().map(|(key, route)| (key.clone(), route.next_hop.clone())));
+	self.peak = self.visible.len();
+	self
+    }
+
+    /// Returns the watched prefix
+    pub fn prefix(&self) -> &IpNet {
+	&self.prefix
+    }
+
+    /// Returns how many peer sessions currently see the prefix
+    pub fn visibility(&self) -> usize {
+	self.visible.len()
+    }
+
+    fn expected(&self, origin: Option<u32>) -> bool {
+	self.origins.is_empty() || origin.is_some_and(|origin| self.origins.contains(&origin))
+    }
+
+    fn check_visibility(&mut self, data: &RisResponseData, events: &mut Vec<WatchEvent>) {
+	let timestamp = data.timestamp();
+	let labels = data.labels().clone();
+	let visible = self.visible.len();
+	self.peak = self.peak.max(visible);
+	let threshold = self.peak as f64 * self.min_visibility;
+	if !self.lost && (visible as f64) < threshold {
+	    self.lost = true;
+	    events.push(WatchEvent::VisibilityLoss { visible, peak: self.peak, timestamp, labels });
+	} else if self.lost && visible as f64 >= threshold {
+	    self.lost = false;
+	    events.push(WatchEvent::VisibilityRestored { visible, peak: self.peak, timestamp, labels });
+	}
+    }
+
+    fn check_announcement(&mut self, data: &RisResponseData, key: &PeerKey, prefix: &str, next_hop: &str, origin: Option<u32>, events: &mut Vec<WatchEvent>) {
+	let parsed = match prefix.parse::<IpNet>() {
+	    Ok(parsed) => parsed.trunc(),
+	    Err(_) => return,
+	};
+	if parsed == self.prefix {
+	    let previous = self.visible.insert(key.clone(), next_hop.to_string());
+	    if let Some(previous) = previous.filter(|previous| previous != next_hop) {
+		events.push(WatchEvent::NextHopChange {
+		    collector: data.host().to_string(),
+		    peer: data.peer().to_string(),
+		    prefix: prefix.to_string(),
+		    previous,
+		    next_hop: next_hop.to_string(),
+		    timestamp: data.timestamp(),
+		    labels: data.labels().clone(),
+		});
+	    }
+	    if !self.expected(origin) {
+		events.push(WatchEvent::OriginChange {
+		    collector: data.host().to_string(),
+		    peer: data.peer().to_string(),
+		    prefix: prefix.to_string(),
+		    origin,
+		    path: data.path().to_vec(),
+		    timestamp: data.timestamp(),
+		    labels: data.labels().clone(),
+		});
+	    }
+	} else if self.prefix.contains(&parsed) && (parsed.prefix_len() > self.max_length || !self.expected(origin)) {
+	    events.push(WatchEvent::MoreSpecific {
+		collector: data.host().to_string(),
+		peer: data.peer().to_string(),
+		prefix: prefix.to_string(),
+		origin,
+		path: data.path().to_vec(),
+		timestamp: data.timestamp(),
+		labels: data.labels().clone(),
+	    });
+	}
+    }
+
+    /// Updates the watch with a message, returning anything noteworthy it caused
+    pub fn observe(&mut self, data: &RisResponseData) -> Vec<WatchEvent> {
+	let mut events = Vec::new();
+	let key = PeerKey::of(data);
+	match data.data_type() {
+	    "UPDATE" => {},
+	    "RIS_PEER_STATE" if data.state() == Some("down") => {
+		if self.visible.remove(&key).is_some() {
+		    self.check_visibility(data, &mut events);
+		}
+		return events;
+	    },
+	    _ => return events,
+	}
+	let origin = match data.path().last() {
+	    Some(AsPathEntry::Asn(asn)) => Some(*asn),
+	    _ => None,
+	};
+	for prefix in data.withdrawals() {
+	    if prefix.parse::<IpNet>().map(|prefix| prefix.trunc()) == Ok(self.prefix) {
+		self.visible.remove(&key);
+	    }
+	}
+	for announcement in data.announcements() {
+	    for prefix in announcement.prefixes() {
+		self.check_announcement(data, &key, prefix, announcement.next_hop(), origin, &mut events);
+	    }
+	}
+	self.check_visibility(data, &mut events);
+	events
+    }
+}
@@ -0,0 +1,56 @@
+//! Withdrawal-only streams
+//!
+//! Outage detection only needs to know which prefixes were withdrawn, by whom
+//! and when. `RisClient::stream_withdrawals` asks RIS Live for UPDATEs carrying
+//! withdrawals, and deserialises each straight into a `Withdrawal`, skipping
+//! over the path, communities and announcements rather than building them.
+
+/// The withdrawals of one UPDATE
+///
+/// # Examples
+///
+/// ```
+/// use risclient::withdrawal::Withdrawal;
+/// let withdrawal: Withdrawal = serde_json::from_str(r#"{"type": "ris_message", "data": {"timestamp": 1650000000.25,
+///     "peer": "192.0.2.1", "peer_asn": "64500", "id": "1", "host": "rrc00", "type": "UPDATE", "path": [64500, 64501],
+///     "announcements": [{"next_hop": "192.0.2.1", "prefixes": ["203.0.113.0/24"]}], "withdrawals": ["198.51.100.0/24"]}}"#).unwrap();
+/// assert_eq!(withdrawal.withdrawals, vec!["198.51.100.0/24"]);
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(from = "Frame")]
+pub struct Withdrawal {
+    /// When the collector received the UPDATE, in seconds since the epoch
+    pub timestamp: f64,
+    pub peer: String,
+    pub peer_asn: String,
+    /// The collector, such as "rrc00"
+    pub host: String,
+    pub withdrawals: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct Frame {
+    data: Data,
+}
+
+// only the fields a Withdrawal keeps, so that everything else is skipped rather than parsed
+#[derive(Deserialize)]
+struct Data {
+    #[serde(default)]
+    timestamp: f64,
+    #[serde(default)]
+    peer: String,
+    #[serde(default)]
+    peer_asn: String,
+    #[serde(default)]
+    host: String,
+    #[serde(default)]
+    withdrawals: Vec<String>,
+}
+
+impl From<Frame> for Withdrawal {
+    fn from(frame: Frame) -> Withdrawal {
+	let Data { timestamp, peer, peer_asn, host, withdrawals } = frame.data;
+	Withdrawal { timestamp, peer, peer_asn, host, withdrawals }
+    }
+}